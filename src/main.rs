@@ -1,6 +1,22 @@
 mod raytris;
-use self::raytris::Raytris;
+use self::raytris::{Arguments, Raytris};
+use std::{env, process};
 
 fn main() {
-  Raytris::new().run();
+  let arguments = match Arguments::parse(env::args().skip(1)) {
+    Ok(arguments) => arguments,
+    Err(error) => {
+      eprintln!(
+        "{}\nUsage: raytris [--seed <number>] [--pieces <sequence, like IOTSZJL>] [--replay <path>] [--benchmark <games>]",
+        error
+      );
+      process::exit(2);
+    }
+  };
+
+  if let Some(games) = arguments.benchmark {
+    Raytris::benchmark(games);
+    return;
+  }
+  Raytris::new().run(arguments);
 }
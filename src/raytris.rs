@@ -1,9 +1,66 @@
+mod controls;
 mod game;
 mod menu;
+mod records;
+mod settings;
 
 use self::game::*;
 use self::menu::*;
+use self::settings::*;
+use rand::Rng;
 use raylib::prelude::*;
+use std::path::PathBuf;
+
+// Options given on the command line, which take the place of the menu's defaults
+pub struct Arguments {
+  pub seed: Option<u64>,
+  pub randomizer: Option<RandomizerKind>,
+  pub replay: Option<PathBuf>,
+  pub benchmark: Option<u64>,
+}
+
+impl Arguments {
+  pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+    let mut arguments = Self {
+      seed: None,
+      randomizer: None,
+      replay: None,
+      benchmark: None,
+    };
+
+    while let Some(arg) = args.next() {
+      match arg.as_str() {
+        "--seed" => match args.next().map(|seed| seed.parse()) {
+          Some(Ok(seed)) => arguments.seed = Some(seed),
+          _ => return Err(format!("--seed needs a number from 0 to {}", u64::MAX)),
+        },
+        "--pieces" => match args
+          .next()
+          .and_then(|pieces| RandomizerKind::scripted(&pieces))
+        {
+          Some(randomizer) => arguments.randomizer = Some(randomizer),
+          None => {
+            return Err(format!(
+              "--pieces needs a sequence of at most {} of I, O, T, S, Z, J and L",
+              RandomizerKind::MAX_SCRIPTED_LEN
+            ))
+          }
+        },
+        "--replay" => match args.next() {
+          Some(path) => arguments.replay = Some(PathBuf::from(path)),
+          None => return Err("--replay needs the path of a .rtr file".to_string()),
+        },
+        "--benchmark" => match args.next().map(|games| games.parse()) {
+          Some(Ok(games)) => arguments.benchmark = Some(games),
+          _ => return Err("--benchmark needs a number of games".to_string()),
+        },
+        _ => return Err(format!("Unknown argument {}", arg)),
+      }
+    }
+
+    Ok(arguments)
+  }
+}
 
 pub struct Raytris {
   rl: RaylibHandle,
@@ -11,6 +68,8 @@ pub struct Raytris {
 }
 
 impl Raytris {
+  const BENCHMARK_PIECES: u32 = 500;
+
   pub fn new() -> Self {
     let (mut rl, thread) = raylib::init()
       .size(Menu::INITIAL_RESOLUTION.0, Menu::INITIAL_RESOLUTION.1)
@@ -21,12 +80,90 @@ impl Raytris {
     Raytris { rl, thread }
   }
 
-  pub fn run(&mut self) {
+  // Has the bot play a game on each seed from 0, without opening a window
+  pub fn benchmark(games: u64) {
+    let benchmark = Bot::new(Difficulty::Hard).benchmark(0..games, Self::BENCHMARK_PIECES);
+    println!("{}", benchmark);
+  }
+
+  // Without a seed from the command line or the menu, every game gets a random one
+  pub fn run(&mut self, mut arguments: Arguments) {
     let mut rng = rand::thread_rng();
-    let mut menu = Menu::new();
-    while menu.run(&mut self.rl, &self.thread) == ExitCode::Game {
-      let mut game = Game::new(&mut self.rl, &mut rng);
-      game.run(&mut self.rl, &self.thread, &mut rng);
+    let (settings, error) = match Settings::load() {
+      Ok(settings) => (settings, None),
+      Err(error) => (Settings::new(), Some(error)),
+    };
+    let mut replay = arguments.replay.take().map(|path| Replay::load(&path));
+    let mut menu = Menu::new(&mut self.rl, settings, error, arguments);
+
+    loop {
+      match replay.take() {
+        Some(Ok(replay)) => {
+          let mut game = Game::watch(&self.rl, replay, menu.settings());
+          game.run(&mut self.rl, &self.thread);
+        }
+        Some(Err(error)) => menu.set_error(format!("Could not play the replay: {}", error)),
+        None => {}
+      }
+
+      match menu.run(&mut self.rl, &self.thread) {
+        ExitCode::Game => {
+          let rules = menu_rules(&menu, menu.seed().unwrap_or_else(|| rng.gen()));
+          if rules.mode == GameMode::Versus {
+            Versus::new(&self.rl, rules, menu.settings(), menu.opponent())
+              .run(&mut self.rl, &self.thread);
+            continue;
+          }
+
+          let mut game = Game::new(&self.rl, rules, menu.settings());
+          if menu.broadcast() {
+            if let Err(error) = game.broadcast() {
+              menu.set_error(format!("Could not broadcast: {}", error));
+            }
+          }
+          game.run(&mut self.rl, &self.thread);
+
+          if let Some(Err(error)) = game.finished_replay().map(|replay| replay.save()) {
+            menu.set_error(format!("Could not save the replay: {}", error));
+          }
+        }
+        ExitCode::Host => {
+          let rules = menu_rules(&menu, menu.seed().unwrap_or_else(|| rng.gen()));
+          match Netplay::host(&mut self.rl, &self.thread, rules, menu.settings()) {
+            Ok(Some(mut netplay)) => netplay.run(&mut self.rl, &self.thread),
+            Ok(None) => {}
+            Err(error) => menu.set_error(format!("Could not host: {}", error)),
+          }
+        }
+        ExitCode::Join => {
+          match Netplay::join(&mut self.rl, &self.thread, menu.address(), menu.settings()) {
+            Ok(mut netplay) => netplay.run(&mut self.rl, &self.thread),
+            Err(error) => menu.set_error(format!("Could not join: {}", error)),
+          }
+        }
+        ExitCode::Watch => match Game::spectate(&self.rl, menu.address(), menu.settings()) {
+          Ok(mut game) => game.run(&mut self.rl, &self.thread),
+          Err(error) => menu.set_error(format!("Could not watch: {}", error)),
+        },
+        ExitCode::Demo => {
+          Game::demo(&self.rl, Rules::new(rng.gen()), menu.settings())
+            .run(&mut self.rl, &self.thread);
+        }
+        ExitCode::Replay => replay = Some(Replay::latest().and_then(|path| Replay::load(&path))),
+        ExitCode::Exit => break,
+      }
     }
   }
 }
+
+// The rules picked in the menu
+fn menu_rules(menu: &Menu, seed: u64) -> Rules {
+  Rules {
+    randomizer: menu.randomizer(),
+    scoring: menu.scoring_system(),
+    gravity: menu.gravity_curve(),
+    handling: menu.settings().handling,
+    mode: menu.mode(),
+    ..Rules::new(seed)
+  }
+}
@@ -0,0 +1,672 @@
+use super::game::Action;
+use raylib::prelude::*;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Control {
+  ShiftLeft,
+  ShiftRight,
+  RotateClockwise,
+  RotateCounterClockwise,
+  RotateOneEighty,
+  SoftDrop,
+  HardDrop,
+  Hold,
+  Restart,
+  Pause,
+  Undo,
+  Quit,
+  Confirm,
+  Resize,
+  Stats,
+  SpeedUp,
+  SlowDown,
+  SeekForward,
+  SeekBack,
+  StepFrame,
+  ChangeMode,
+  FirstModeOption,
+  SecondModeOption,
+  Join,
+  ChangeScoring,
+  ChangeGravity,
+  ChangePieces,
+  ChangeSeed,
+  OpenHandling,
+  OpenControls,
+  OpenReplay,
+  ToggleBroadcast,
+  Watch,
+}
+
+// The screens controls are read on
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Context {
+  Game,
+  Replay,
+  Results,
+  Menu,
+}
+
+impl Control {
+  pub const ALL: [Control; 33] = [
+    Control::ShiftLeft,
+    Control::ShiftRight,
+    Control::RotateClockwise,
+    Control::RotateCounterClockwise,
+    Control::RotateOneEighty,
+    Control::SoftDrop,
+    Control::HardDrop,
+    Control::Hold,
+    Control::Restart,
+    Control::Pause,
+    Control::Undo,
+    Control::Quit,
+    Control::Confirm,
+    Control::Resize,
+    Control::Stats,
+    Control::SpeedUp,
+    Control::SlowDown,
+    Control::SeekForward,
+    Control::SeekBack,
+    Control::StepFrame,
+    Control::ChangeMode,
+    Control::FirstModeOption,
+    Control::SecondModeOption,
+    Control::Join,
+    Control::ChangeScoring,
+    Control::ChangeGravity,
+    Control::ChangePieces,
+    Control::ChangeSeed,
+    Control::OpenHandling,
+    Control::OpenControls,
+    Control::OpenReplay,
+    Control::ToggleBroadcast,
+    Control::Watch,
+  ];
+
+  pub fn action(self) -> Option<Action> {
+    match self {
+      Self::ShiftLeft => Some(Action::ShiftLeft),
+      Self::ShiftRight => Some(Action::ShiftRight),
+      Self::RotateClockwise => Some(Action::RotateClockwise),
+      Self::RotateCounterClockwise => Some(Action::RotateCounterClockwise),
+      Self::RotateOneEighty => Some(Action::RotateOneEighty),
+      Self::SoftDrop => Some(Action::SoftDrop),
+      Self::HardDrop => Some(Action::HardDrop),
+      Self::Hold => Some(Action::Hold),
+      Self::Restart => Some(Action::Restart),
+      _ => None,
+    }
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::ShiftLeft => "shift_left",
+      Self::ShiftRight => "shift_right",
+      Self::RotateClockwise => "rotate_clockwise",
+      Self::RotateCounterClockwise => "rotate_counter_clockwise",
+      Self::RotateOneEighty => "rotate_180",
+      Self::SoftDrop => "soft_drop",
+      Self::HardDrop => "hard_drop",
+      Self::Hold => "hold",
+      Self::Restart => "restart",
+      Self::Pause => "pause",
+      Self::Undo => "undo",
+      Self::Quit => "quit",
+      Self::Confirm => "confirm",
+      Self::Resize => "resize",
+      Self::Stats => "stats",
+      Self::SpeedUp => "speed_up",
+      Self::SlowDown => "slow_down",
+      Self::SeekForward => "seek_forward",
+      Self::SeekBack => "seek_back",
+      Self::StepFrame => "step_frame",
+      Self::ChangeMode => "mode",
+      Self::FirstModeOption => "mode_option_1",
+      Self::SecondModeOption => "mode_option_2",
+      Self::Join => "join",
+      Self::ChangeScoring => "scoring",
+      Self::ChangeGravity => "gravity",
+      Self::ChangePieces => "pieces",
+      Self::ChangeSeed => "seed",
+      Self::OpenHandling => "handling",
+      Self::OpenControls => "controls",
+      Self::OpenReplay => "replay",
+      Self::ToggleBroadcast => "broadcast",
+      Self::Watch => "watch",
+    }
+  }
+
+  pub fn label(self) -> &'static str {
+    match self {
+      Self::ShiftLeft => "Move left",
+      Self::ShiftRight => "Move right",
+      Self::RotateClockwise => "Rotate clockwise",
+      Self::RotateCounterClockwise => "Rotate counterclockwise",
+      Self::RotateOneEighty => "Rotate 180",
+      Self::SoftDrop => "Soft drop",
+      Self::HardDrop => "Hard drop",
+      Self::Hold => "Hold",
+      Self::Restart => "Restart",
+      Self::Pause => "Pause",
+      Self::Undo => "Undo",
+      Self::Quit => "Quit / back",
+      Self::Confirm => "Play / pick",
+      Self::Resize => "Resize window",
+      Self::Stats => "Toggle stats",
+      Self::SpeedUp => "Replay faster",
+      Self::SlowDown => "Replay slower",
+      Self::SeekForward => "Skip ahead",
+      Self::SeekBack => "Skip back",
+      Self::StepFrame => "Next frame",
+      Self::ChangeMode => "Change mode",
+      Self::FirstModeOption => "First mode option",
+      Self::SecondModeOption => "Second mode option / host",
+      Self::Join => "Join online game",
+      Self::ChangeScoring => "Change scoring",
+      Self::ChangeGravity => "Change gravity",
+      Self::ChangePieces => "Change pieces",
+      Self::ChangeSeed => "Set seed",
+      Self::OpenHandling => "Handling",
+      Self::OpenControls => "Controls",
+      Self::OpenReplay => "Watch replay",
+      Self::ToggleBroadcast => "Toggle broadcast",
+      Self::Watch => "Watch broadcast",
+    }
+  }
+
+  fn contexts(self) -> &'static [Context] {
+    match self {
+      Self::Quit => &[
+        Context::Game,
+        Context::Replay,
+        Context::Results,
+        Context::Menu,
+      ],
+      Self::Pause => &[Context::Game, Context::Replay],
+      Self::Stats => &[Context::Game, Context::Replay, Context::Results],
+      // The results screen picks its options with the moves, and retries on restart
+      Self::ShiftLeft | Self::ShiftRight | Self::Restart | Self::Undo => {
+        &[Context::Game, Context::Results]
+      }
+      Self::Confirm => &[Context::Menu, Context::Results],
+      Self::Resize
+      | Self::ChangeMode
+      | Self::FirstModeOption
+      | Self::SecondModeOption
+      | Self::Join
+      | Self::ChangeScoring
+      | Self::ChangeGravity
+      | Self::ChangePieces
+      | Self::ChangeSeed
+      | Self::OpenHandling
+      | Self::OpenControls
+      | Self::OpenReplay
+      | Self::ToggleBroadcast
+      | Self::Watch => &[Context::Menu],
+      Self::SpeedUp | Self::SlowDown | Self::SeekForward | Self::SeekBack | Self::StepFrame => {
+        &[Context::Replay]
+      }
+      _ => &[Context::Game],
+    }
+  }
+
+  // Without these there is no way out of a game or the menu, or back to the
+  // controls to fix that
+  fn is_required(self) -> bool {
+    matches!(
+      self,
+      Self::Pause | Self::Quit | Self::Confirm | Self::OpenControls
+    )
+  }
+
+  // Versus reads both keymaps on the same frame, but only moves pieces with player
+  // two's. Restarting is left out of a match.
+  fn is_read_in_versus(self, is_player_two: bool) -> bool {
+    let moves_piece = self.action().is_some() && self != Self::Restart;
+    moves_piece || !is_player_two && matches!(self, Self::Pause | Self::Quit)
+  }
+
+  // Two controls can only get in each other's way if they are read on the same screen
+  fn shares_context(self, other: Control) -> bool {
+    self
+      .contexts()
+      .iter()
+      .any(|context| other.contexts().contains(context))
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KeyBinding {
+  pub modifier: Option<KeyboardKey>,
+  pub key: KeyboardKey,
+}
+
+impl KeyBinding {
+  const MODIFIERS: [KeyboardKey; 6] = [
+    KeyboardKey::KEY_LEFT_CONTROL,
+    KeyboardKey::KEY_RIGHT_CONTROL,
+    KeyboardKey::KEY_LEFT_SHIFT,
+    KeyboardKey::KEY_RIGHT_SHIFT,
+    KeyboardKey::KEY_LEFT_ALT,
+    KeyboardKey::KEY_RIGHT_ALT,
+  ];
+
+  pub fn new(key: KeyboardKey) -> Self {
+    Self {
+      modifier: None,
+      key,
+    }
+  }
+
+  pub fn with_modifier(modifier: KeyboardKey, key: KeyboardKey) -> Self {
+    Self {
+      modifier: Some(modifier),
+      key,
+    }
+  }
+
+  // Builds a binding out of the key that was just pressed. Pressing a key while
+  // holding a modifier binds the combination, unless the key is a modifier itself.
+  pub fn from_key_press(rl: &RaylibHandle, key: KeyboardKey) -> Self {
+    if Self::is_modifier(key) {
+      return Self::new(key);
+    }
+
+    match Self::MODIFIERS
+      .into_iter()
+      .find(|&modifier| rl.is_key_down(modifier))
+    {
+      Some(modifier) => Self::with_modifier(modifier, key),
+      None => Self::new(key),
+    }
+  }
+
+  pub fn is_modifier(key: KeyboardKey) -> bool {
+    Self::MODIFIERS.contains(&key)
+  }
+
+  pub fn is_pressed(&self, rl: &RaylibHandle) -> bool {
+    rl.is_key_pressed(self.key) && self.modifier.is_none_or(|key| rl.is_key_down(key))
+  }
+
+  pub fn is_down(&self, rl: &RaylibHandle) -> bool {
+    rl.is_key_down(self.key) && self.modifier.is_none_or(|key| rl.is_key_down(key))
+  }
+
+  pub fn name(&self) -> String {
+    match self.modifier {
+      Some(modifier) => format!("{}+{}", key_name(modifier), key_name(self.key)),
+      None => key_name(self.key),
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name.split_once('+') {
+      Some((modifier, key)) => Some(Self::with_modifier(
+        key_from_name(modifier)?,
+        key_from_name(key)?,
+      )),
+      None => Some(Self::new(key_from_name(name)?)),
+    }
+  }
+}
+
+fn key_name(key: KeyboardKey) -> String {
+  let name = format!("{:?}", key);
+  name.strip_prefix("KEY_").unwrap_or(&name).to_string()
+}
+
+fn key_from_name(name: &str) -> Option<KeyboardKey> {
+  (0..=KeyboardKey::KEY_KB_MENU as i32)
+    .filter_map(key_from_i32)
+    .find(|&key| key_name(key).eq_ignore_ascii_case(name.trim()))
+}
+
+const GAMEPAD_BUTTONS: [GamepadButton; 17] = [
+  GamepadButton::GAMEPAD_BUTTON_LEFT_FACE_UP,
+  GamepadButton::GAMEPAD_BUTTON_LEFT_FACE_RIGHT,
+  GamepadButton::GAMEPAD_BUTTON_LEFT_FACE_DOWN,
+  GamepadButton::GAMEPAD_BUTTON_LEFT_FACE_LEFT,
+  GamepadButton::GAMEPAD_BUTTON_RIGHT_FACE_UP,
+  GamepadButton::GAMEPAD_BUTTON_RIGHT_FACE_RIGHT,
+  GamepadButton::GAMEPAD_BUTTON_RIGHT_FACE_DOWN,
+  GamepadButton::GAMEPAD_BUTTON_RIGHT_FACE_LEFT,
+  GamepadButton::GAMEPAD_BUTTON_LEFT_TRIGGER_1,
+  GamepadButton::GAMEPAD_BUTTON_LEFT_TRIGGER_2,
+  GamepadButton::GAMEPAD_BUTTON_RIGHT_TRIGGER_1,
+  GamepadButton::GAMEPAD_BUTTON_RIGHT_TRIGGER_2,
+  GamepadButton::GAMEPAD_BUTTON_MIDDLE_LEFT,
+  GamepadButton::GAMEPAD_BUTTON_MIDDLE,
+  GamepadButton::GAMEPAD_BUTTON_MIDDLE_RIGHT,
+  GamepadButton::GAMEPAD_BUTTON_LEFT_THUMB,
+  GamepadButton::GAMEPAD_BUTTON_RIGHT_THUMB,
+];
+
+pub fn button_name(button: GamepadButton) -> String {
+  let name = format!("{:?}", button);
+  name
+    .strip_prefix("GAMEPAD_BUTTON_")
+    .unwrap_or(&name)
+    .to_string()
+}
+
+pub fn button_from_name(name: &str) -> Option<GamepadButton> {
+  GAMEPAD_BUTTONS
+    .into_iter()
+    .find(|&button| button_name(button).eq_ignore_ascii_case(name.trim()))
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum KeymapPreset {
+  Guideline,
+  Wasd,
+  LeftHanded,
+  Numpad,
+}
+
+impl KeymapPreset {
+  pub fn next(&self) -> Self {
+    match self {
+      Self::Guideline => Self::Wasd,
+      Self::Wasd => Self::LeftHanded,
+      Self::LeftHanded => Self::Numpad,
+      Self::Numpad => Self::Guideline,
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Self::Guideline => "Guideline",
+      Self::Wasd => "WASD",
+      Self::LeftHanded => "Left-handed",
+      Self::Numpad => "Numpad",
+    }
+  }
+
+  pub fn keymap(&self) -> Keymap {
+    use KeyboardKey::*;
+    let bindings = Control::ALL.map(|control| {
+      let keys: &[KeyboardKey] = match control {
+        Control::ShiftLeft => self.game_keys(&[KEY_LEFT], &[KEY_A], &[KEY_S], &[KEY_KP_4]),
+        Control::ShiftRight => self.game_keys(&[KEY_RIGHT], &[KEY_D], &[KEY_F], &[KEY_KP_6]),
+        Control::RotateClockwise => {
+          self.game_keys(&[KEY_UP, KEY_X], &[KEY_K], &[KEY_PERIOD], &[KEY_KP_9])
+        }
+        Control::RotateCounterClockwise => {
+          self.game_keys(&[KEY_Z], &[KEY_J], &[KEY_COMMA], &[KEY_KP_7])
+        }
+        Control::RotateOneEighty => self.game_keys(&[KEY_A], &[KEY_L], &[KEY_SLASH], &[KEY_KP_8]),
+        Control::SoftDrop => self.game_keys(&[KEY_DOWN], &[KEY_S], &[KEY_D], &[KEY_KP_5]),
+        Control::HardDrop => self.game_keys(&[KEY_SPACE], &[KEY_W], &[KEY_SPACE], &[KEY_KP_0]),
+        Control::Hold => self.game_keys(
+          &[KEY_C, KEY_LEFT_SHIFT],
+          &[KEY_SPACE, KEY_LEFT_SHIFT],
+          &[KEY_M],
+          &[KEY_KP_ADD],
+        ),
+        Control::Restart => self.game_keys(&[KEY_R], &[KEY_R], &[KEY_R], &[KEY_KP_DECIMAL]),
+        Control::Undo => return vec![KeyBinding::with_modifier(KEY_LEFT_CONTROL, KEY_Z)],
+        Control::Pause | Control::Confirm => &[KEY_ENTER],
+        Control::Quit => &[KEY_ESCAPE],
+        Control::Resize => &[KEY_F],
+        Control::Stats => &[KEY_TAB],
+        Control::SpeedUp => &[KEY_UP],
+        Control::SlowDown => &[KEY_DOWN],
+        Control::SeekForward => &[KEY_RIGHT],
+        Control::SeekBack => &[KEY_LEFT],
+        Control::StepFrame => &[KEY_PERIOD],
+        Control::ChangeMode => &[KEY_M],
+        Control::FirstModeOption => &[KEY_L],
+        Control::SecondModeOption => &[KEY_O],
+        Control::Join => &[KEY_J],
+        Control::ChangeScoring => &[KEY_S],
+        Control::ChangeGravity => &[KEY_G],
+        Control::ChangePieces => &[KEY_B],
+        Control::ChangeSeed => &[KEY_N],
+        Control::OpenHandling => &[KEY_H],
+        Control::OpenControls => &[KEY_K],
+        Control::OpenReplay => &[KEY_R],
+        Control::ToggleBroadcast => &[KEY_V],
+        Control::Watch => &[KEY_W],
+      };
+      keys.iter().map(|&key| KeyBinding::new(key)).collect()
+    });
+
+    let buttons = Control::ALL
+      .into_iter()
+      .map(|control| Keymap::default_buttons(control).to_vec())
+      .collect();
+    Keymap {
+      bindings: bindings.into(),
+      buttons,
+    }
+  }
+
+  // The keys that play the game are what tells the presets apart, the rest are
+  // the same in all of them
+  fn game_keys(
+    &self,
+    guideline: &'static [KeyboardKey],
+    wasd: &'static [KeyboardKey],
+    left_handed: &'static [KeyboardKey],
+    numpad: &'static [KeyboardKey],
+  ) -> &'static [KeyboardKey] {
+    match self {
+      Self::Guideline => guideline,
+      Self::Wasd => wasd,
+      Self::LeftHanded => left_handed,
+      Self::Numpad => numpad,
+    }
+  }
+}
+
+// The keys of each control, and the gamepad buttons for when the player is on a
+// gamepad instead
+#[derive(Clone, PartialEq, Debug)]
+pub struct Keymap {
+  bindings: Vec<Vec<KeyBinding>>,
+  buttons: Vec<Vec<GamepadButton>>,
+}
+
+impl Keymap {
+  pub fn new() -> Self {
+    KeymapPreset::Guideline.keymap()
+  }
+
+  // The same for every preset, a gamepad has no layouts to pick from
+  fn default_buttons(control: Control) -> &'static [GamepadButton] {
+    use GamepadButton::*;
+    match control {
+      Control::ShiftLeft => &[GAMEPAD_BUTTON_LEFT_FACE_LEFT],
+      Control::ShiftRight => &[GAMEPAD_BUTTON_LEFT_FACE_RIGHT],
+      Control::RotateClockwise => &[GAMEPAD_BUTTON_RIGHT_FACE_RIGHT],
+      Control::RotateCounterClockwise => &[GAMEPAD_BUTTON_RIGHT_FACE_DOWN],
+      Control::RotateOneEighty => &[GAMEPAD_BUTTON_RIGHT_FACE_UP],
+      Control::SoftDrop => &[GAMEPAD_BUTTON_LEFT_FACE_DOWN],
+      Control::HardDrop => &[GAMEPAD_BUTTON_LEFT_FACE_UP],
+      Control::Hold => &[GAMEPAD_BUTTON_LEFT_TRIGGER_1],
+      Control::Pause => &[GAMEPAD_BUTTON_MIDDLE_RIGHT],
+      Control::Quit => &[GAMEPAD_BUTTON_MIDDLE_LEFT],
+      Control::Confirm => &[GAMEPAD_BUTTON_RIGHT_FACE_DOWN],
+      _ => &[],
+    }
+  }
+
+  pub fn bindings(&self, control: Control) -> &[KeyBinding] {
+    &self.bindings[control as usize]
+  }
+
+  pub fn buttons(&self, control: Control) -> &[GamepadButton] {
+    &self.buttons[control as usize]
+  }
+
+  pub fn set_buttons(&mut self, control: Control, buttons: Vec<GamepadButton>) {
+    self.buttons[control as usize] = buttons;
+  }
+
+  // Controls that can't be left unbound get their default keys back instead
+  pub fn set_bindings(&mut self, control: Control, bindings: Vec<KeyBinding>) {
+    self.bindings[control as usize] = if bindings.is_empty() && control.is_required() {
+      Self::new().bindings[control as usize].clone()
+    } else {
+      bindings
+    };
+  }
+
+  pub fn add_binding(&mut self, control: Control, binding: KeyBinding) {
+    let bindings = &mut self.bindings[control as usize];
+    if !bindings.contains(&binding) {
+      bindings.push(binding);
+    }
+  }
+
+  pub fn clear_bindings(&mut self, control: Control) {
+    self.set_bindings(control, vec![]);
+  }
+
+  // Controls read on the same screen as `control` that share one of its bindings
+  pub fn conflicts(&self, control: Control) -> Vec<Control> {
+    Control::ALL
+      .into_iter()
+      .filter(|&other| other != control && control.shares_context(other))
+      .filter(|&other| {
+        self
+          .bindings(other)
+          .iter()
+          .any(|binding| self.bindings(control).contains(binding))
+      })
+      .collect()
+  }
+
+  // Controls of player one's keymap that share a binding with `control` of
+  // player two's, and so would both be read at once in versus
+  pub fn versus_conflicts(&self, two: &Keymap, control: Control) -> Vec<Control> {
+    if !control.is_read_in_versus(true) {
+      return vec![];
+    }
+    Control::ALL
+      .into_iter()
+      .filter(|&other| other.is_read_in_versus(false))
+      .filter(|&other| {
+        self
+          .bindings(other)
+          .iter()
+          .any(|binding| two.bindings(control).contains(binding))
+      })
+      .collect()
+  }
+
+  // Name of the first key bound to `control`, to tell the player what to press
+  pub fn hint(&self, control: Control) -> String {
+    match self.bindings(control).first() {
+      Some(binding) => binding.name(),
+      None => "-".to_string(),
+    }
+  }
+
+  pub fn is_pressed(&self, rl: &RaylibHandle, control: Control) -> bool {
+    self
+      .bindings(control)
+      .iter()
+      .any(|binding| binding.is_pressed(rl))
+  }
+
+  pub fn is_down(&self, rl: &RaylibHandle, control: Control) -> bool {
+    self
+      .bindings(control)
+      .iter()
+      .any(|binding| binding.is_down(rl))
+  }
+
+  pub fn is_button_pressed(&self, rl: &RaylibHandle, gamepad: i32, control: Control) -> bool {
+    self
+      .buttons(control)
+      .iter()
+      .any(|&button| rl.is_gamepad_button_pressed(gamepad, button))
+  }
+
+  pub fn is_button_down(&self, rl: &RaylibHandle, gamepad: i32, control: Control) -> bool {
+    self
+      .buttons(control)
+      .iter()
+      .any(|&button| rl.is_gamepad_button_down(gamepad, button))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const PRESETS: [KeymapPreset; 4] = [
+    KeymapPreset::Guideline,
+    KeymapPreset::Wasd,
+    KeymapPreset::LeftHanded,
+    KeymapPreset::Numpad,
+  ];
+
+  #[test]
+  fn presets_have_no_conflicts() {
+    for preset in PRESETS {
+      let keymap = preset.keymap();
+      for control in Control::ALL {
+        assert_eq!(keymap.conflicts(control), [], "{}", preset.name());
+      }
+    }
+  }
+
+  #[test]
+  fn replay_controls_are_checked_against_the_ones_read_while_watching() {
+    let mut keymap = Keymap::new();
+    keymap.set_bindings(Control::SeekBack, keymap.bindings(Control::Pause).to_vec());
+    assert_eq!(keymap.conflicts(Control::SeekBack), [Control::Pause]);
+
+    // Moving left is never read while a replay plays
+    keymap.set_bindings(Control::SeekBack, keymap.bindings(Control::ShiftLeft).to_vec());
+    assert_eq!(keymap.conflicts(Control::SeekBack), []);
+  }
+
+  #[test]
+  fn player_two_keys_are_checked_against_player_one_in_versus() {
+    let one = KeymapPreset::Guideline.keymap();
+    let two = KeymapPreset::Wasd.keymap();
+    assert_eq!(
+      one.versus_conflicts(&two, Control::Hold),
+      [Control::HardDrop, Control::Hold]
+    );
+    assert_eq!(one.versus_conflicts(&two, Control::Restart), []);
+
+    let numpad = KeymapPreset::Numpad.keymap();
+    for control in Control::ALL {
+      assert_eq!(one.versus_conflicts(&numpad, control), []);
+    }
+  }
+
+  #[test]
+  fn confirming_is_checked_against_the_keys_of_the_results_screen() {
+    let mut keymap = Keymap::new();
+    keymap.set_bindings(Control::Confirm, keymap.bindings(Control::ShiftRight).to_vec());
+    assert_eq!(keymap.conflicts(Control::Confirm), [Control::ShiftRight]);
+  }
+
+  #[test]
+  fn default_buttons_are_not_shared_on_a_screen() {
+    let keymap = Keymap::new();
+    for control in Control::ALL {
+      for other in Control::ALL {
+        if other == control || !control.shares_context(other) {
+          continue;
+        }
+        assert!(
+          keymap
+            .buttons(control)
+            .iter()
+            .all(|button| !keymap.buttons(other).contains(button)),
+          "{:?} and {:?}",
+          control,
+          other
+        );
+      }
+    }
+  }
+}
@@ -1,56 +1,384 @@
+mod bot;
+mod netplay;
 mod playfield;
+mod replay;
+mod versus;
 
-use self::playfield::{falling_piece::*, next_queue::*, *};
-use rand::Rng;
+pub use self::bot::{Bot, Difficulty};
+pub use self::netplay::Netplay;
+use self::netplay::{Broadcast, NetplayError, Spectator};
+use self::playfield::{
+  falling_piece::*,
+  finesse::Trainer,
+  input::*,
+  mode::Record,
+  next_queue::*,
+  scoring::LineClear,
+  stats::{Stats, Timeline},
+  *,
+};
+pub use self::playfield::{
+  gravity::GravityCurve,
+  handling::{HandlingSettings, SoftDropSpeed},
+  input::Action,
+  mode::GameMode,
+  randomizer::RandomizerKind,
+  rules::Rules,
+  scoring::ScoringSystem,
+};
+pub use self::replay::Replay;
+pub use self::versus::Versus;
+use super::{
+  controls::{Control, Keymap},
+  records::Records,
+  settings::{Settings, VisualSettings},
+};
 use raylib::prelude::*;
+use std::{io, path::PathBuf};
 
 pub struct Game {
   block_length: f32,
   position: Vector2,
   playfield: Playfield,
   paused: bool,
-  undo_move_stack: Vec<Playfield>,
+  undo_move_stack: Vec<(Playfield, usize)>,
+  keyboard_input: KeyboardInput,
+  visual: VisualSettings,
+  replay: Replay,
+  playback: Option<Playback>,
+  spectator: Option<Spectator>,
+  broadcast: Option<Broadcast>,
+  bot: Option<Bot>,
+  finish: Option<Finish>,
+  timeline: Timeline,
+  results: Option<Results>,
+  is_quitting: bool,
+}
+
+// How a game that reached the goal of its mode compares to the personal best
+struct Finish {
+  record: Option<Record>,
+  best: Option<Record>,
+  error: Option<String>,
+}
+
+impl Finish {
+  fn new(playfield: &Playfield) -> Self {
+    let rules = playfield.rules();
+    let mut finish = Self {
+      record: rules.mode.record(playfield),
+      best: None,
+      error: None,
+    };
+    let Some(record) = finish.record else {
+      return finish;
+    };
+
+    let name = rules.record_name();
+    let mut records = match Records::load() {
+      Ok(records) => records,
+      Err(error) => {
+        finish.error = Some(format!("Could not load the records: {}", error));
+        return finish;
+      }
+    };
+    finish.best = records.get(&name).map(|value| record.with_value(value));
+    if finish.is_new_best() {
+      records.set(&name, record.value());
+      if let Err(error) = records.save() {
+        finish.error = Some(format!("Could not save the record: {}", error));
+      }
+    }
+    finish
+  }
+
+  fn is_new_best(&self) -> bool {
+    match (self.record, self.best) {
+      (Some(record), Some(best)) => record.beats(best),
+      (record, None) => record.is_some(),
+      (None, _) => false,
+    }
+  }
+}
+
+// What can be done from the results screen
+#[derive(Clone, Copy, PartialEq)]
+enum ResultsOption {
+  Retry,
+  SaveReplay,
+  Menu,
+}
+
+impl ResultsOption {
+  const ALL: [Self; 3] = [Self::Retry, Self::SaveReplay, Self::Menu];
+
+  fn name(self) -> &'static str {
+    match self {
+      Self::Retry => "RETRY",
+      Self::SaveReplay => "SAVE REPLAY",
+      Self::Menu => "MENU",
+    }
+  }
+}
+
+// The screen a game that is over stays on, lost or finished, until the player
+// picks what to do next
+struct Results {
+  selected: usize,
+  saved: Option<PathBuf>,
+  error: Option<String>,
+}
+
+impl Results {
+  fn new() -> Self {
+    Self {
+      selected: 0,
+      saved: None,
+      error: None,
+    }
+  }
+}
+
+// Plays a replay back. Snapshots of the playfield are kept every few seconds of
+// the replay, so seeking backwards doesn't have to simulate it from the start.
+struct Playback {
+  frame: usize,
+  speed: f32,
+  progress: f32,
+  is_paused: bool,
+  keyframes: Vec<Playfield>,
+}
+
+impl Playback {
+  const KEYFRAME_INTERVAL: usize = 600;
+  const SEEK_FRAMES: usize = 300;
+  const MIN_SPEED: f32 = 0.25;
+  const MAX_SPEED: f32 = 8.0;
+
+  fn new(playfield: &Playfield) -> Self {
+    Self {
+      frame: 0,
+      speed: 1.0,
+      progress: 0.0,
+      is_paused: false,
+      keyframes: vec![playfield.clone()],
+    }
+  }
+
+  fn seek(&mut self, target: usize, playfield: &mut Playfield, replay: &Replay) {
+    let target = target.min(replay.len());
+    if target < self.frame {
+      let keyframe = (target / Self::KEYFRAME_INTERVAL).min(self.keyframes.len() - 1);
+      *playfield = self.keyframes[keyframe].clone();
+      self.frame = keyframe * Self::KEYFRAME_INTERVAL;
+    }
+
+    while self.frame < target {
+      playfield.update(replay.frame(self.frame));
+      self.frame += 1;
+      if self.frame == self.keyframes.len() * Self::KEYFRAME_INTERVAL {
+        self.keyframes.push(playfield.clone());
+      }
+    }
+  }
+
+  // Forgets everything past `len` frames, going back to before them if needed
+  fn truncate(&mut self, len: usize, playfield: &mut Playfield) {
+    self.keyframes.truncate(len / Self::KEYFRAME_INTERVAL + 1);
+    if self.frame > len {
+      *playfield = self.keyframes.last().unwrap().clone();
+      self.frame = (self.keyframes.len() - 1) * Self::KEYFRAME_INTERVAL;
+    }
+  }
+}
+
+struct KeyboardInput {
+  keymap: Keymap,
+}
+
+impl KeyboardInput {
+  fn poll(&self, rl: &RaylibHandle) -> InputFrame {
+    let mut input = InputFrame::new();
+    for control in Control::ALL {
+      let Some(action) = control.action() else {
+        continue;
+      };
+
+      if self.keymap.is_pressed(rl, control) {
+        input.press(action);
+      } else if self.keymap.is_down(rl, control) {
+        input.hold(action);
+      }
+    }
+    input
+  }
 }
 
 impl Game {
   const HEIGHT_SCALE_FACTOR: f32 = 0.8;
-  pub fn new(rl: &RaylibHandle, rng: &mut impl Rng) -> Self {
-    let block_length =
-      rl.get_screen_height() as f32 * Self::HEIGHT_SCALE_FACTOR / Playfield::VISIBLE_HEIGHT as f32;
-    let position = Vector2 {
-      x: (rl.get_screen_width() as f32 - block_length * Playfield::WIDTH as f32) / 2.0,
-      y: (rl.get_screen_height() as f32 - block_length * Playfield::VISIBLE_HEIGHT as f32) / 2.0,
-    };
-    let playfield = Playfield::new(rng);
-    let undo_move_stack = vec![playfield.clone()];
-    Game {
-      block_length,
-      position,
+  // Blocks across the tetrion with the hold piece, next queue and stats around it
+  const BOARD_WIDTH: f32 = 30.0;
+  // Rows in the stats panel, one for each stat
+  const STATS_ROWS: usize = 13;
+
+  pub fn new(rl: &RaylibHandle, rules: Rules, settings: &Settings) -> Self {
+    Self::with_replay(rl, Replay::new(rules), settings, false)
+  }
+
+  pub fn watch(rl: &RaylibHandle, replay: Replay, settings: &Settings) -> Self {
+    Self::with_replay(rl, replay, settings, true)
+  }
+
+  // The bot playing on its own, to show the game off on the title screen
+  pub fn demo(rl: &RaylibHandle, rules: Rules, settings: &Settings) -> Self {
+    let mut game = Self::new(rl, rules, settings);
+    game.bot = Some(Bot::new(Difficulty::Medium));
+    game
+  }
+
+  // Watches a game broadcast from another machine as it is played
+  pub fn spectate(
+    rl: &RaylibHandle,
+    address: &str,
+    settings: &Settings,
+  ) -> Result<Self, NetplayError> {
+    let (spectator, rules) = Spectator::connect(address)?;
+    let mut game = Self::with_replay(rl, Replay::new(rules), settings, true);
+    game.spectator = Some(spectator);
+    Ok(game)
+  }
+
+  // Lets others watch this game live
+  pub fn broadcast(&mut self) -> io::Result<()> {
+    self.broadcast = Some(Broadcast::new()?);
+    Ok(())
+  }
+
+  fn with_replay(
+    rl: &RaylibHandle,
+    replay: Replay,
+    settings: &Settings,
+    is_playback: bool,
+  ) -> Self {
+    let playfield = replay.playfield();
+    let undo_move_stack = vec![(playfield.clone(), 0)];
+    let playback = is_playback.then(|| Playback::new(&playfield));
+    let mut game = Game {
+      block_length: 0.0,
+      position: Vector2 { x: 0.0, y: 0.0 },
       playfield,
       paused: false,
       undo_move_stack,
+      keyboard_input: KeyboardInput {
+        keymap: settings.keymap.clone(),
+      },
+      visual: settings.visual,
+      replay,
+      playback,
+      spectator: None,
+      broadcast: None,
+      bot: None,
+      finish: None,
+      timeline: Timeline::new(),
+      results: None,
+      is_quitting: false,
+    };
+    game.fit(Rectangle::new(
+      0.0,
+      0.0,
+      rl.get_screen_width() as f32,
+      rl.get_screen_height() as f32,
+    ));
+    game
+  }
+
+  // Sizes the board to fit in `area` of the screen, with the tetrion at its center
+  fn fit(&mut self, area: Rectangle) {
+    self.block_length = (area.height * Self::HEIGHT_SCALE_FACTOR
+      / Playfield::VISIBLE_HEIGHT as f32)
+      .min(area.width / Self::BOARD_WIDTH);
+    self.position = Vector2 {
+      x: area.x + (area.width - self.block_length * Playfield::WIDTH as f32) / 2.0,
+      y: area.y + (area.height - self.block_length * Playfield::VISIBLE_HEIGHT as f32) / 2.0,
+    };
+  }
+
+  // The recording of a game that was quit before it was over, ready to be saved.
+  // Games that are over are only saved if the player picks it on the results screen.
+  pub fn finished_replay(&self) -> Option<Replay> {
+    if self.playback.is_some()
+      || self.bot.is_some()
+      || self.replay.is_empty()
+      || self.results.is_some()
+    {
+      return None;
     }
+
+    let mut replay = self.replay.clone();
+    replay.finish(&self.playfield);
+    Some(replay)
   }
 
-  pub fn run(&mut self, rl: &mut RaylibHandle, thread: &RaylibThread, rng: &mut impl Rng) {
-    while !rl.is_key_pressed(KeyboardKey::KEY_ESCAPE) || !self.paused {
-      self.update(rl, rng);
+  pub fn run(&mut self, rl: &mut RaylibHandle, thread: &RaylibThread) {
+    while !self.is_quitting
+      && (!self.keyboard_input.keymap.is_pressed(rl, Control::Quit) || !self.can_quit())
+    {
+      // The demo goes on until any key is pressed, or the bot tops out
+      if self.bot.is_some() && (rl.get_key_pressed().is_some() || self.playfield.has_lost) {
+        break;
+      }
+      self.update(rl);
       self.draw(rl, thread);
     }
 
     let _d = rl.begin_drawing(thread);
   }
 
-  fn update(&mut self, rl: &RaylibHandle, rng: &mut impl Rng) {
-    if rl.is_key_down(KeyboardKey::KEY_LEFT_CONTROL)
-      && rl.is_key_pressed(KeyboardKey::KEY_Z)
-      && !self.undo_move_stack.is_empty()
-    {
-      self.playfield = self.undo_move_stack.pop().unwrap();
+  // Quitting needs the game to be paused or over, so it can't happen by accident
+  fn can_quit(&self) -> bool {
+    self.paused || self.playback.is_some() || self.playfield.has_lost || self.playfield.is_finished
+  }
+
+  fn update(&mut self, rl: &RaylibHandle) {
+    if let Some(broadcast) = &mut self.broadcast {
+      broadcast.update(&self.replay);
+    }
+    if self.keyboard_input.keymap.is_pressed(rl, Control::Stats) {
+      self.visual.stats_panel = !self.visual.stats_panel;
+    }
+    if self.spectator.is_some() {
+      self.update_spectator();
+      return;
+    }
+    if self.playback.is_some() {
+      self.update_playback(rl);
+      return;
+    }
+
+    // Undoing would roll the clock back on a game that sets a record
+    let can_undo = self
+      .playfield
+      .rules()
+      .mode
+      .record(&self.playfield)
+      .is_none();
+    let keymap = &self.keyboard_input.keymap;
+    if can_undo && keymap.is_pressed(rl, Control::Undo) && !self.undo_move_stack.is_empty() {
+      let (playfield, frames) = self.undo_move_stack.pop().unwrap();
+      self.playfield = playfield;
+      self.replay.truncate(frames);
+      self.timeline.rewind(self.playfield.stats.frames);
+      self.finish = None;
+      self.results = None;
+      return;
+    }
+
+    if self.results.is_some() {
+      self.update_results(rl);
       return;
     }
 
-    if rl.is_key_pressed(KeyboardKey::KEY_ENTER) {
+    if keymap.is_pressed(rl, Control::Pause) {
       self.paused = !self.paused;
     }
 
@@ -58,11 +386,143 @@ impl Game {
       return;
     }
 
-    if self.playfield.update(rl, rng) {
-      self.undo_move_stack.push(self.playfield.clone());
+    let input = match &mut self.bot {
+      Some(bot) => bot.poll(&self.playfield),
+      None => self.keyboard_input.poll(rl),
+    };
+    self.replay.push(input);
+    if self.playfield.update(input) {
+      self
+        .undo_move_stack
+        .push((self.playfield.clone(), self.replay.len()));
+    }
+
+    self.timeline.record(&self.playfield);
+
+    if !self.playfield.is_finished {
+      self.finish = None;
+    } else if self.finish.is_none() {
+      self.finish = Some(Finish::new(&self.playfield));
+    }
+    if self.playfield.has_lost || self.playfield.is_finished {
+      self.results = Some(Results::new());
+    }
+  }
+
+  // Moving left and right picks an option and confirming takes it. Restarting
+  // retries right away, like it does while playing.
+  fn update_results(&mut self, rl: &RaylibHandle) {
+    let Some(results) = &mut self.results else {
+      return;
+    };
+    let keymap = &self.keyboard_input.keymap;
+    let options = ResultsOption::ALL.len();
+    if keymap.is_pressed(rl, Control::ShiftRight) {
+      results.selected = (results.selected + 1) % options;
+    } else if keymap.is_pressed(rl, Control::ShiftLeft) {
+      results.selected = (results.selected + options - 1) % options;
+    }
+
+    let option = if keymap.is_pressed(rl, Control::Restart) {
+      ResultsOption::Retry
+    } else if keymap.is_pressed(rl, Control::Confirm) {
+      ResultsOption::ALL[results.selected]
+    } else {
+      return;
+    };
+    match option {
+      ResultsOption::Retry => self.retry(),
+      ResultsOption::SaveReplay => self.save_replay(),
+      ResultsOption::Menu => self.is_quitting = true,
     }
   }
 
+  // Starts over on the same seed, as a new recording
+  fn retry(&mut self) {
+    self.replay = Replay::new(self.playfield.rules().clone());
+    self.playfield = self.replay.playfield();
+    self.undo_move_stack = vec![(self.playfield.clone(), 0)];
+    self.timeline = Timeline::new();
+    self.finish = None;
+    self.results = None;
+  }
+
+  fn save_replay(&mut self) {
+    let Some(results) = &mut self.results else {
+      return;
+    };
+    if results.saved.is_some() {
+      return;
+    }
+
+    let mut replay = self.replay.clone();
+    replay.finish(&self.playfield);
+    match replay.save() {
+      Ok(path) => results.saved = Some(path),
+      Err(error) => results.error = Some(format!("Could not save the replay: {}", error)),
+    }
+  }
+
+  fn update_playback(&mut self, rl: &RaylibHandle) {
+    let Some(playback) = &mut self.playback else {
+      return;
+    };
+
+    let keymap = &self.keyboard_input.keymap;
+    if keymap.is_pressed(rl, Control::Pause) {
+      playback.is_paused = !playback.is_paused;
+    }
+    if keymap.is_pressed(rl, Control::SpeedUp) {
+      playback.speed = (playback.speed * 2.0).min(Playback::MAX_SPEED);
+    } else if keymap.is_pressed(rl, Control::SlowDown) {
+      playback.speed = (playback.speed / 2.0).max(Playback::MIN_SPEED);
+    }
+
+    let mut target = playback.frame;
+    if keymap.is_pressed(rl, Control::SeekForward) {
+      target += Playback::SEEK_FRAMES;
+    } else if keymap.is_pressed(rl, Control::SeekBack) {
+      target = target.saturating_sub(Playback::SEEK_FRAMES);
+    }
+
+    if playback.is_paused {
+      if keymap.is_pressed(rl, Control::StepFrame) {
+        target += 1;
+      }
+    } else {
+      playback.progress += playback.speed;
+      let steps = playback.progress as usize;
+      playback.progress -= steps as f32;
+      target += steps;
+    }
+
+    playback.seek(target, &mut self.playfield, &self.replay);
+  }
+
+  // Plays along a short delay behind the broadcast, skipping ahead if it falls
+  // further behind than that
+  fn update_spectator(&mut self) {
+    let (Some(spectator), Some(playback)) = (&mut self.spectator, &mut self.playback) else {
+      return;
+    };
+
+    if let Some(len) = spectator.receive(&mut self.replay) {
+      playback.truncate(len, &mut self.playfield);
+    }
+    let delay = if spectator.is_live() {
+      Spectator::DELAY
+    } else {
+      0
+    };
+    let live = self.replay.len().saturating_sub(delay);
+    let target = if playback.frame + Spectator::DELAY < live {
+      live
+    } else {
+      live.min(playback.frame + 1)
+    };
+    playback.seek(target, &mut self.playfield, &self.replay);
+  }
+
   fn get_block_rectangle(&self, i: i8, j: i8) -> Rectangle {
     Rectangle::new(
       self.position.x + i as f32 * self.block_length,
@@ -98,18 +558,49 @@ impl Game {
   pub fn draw(&self, rl: &mut RaylibHandle, thread: &RaylibThread) {
     let mut d = rl.begin_drawing(thread);
     d.clear_background(Color::LIGHTGRAY);
+    self.draw_board(&mut d);
+    if self.visual.stats_panel {
+      self.draw_stats_panel(&mut d);
+    }
 
-    self.draw_tetrion(&mut d);
-    let ghost_piece = self.playfield.get_ghost_piece();
-    self.draw_piece(
-      &mut d,
-      &ghost_piece.tetromino_map,
-      Color::GRAY,
-      ghost_piece.position,
-    );
+    if let Some(playback) = &self.playback {
+      self.draw_playback(&mut d, playback);
+      return;
+    }
+    if let Some(broadcast) = &self.broadcast {
+      let live = format!("LIVE: {} watching", broadcast.viewers());
+      self.draw_status(&mut d, &live, Color::RED);
+    }
+    if self.bot.is_some() {
+      self.draw_status(&mut d, "DEMO  Press any key", Color::BLUE);
+    }
+    if let Some(trainer) = &self.playfield.trainer {
+      self.draw_trainer_status(&mut d, trainer);
+    }
+    self.draw_overlay(&mut d);
+  }
+
+  // Everything but the overlays: the tetrion, the pieces, the previews and the stats
+  fn draw_board(&self, d: &mut RaylibDrawHandle) {
+    self.draw_tetrion(d);
+    if self.visual.ghost_piece {
+      let ghost_piece = self.playfield.get_ghost_piece();
+      self.draw_piece(
+        d,
+        &ghost_piece.tetromino_map,
+        Color::GRAY,
+        ghost_piece.position,
+      );
+    }
+    let trainer = self.playfield.trainer.as_ref();
+    if let Some(target) = trainer.and_then(|trainer| trainer.target.as_ref()) {
+      let mut color = target.piece.tetromino.get_tetromino_color();
+      color.a = 100;
+      self.draw_piece(d, &target.piece.tetromino_map, color, target.piece.position);
+    }
     let falling_piece = &self.playfield.falling_piece;
     self.draw_piece(
-      &mut d,
+      d,
       &falling_piece.tetromino_map,
       falling_piece.tetromino.get_tetromino_color(),
       falling_piece.position,
@@ -139,7 +630,7 @@ impl Game {
     for id in 0..NextQueue::NEXT_QUEUE_SIZE {
       let current_tetromino = self.playfield.next_queue[id];
       self.draw_piece(
-        &mut d,
+        d,
         &current_tetromino.initial_tetromino_map(),
         current_tetromino.get_tetromino_color(),
         (
@@ -171,7 +662,7 @@ impl Game {
       Color::DARKGRAY
     };
     self.draw_piece(
-      &mut d,
+      d,
       &self.playfield.holding_piece.initial_tetromino_map(),
       hold_color,
       (-5, 4 + Playfield::VISIBLE_HEIGHT as i8),
@@ -236,11 +727,27 @@ impl Game {
         font_size,
         text_color,
       );
+
+      let spin_text = match self.playfield.message.spin_type {
+        SpinType::Proper => "T-SPIN",
+        SpinType::Mini => "T-SPIN MINI",
+        SpinType::No => "",
+      };
+      let spin_text_block = self.get_block_rectangle(-10, Playfield::HEIGHT as i8 - 2);
+      let mut spin_color = Tetromino::T.get_tetromino_color();
+      spin_color.a = text_color.a;
+      d.draw_text(
+        spin_text,
+        spin_text_block.x as i32,
+        spin_text_block.y as i32,
+        font_size,
+        spin_color,
+      );
     }
 
     // Combo
     if self.playfield.combo >= 2 {
-      let combo_text_block = self.get_block_rectangle(-10, Playfield::HEIGHT as i8 - 2);
+      let combo_text_block = self.get_block_rectangle(-10, Playfield::HEIGHT as i8 - 4);
       let combo = format!("{}", self.playfield.combo);
       d.draw_text(
         "COMBO ",
@@ -251,7 +758,7 @@ impl Game {
       );
       d.draw_text(
         &combo,
-        combo_text_block.x as i32 + d.measure_text("COMBO ", font_size as i32),
+        combo_text_block.x as i32 + d.measure_text("COMBO ", font_size),
         combo_text_block.y as i32,
         font_size,
         Color::BLUE,
@@ -260,7 +767,7 @@ impl Game {
 
     // Back to Back (B2B)
     if self.playfield.b2b >= 2 {
-      let b2b_text_block = self.get_block_rectangle(-10, Playfield::HEIGHT as i8 - 4);
+      let b2b_text_block = self.get_block_rectangle(-10, Playfield::HEIGHT as i8 - 6);
       let b2b = format!("{}", self.playfield.b2b - 1);
       d.draw_text(
         "B2B ",
@@ -278,6 +785,30 @@ impl Game {
       );
     }
 
+    // Level and lines, or the progress towards the goal of the mode
+    for (id, (label, value)) in self.stats().iter().enumerate() {
+      let stat_text_block =
+        self.get_block_rectangle(-10, Playfield::VISIBLE_HEIGHT as i8 + 8 + 2 * id as i8);
+      d.draw_text(
+        label,
+        stat_text_block.x as i32,
+        stat_text_block.y as i32,
+        font_size,
+        Color::BLACK,
+      );
+      d.draw_text(
+        value,
+        stat_text_block.x as i32 + d.measure_text(label, font_size),
+        stat_text_block.y as i32,
+        font_size,
+        Color::BLACK,
+      );
+    }
+
+    if trainer.is_some() {
+      self.draw_finesse_counts(d);
+    }
+
     // Score
     let score_text_block = self.get_block_rectangle(11, Playfield::HEIGHT as i8 - 2);
     d.draw_text(
@@ -296,9 +827,14 @@ impl Game {
       font_size,
       Color::BLACK,
     );
+  }
 
-    // Game over or paused
-    if self.playfield.has_lost || self.paused {
+  // Game over, finished or paused
+  fn draw_overlay(&self, d: &mut RaylibDrawHandle) {
+    let font_size = self.block_length as i32 * 2;
+    if let Some(results) = &self.results {
+      self.draw_results(d, results);
+    } else if self.can_quit() {
       let screen_width = d.get_screen_width();
       let screen_height = d.get_screen_height();
       let font_size_big = self.block_length as i32 * 5;
@@ -316,15 +852,7 @@ impl Game {
         },
       );
 
-      if self.playfield.has_lost {
-        d.draw_text(
-          "YOU LOST",
-          (screen_width - d.measure_text("YOU LOST", font_size_big)) / 2,
-          screen_height / 2,
-          font_size_big,
-          Color::RED,
-        );
-      } else if self.paused {
+      if self.paused {
         d.draw_text(
           "GAME PAUSED",
           (screen_width - d.measure_text("GAME PAUSED", font_size_big)) / 2,
@@ -333,9 +861,21 @@ impl Game {
           Color::BLUE,
         );
       }
+      let seed_text = format!("Seed: {}", self.playfield.rules().seed);
       d.draw_text(
-        "Press Esc to quit",
-        (screen_width - d.measure_text("Press Esc to quit", font_size)) / 2,
+        &seed_text,
+        (screen_width - d.measure_text(&seed_text, font_size)) / 2,
+        screen_height / 2 + font_size_big + 2 * font_size,
+        font_size,
+        Color::WHITE,
+      );
+      let quit_text = format!(
+        "Press {} to quit",
+        self.keyboard_input.keymap.hint(Control::Quit)
+      );
+      d.draw_text(
+        &quit_text,
+        (screen_width - d.measure_text(&quit_text, font_size)) / 2,
         screen_height / 2 + font_size_big,
         font_size,
         Color::WHITE,
@@ -343,6 +883,478 @@ impl Game {
     }
   }
 
+  fn stats(&self) -> Vec<(&'static str, String)> {
+    let playfield = &self.playfield;
+    match playfield.rules().mode {
+      GameMode::Endless | GameMode::Versus => vec![
+        ("LEVEL ", playfield.level.to_string()),
+        ("LINES ", playfield.stats.lines.to_string()),
+      ],
+      GameMode::Finesse => vec![
+        ("PIECES ", playfield.finesse.pieces().to_string()),
+        ("FAULTS ", playfield.finesse.faults().to_string()),
+        (
+          "MISSED ",
+          playfield
+            .trainer
+            .as_ref()
+            .map_or(0, |trainer| trainer.misses)
+            .to_string(),
+        ),
+      ],
+      GameMode::Survival => vec![
+        ("TIME ", Record::Time(playfield.stats.frames).to_string()),
+        ("LEVEL ", playfield.level.to_string()),
+        ("LINES ", playfield.stats.lines.to_string()),
+      ],
+      GameMode::Ultra => vec![
+        (
+          "TIME ",
+          Record::Time(GameMode::ULTRA_FRAMES.saturating_sub(playfield.stats.frames)).to_string(),
+        ),
+        ("LEVEL ", playfield.level.to_string()),
+        ("LINES ", playfield.stats.lines.to_string()),
+      ],
+      GameMode::Marathon { goal, .. } => vec![
+        ("LEVEL ", playfield.level.to_string()),
+        (
+          "LINES ",
+          match goal {
+            Some(goal) => format!("{}/{}", playfield.stats.lines, goal),
+            None => playfield.stats.lines.to_string(),
+          },
+        ),
+      ],
+      GameMode::Sprint | GameMode::Dig { .. } => {
+        let left = match playfield.rules().mode {
+          GameMode::Dig { goal, .. } => goal.saturating_sub(playfield.garbage_cleared),
+          _ => GameMode::SPRINT_LINES.saturating_sub(playfield.stats.lines),
+        };
+        vec![
+          ("TIME ", Record::Time(playfield.stats.frames).to_string()),
+          ("PPS ", format!("{:.2}", playfield.stats.pieces_per_second())),
+          ("LEFT ", left.to_string()),
+        ]
+      }
+    }
+  }
+
+  // The final stats of a game that is over, graphs of how it went, the clears
+  // its score came from, and what to do next
+  fn draw_results(&self, d: &mut RaylibDrawHandle, results: &Results) {
+    let screen_width = d.get_screen_width();
+    let screen_height = d.get_screen_height();
+    let block_length = self.block_length as i32;
+    let font_size = block_length * 3 / 4;
+    let center = screen_width / 2;
+    let row = |id: usize| 29 * block_length / 4 + id as i32 * font_size;
+    d.draw_rectangle(
+      0,
+      0,
+      screen_width,
+      screen_height,
+      Color {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 200,
+      },
+    );
+
+    let (title, title_color) = if self.playfield.has_lost {
+      ("YOU LOST", Color::RED)
+    } else {
+      ("FINISHED", Color::GREEN)
+    };
+    d.draw_text(
+      title,
+      (screen_width - d.measure_text(title, 3 * block_length)) / 2,
+      block_length,
+      3 * block_length,
+      title_color,
+    );
+
+    let mut lines = Vec::new();
+    if let Some(finish) = &self.finish {
+      if let Some(record) = finish.record {
+        lines.push((record.to_string(), Color::WHITE));
+      }
+      if let Some(error) = &finish.error {
+        lines.push((error.clone(), Color::RED));
+      } else if finish.is_new_best() {
+        lines.push(("NEW PERSONAL BEST".to_string(), Color::GOLD));
+      } else if let Some(best) = finish.best {
+        lines.push((format!("Best: {}", best), Color::WHITE));
+      }
+    }
+    if let Some(path) = &results.saved {
+      lines.push((format!("Replay saved to {}", path.display()), Color::WHITE));
+    }
+    if let Some(error) = &results.error {
+      lines.push((error.clone(), Color::RED));
+    }
+    for (id, (text, color)) in lines.iter().enumerate() {
+      d.draw_text(
+        text,
+        (screen_width - d.measure_text(text, font_size)) / 2,
+        17 * block_length / 4 + id as i32 * font_size,
+        font_size,
+        *color,
+      );
+    }
+
+    // The stats on the left
+    let stats_x = center - 20 * block_length;
+    for (id, (label, value)) in Self::stats_rows(&self.playfield.stats).iter().enumerate() {
+      d.draw_text(label, stats_x, row(id), font_size, Color::WHITE);
+      d.draw_text(
+        value,
+        stats_x + 8 * block_length - d.measure_text(value, font_size),
+        row(id),
+        font_size,
+        Color::LIGHTGRAY,
+      );
+    }
+
+    // How fast the pieces went down and how high the stack got in the middle
+    let graph = |top: i32| {
+      Rectangle::new(
+        (center - 10 * block_length) as f32,
+        (row(0) + top * block_length) as f32,
+        13.0 * self.block_length,
+        5.0 * self.block_length,
+      )
+    };
+    self.draw_graph(
+      d,
+      graph(1),
+      "PPS",
+      &self.timeline.pieces_per_second(),
+      0.0,
+      Color::SKYBLUE,
+    );
+    self.draw_graph(
+      d,
+      graph(8),
+      "STACK HEIGHT",
+      &self.timeline.heights(),
+      Playfield::VISIBLE_HEIGHT as f32,
+      Color::ORANGE,
+    );
+
+    // Line clears on the right, each with how many there were and the points
+    // they made, then the bonuses on top of them
+    let breakdown = &self.playfield.breakdown;
+    let clears: Vec<_> = LineClear::NAMES
+      .iter()
+      .zip(breakdown.clears)
+      .filter(|(_, clear)| clear.count > 0)
+      .collect();
+    let most = clears
+      .iter()
+      .map(|(_, clear)| clear.count)
+      .max()
+      .unwrap_or(1);
+    let clears_x = center + 4 * block_length;
+    let right = center + 20 * block_length;
+    d.draw_text("CLEARS", clears_x, row(0), font_size, Color::WHITE);
+    for (id, (name, clear)) in clears.iter().enumerate() {
+      let y = row(id + 1);
+      let value = format!("{}  {}", clear.count, clear.points);
+      let bar_width = (3.5 * self.block_length * clear.count as f32 / most as f32) as i32;
+      d.draw_text(name, clears_x, y, font_size, Color::WHITE);
+      d.draw_rectangle(
+        clears_x + 17 * block_length / 2,
+        y,
+        bar_width.max(1),
+        font_size,
+        Color::SKYBLUE,
+      );
+      d.draw_text(
+        &value,
+        right - d.measure_text(&value, font_size),
+        y,
+        font_size,
+        Color::LIGHTGRAY,
+      );
+    }
+    let bonuses = [
+      ("Back to back", breakdown.b2b),
+      ("Combos", breakdown.combo),
+      ("All clears", breakdown.all_clear),
+      ("Drops", breakdown.drops),
+      ("Score", self.playfield.score),
+    ];
+    for (id, (label, points)) in bonuses.iter().enumerate() {
+      let y = row(clears.len() + 2 + id);
+      let points = points.to_string();
+      d.draw_text(label, clears_x, y, font_size, Color::WHITE);
+      d.draw_text(
+        &points,
+        right - d.measure_text(&points, font_size),
+        y,
+        font_size,
+        Color::WHITE,
+      );
+    }
+
+    let options: Vec<_> = ResultsOption::ALL
+      .iter()
+      .map(|&option| match option {
+        ResultsOption::SaveReplay if results.saved.is_some() => "SAVED",
+        option => option.name(),
+      })
+      .collect();
+    let gap = 2 * block_length;
+    let width: i32 = options
+      .iter()
+      .map(|name| d.measure_text(name, block_length) + gap)
+      .sum::<i32>()
+      - gap;
+    let mut x = (screen_width - width) / 2;
+    for (id, name) in options.iter().enumerate() {
+      let color = if id == results.selected {
+        Color::GOLD
+      } else {
+        Color::WHITE
+      };
+      d.draw_text(
+        name,
+        x,
+        screen_height - 4 * block_length,
+        block_length,
+        color,
+      );
+      x += d.measure_text(name, block_length) + gap;
+    }
+
+    let keymap = &self.keyboard_input.keymap;
+    let help = format!(
+      "{}/{} to choose, {} to confirm, {} to retry  Seed: {}",
+      keymap.hint(Control::ShiftLeft),
+      keymap.hint(Control::ShiftRight),
+      keymap.hint(Control::Confirm),
+      keymap.hint(Control::Restart),
+      self.playfield.rules().seed
+    );
+    d.draw_text(
+      &help,
+      (screen_width - d.measure_text(&help, font_size)) / 2,
+      screen_height - 2 * block_length,
+      font_size,
+      Color::WHITE,
+    );
+  }
+
+  // A line graph of `values` taken over the game, scaled to `top` or to the
+  // largest of them if that is higher
+  fn draw_graph(
+    &self,
+    d: &mut RaylibDrawHandle,
+    rec: Rectangle,
+    title: &str,
+    values: &[f32],
+    top: f32,
+    color: Color,
+  ) {
+    let font_size = self.block_length as i32 * 3 / 4;
+    let peak = values.iter().copied().fold(0.0, f32::max);
+    let title = format!("{}  peak {:.1}", title, peak);
+    d.draw_text(
+      &title,
+      rec.x as i32,
+      (rec.y - self.block_length) as i32,
+      font_size,
+      Color::WHITE,
+    );
+    d.draw_rectangle_lines_ex(rec, 1.0, Color::GRAY);
+
+    let top = top.max(peak);
+    if values.len() < 2 || top <= 0.0 {
+      return;
+    }
+    let points: Vec<Vector2> = values
+      .iter()
+      .enumerate()
+      .map(|(id, value)| Vector2 {
+        x: rec.x + rec.width * id as f32 / (values.len() - 1) as f32,
+        y: rec.y + rec.height * (1.0 - value / top),
+      })
+      .collect();
+    d.draw_line_strip(&points, color);
+  }
+
+  fn draw_playback(&self, d: &mut RaylibDrawHandle, playback: &Playback) {
+    let font_size = self.block_length as i32;
+    let keymap = &self.keyboard_input.keymap;
+    let status_block = self.get_block_rectangle(0, Playfield::VISIBLE_HEIGHT as i8 - 2);
+    let status = match &self.spectator {
+      Some(spectator) if spectator.is_live() => "WATCHING LIVE".to_string(),
+      Some(_) => "BROADCAST ENDED".to_string(),
+      None => format!(
+        "REPLAY {}/{}  x{}{}",
+        playback.frame,
+        self.replay.len(),
+        playback.speed,
+        if playback.is_paused { "  PAUSED" } else { "" }
+      ),
+    };
+    d.draw_text(
+      &status,
+      status_block.x as i32,
+      status_block.y as i32,
+      font_size,
+      Color::BLACK,
+    );
+
+    let help = if self.spectator.is_some() {
+      format!("{} quit", keymap.hint(Control::Quit))
+    } else {
+      format!(
+        "{} pause, {} step, {}/{} speed, {}/{} seek, {} quit",
+        keymap.hint(Control::Pause),
+        keymap.hint(Control::StepFrame),
+        keymap.hint(Control::SpeedUp),
+        keymap.hint(Control::SlowDown),
+        keymap.hint(Control::SeekBack),
+        keymap.hint(Control::SeekForward),
+        keymap.hint(Control::Quit)
+      )
+    };
+    d.draw_text(
+      &help,
+      status_block.x as i32,
+      status_block.y as i32 + font_size,
+      font_size / 2,
+      Color::BLACK,
+    );
+  }
+
+  // The stats panel and the results screen show the same things
+  fn stats_rows(stats: &Stats) -> [(&'static str, String); Self::STATS_ROWS] {
+    let t_spins = stats.t_spins;
+    [
+      ("TIME", Record::Time(stats.frames).to_string()),
+      ("PIECES", stats.pieces.to_string()),
+      ("PPS", format!("{:.2}", stats.pieces_per_second())),
+      ("LINES", stats.lines.to_string()),
+      ("ATTACK", stats.attack.to_string()),
+      ("APM", format!("{:.1}", stats.attack_per_minute())),
+      ("KPP", format!("{:.2}", stats.keys_per_piece())),
+      ("FINESSE", stats.finesse_faults.to_string()),
+      ("TSM", stats.t_spin_minis.to_string()),
+      ("TS/TSS", format!("{}/{}", t_spins[0], t_spins[1])),
+      ("TSD/TST", format!("{}/{}", t_spins[2], t_spins[3])),
+      ("MAX COMBO", stats.max_combo.to_string()),
+      ("MAX B2B", stats.max_b2b.to_string()),
+    ]
+  }
+
+  // How the game is going so far, right of the next queue. Versus has no room
+  // for it, so it is only drawn for a single board.
+  fn draw_stats_panel(&self, d: &mut RaylibDrawHandle) {
+    let font_size = self.block_length as i32 * 3 / 4;
+    let rows = Self::stats_rows(&self.playfield.stats);
+    for (id, (label, value)) in rows.iter().enumerate() {
+      let block = self.get_block_rectangle(18, Playfield::VISIBLE_HEIGHT as i8 + 1 + id as i8);
+      let right = block.x as i32 + 8 * self.block_length as i32;
+      d.draw_text(
+        label,
+        block.x as i32,
+        block.y as i32,
+        font_size,
+        Color::BLACK,
+      );
+      d.draw_text(
+        value,
+        right - d.measure_text(value, font_size),
+        block.y as i32,
+        font_size,
+        Color::DARKGRAY,
+      );
+    }
+  }
+
+  // Faults and pieces for each tetromino, right of the next queue. The left
+  // column is taken by the line clear messages, so they go under the stats panel.
+  fn draw_finesse_counts(&self, d: &mut RaylibDrawHandle) {
+    let font_size = self.block_length as i32;
+    let first_row = if self.visual.stats_panel {
+      Self::STATS_ROWS
+    } else {
+      0
+    };
+    let row = |id: usize| {
+      self.get_block_rectangle(18, Playfield::VISIBLE_HEIGHT as i8 + 1 + (first_row + id) as i8)
+    };
+    let header = row(0);
+    d.draw_text(
+      "FAULTS/PIECES",
+      header.x as i32,
+      header.y as i32,
+      font_size,
+      Color::BLACK,
+    );
+    for (id, (tetromino, count)) in Tetromino::ALL
+      .iter()
+      .zip(self.playfield.finesse.counts)
+      .enumerate()
+    {
+      let text = format!("{:?}  {}/{}", tetromino, count.faults, count.pieces);
+      let block = row(id + 1);
+      d.draw_text(
+        &text,
+        block.x as i32,
+        block.y as i32,
+        font_size,
+        Color::BLACK,
+      );
+    }
+  }
+
+  // The keys pressed for the falling piece against the fewest its target needs,
+  // flagged as soon as there are too many
+  fn draw_trainer_status(&self, d: &mut RaylibDrawHandle, trainer: &Trainer) {
+    let Some(target) = &trainer.target else {
+      return;
+    };
+    let presses = self.playfield.finesse.presses();
+    let (text, color) = if presses as usize > target.path.len() {
+      let path: Vec<_> = target.path.iter().map(|press| press.name()).collect();
+      let path = if path.is_empty() {
+        "just drop it".to_string()
+      } else {
+        path.join(", ")
+      };
+      (format!("FAULT  Fastest: {}", path), Color::RED)
+    } else {
+      (
+        format!("KEYS {}/{}", presses, target.path.len()),
+        Color::BLACK,
+      )
+    };
+    let status_block = self.get_block_rectangle(0, Playfield::VISIBLE_HEIGHT as i8 - 1);
+    d.draw_text(
+      &text,
+      status_block.x as i32,
+      status_block.y as i32,
+      self.block_length as i32,
+      color,
+    );
+  }
+
+  // A line over the tetrion
+  fn draw_status(&self, d: &mut RaylibDrawHandle, text: &str, color: Color) {
+    let status_block = self.get_block_rectangle(0, Playfield::VISIBLE_HEIGHT as i8 - 2);
+    d.draw_text(
+      text,
+      status_block.x as i32,
+      status_block.y as i32,
+      self.block_length as i32,
+      color,
+    );
+  }
+
   fn draw_tetrion(&self, d: &mut RaylibDrawHandle) {
     let tetrion = Rectangle::new(
       self.position.x,
@@ -352,6 +1364,45 @@ impl Game {
     );
     d.draw_rectangle_rec(tetrion, Color::BLACK);
 
+    if self.visual.grid_lines {
+      self.draw_grid_lines(d);
+    }
+
+    if let Some(warning) = self.playfield.garbage_warning() {
+      self.draw_garbage_meter(d, warning);
+    }
+
+    for j in 0..Playfield::HEIGHT {
+      for i in 0..Playfield::WIDTH {
+        self.draw_rectangle_pretty(
+          d,
+          self.get_block_rectangle(i as i8, j as i8),
+          self.playfield.grid[j][i].get_tetromino_color(),
+          Color::BLACK,
+        );
+      }
+    }
+  }
+
+  // A bar along the left of the tetrion that fills up until the next row of garbage
+  // rises, turning red when it is about to
+  fn draw_garbage_meter(&self, d: &mut RaylibDrawHandle, warning: f32) {
+    let height = self.block_length * Playfield::VISIBLE_HEIGHT as f32;
+    let width = self.block_length / 2.0;
+    let background = Rectangle::new(self.position.x - width, self.position.y, width, height);
+    d.draw_rectangle_rec(background, Color::DARKGRAY);
+
+    let filled = height * warning.clamp(0.0, 1.0);
+    let meter = Rectangle::new(background.x, background.y + height - filled, width, filled);
+    let color = if warning >= 0.75 {
+      Color::RED
+    } else {
+      Color::ORANGE
+    };
+    d.draw_rectangle_rec(meter, color);
+  }
+
+  fn draw_grid_lines(&self, d: &mut RaylibDrawHandle) {
     for i in 1..Playfield::WIDTH as i8 {
       let rec = self.get_block_rectangle(i, Playfield::VISIBLE_HEIGHT as i8);
       d.draw_line_ex(
@@ -383,17 +1434,6 @@ impl Game {
         Color::DARKGRAY,
       );
     }
-
-    for j in 0..Playfield::HEIGHT {
-      for i in 0..Playfield::WIDTH {
-        self.draw_rectangle_pretty(
-          d,
-          self.get_block_rectangle(i as i8, j as i8),
-          self.playfield.grid[j][i].get_tetromino_color(),
-          Color::BLACK,
-        );
-      }
-    }
   }
 
   fn draw_piece(
@@ -404,8 +1444,8 @@ impl Game {
     offset: (i8, i8),
   ) {
     for coordinates in map {
-      let i = coordinates.0 as i8 + offset.0;
-      let j = coordinates.1 as i8 + offset.1;
+      let i = coordinates.0 + offset.0;
+      let j = coordinates.1 + offset.1;
       self.draw_rectangle_pretty(d, self.get_block_rectangle(i, j), color, Color::BLACK);
     }
   }
@@ -0,0 +1,461 @@
+use super::{
+  playfield::{
+    attack::lines_sent,
+    falling_piece::{FallingPiece, Tetromino},
+    input::{Action, InputFrame},
+    moves::Placement,
+    scoring::LineClear,
+    MessageType, Playfield,
+  },
+  GameMode, Rules,
+};
+use std::{
+  collections::{HashMap, VecDeque},
+  fmt,
+  ops::Range,
+};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Difficulty {
+  Easy,
+  Medium,
+  Hard,
+}
+
+impl Difficulty {
+  pub fn next(&self) -> Self {
+    match self {
+      Self::Easy => Self::Medium,
+      Self::Medium => Self::Hard,
+      Self::Hard => Self::Easy,
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Self::Easy => "Easy",
+      Self::Medium => "Medium",
+      Self::Hard => "Hard",
+    }
+  }
+}
+
+// How much the bot cares about each thing it looks at. Positive weights are
+// wanted, negative ones avoided. Heights and depths are in rows.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Weights {
+  pub height: f32,
+  // Every row of the stack above the middle of the field, on top of `height`
+  pub danger: f32,
+  pub holes: f32,
+  pub bumpiness: f32,
+  // The deepest well, up to the four rows a tetris needs
+  pub well: f32,
+  // Slots a T can spin into to clear two lines
+  pub t_slots: f32,
+  pub b2b: f32,
+  pub combo: f32,
+  // Lines a placement sends, as they would be sent in versus
+  pub attack: f32,
+  pub lines: f32,
+}
+
+impl Weights {
+  pub fn new() -> Self {
+    Self {
+      height: -0.3,
+      danger: -2.0,
+      holes: -5.0,
+      bumpiness: -0.6,
+      well: 0.8,
+      t_slots: 2.5,
+      b2b: 2.0,
+      combo: 0.5,
+      attack: 2.0,
+      lines: -0.5,
+    }
+  }
+}
+
+// The board as the evaluation sees it
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Features {
+  height: usize,
+  holes: usize,
+  bumpiness: usize,
+  well: usize,
+  t_slots: usize,
+}
+
+impl Features {
+  const MAX_WELL: usize = 4;
+
+  fn new(grid: &[[Tetromino; Playfield::WIDTH]; Playfield::HEIGHT]) -> Self {
+    const WIDTH: usize = Playfield::WIDTH;
+    const HEIGHT: usize = Playfield::HEIGHT;
+    let filled = |i: usize, j: usize| grid[j][i] != Tetromino::Empty;
+
+    let heights: [usize; WIDTH] = std::array::from_fn(|i| {
+      (0..HEIGHT)
+        .find(|&j| filled(i, j))
+        .map_or(0, |j| HEIGHT - j)
+    });
+    let holes = (0..WIDTH)
+      .map(|i| {
+        (HEIGHT - heights[i]..HEIGHT)
+          .filter(|&j| !filled(i, j))
+          .count()
+      })
+      .sum();
+
+    // The walls count as high as the field, so a column next to one can be a well
+    let depth = |i: usize| {
+      let left = if i == 0 { HEIGHT } else { heights[i - 1] };
+      let right = heights.get(i + 1).copied().unwrap_or(HEIGHT);
+      left.min(right).saturating_sub(heights[i])
+    };
+    let well_column = (0..WIDTH).max_by_key(|&i| depth(i)).unwrap();
+    let well = depth(well_column).min(Self::MAX_WELL);
+    // The sides of the well are left out, or digging it would count against it
+    let bumpiness = heights
+      .windows(2)
+      .enumerate()
+      .filter(|&(i, _)| well == 0 || (i != well_column && i + 1 != well_column))
+      .map(|(_, pair)| pair[0].abs_diff(pair[1]))
+      .sum();
+
+    // A T pointing down fits with its flat side on row j and its stem on row j + 1,
+    // under an overhang on at least one side. The stem's row has to clear with it.
+    let is_t_slot = |i: usize, j: usize| {
+      !filled(i - 1, j)
+        && !filled(i, j)
+        && !filled(i + 1, j)
+        && !filled(i, j + 1)
+        && filled(i - 1, j + 1)
+        && filled(i + 1, j + 1)
+        && (filled(i - 1, j - 1) || filled(i + 1, j - 1))
+        && (0..WIDTH).all(|k| k == i || filled(k, j + 1))
+    };
+    let t_slots = (1..WIDTH - 1)
+      .flat_map(|i| (1..HEIGHT - 1).map(move |j| (i, j)))
+      .filter(|&(i, j)| is_t_slot(i, j))
+      .count();
+
+    Self {
+      height: heights.into_iter().max().unwrap(),
+      holes,
+      bumpiness,
+      well,
+      t_slots,
+    }
+  }
+}
+
+// A line of play the search is following
+struct Node {
+  playfield: Playfield,
+  // The first placement, the only one the bot will play
+  placement: Option<Placement>,
+  reward: f32,
+  value: f32,
+}
+
+// Plays a playfield on its own, a piece at a time. It searches the placements
+// of the current piece and the ones in the preview, then plays the inputs of the
+// best one, as if they came from a player.
+#[derive(Clone)]
+pub struct Bot {
+  difficulty: Difficulty,
+  weights: Weights,
+  // Pieces looked ahead, and lines of play kept at each of them
+  depth: usize,
+  beam_width: usize,
+  // Frames spent before moving a piece, and after every input
+  think_frames: usize,
+  input_frames: usize,
+  plan: VecDeque<Action>,
+  // Where the plan drops the piece, and frames left until its next input
+  target: Option<FallingPiece>,
+  wait: usize,
+  is_replanned: bool,
+  pieces: u32,
+}
+
+impl Bot {
+  pub fn new(difficulty: Difficulty) -> Self {
+    Self::with_weights(difficulty, Weights::new())
+  }
+
+  pub fn with_weights(difficulty: Difficulty, weights: Weights) -> Self {
+    let (depth, beam_width, think_frames, input_frames) = match difficulty {
+      Difficulty::Easy => (1, 1, 30, 6),
+      Difficulty::Medium => (2, 4, 10, 3),
+      Difficulty::Hard => (3, 8, 0, 1),
+    };
+    Self {
+      difficulty,
+      weights,
+      depth,
+      beam_width,
+      think_frames,
+      input_frames,
+      plan: VecDeque::new(),
+      target: None,
+      wait: 0,
+      is_replanned: false,
+      pieces: 0,
+    }
+  }
+
+  pub fn difficulty(&self) -> Difficulty {
+    self.difficulty
+  }
+
+  // The input for the next frame. A new plan is made whenever a piece locks,
+  // after thinking about it for a while. Each input is a tap but soft drop, which
+  // is held until the piece lands.
+  pub fn poll(&mut self, playfield: &Playfield) -> InputFrame {
+    let mut input = InputFrame::new();
+    if playfield.falling_piece.tetromino == Tetromino::Empty
+      || playfield.has_lost
+      || playfield.is_finished
+    {
+      return input;
+    }
+    if playfield.stats.pieces != self.pieces {
+      self.plan.clear();
+      self.pieces = playfield.stats.pieces;
+      self.wait = self.think_frames;
+      self.is_replanned = false;
+    }
+    if self.wait > 0 {
+      self.wait -= 1;
+      return input;
+    }
+
+    // Gravity may have taken the piece somewhere else than the plan expected.
+    // It gets one more plan from where it is, then it's dropped where it stands.
+    let ghost_piece = playfield.get_ghost_piece();
+    if self.plan.front() == Some(&Action::HardDrop) && Some(ghost_piece) != self.target {
+      self.plan.clear();
+      if self.is_replanned {
+        self.plan.push_back(Action::HardDrop);
+        self.target = Some(ghost_piece);
+      }
+      self.is_replanned = true;
+    }
+    if self.plan.is_empty() {
+      if let Some(placement) = self.search(playfield) {
+        self.plan = placement.inputs.into();
+        self.target = Some(placement.piece);
+      }
+    }
+
+    while self.plan.front() == Some(&Action::SoftDrop) && playfield.falling_piece == ghost_piece {
+      self.plan.pop_front();
+    }
+    match self.plan.front().copied() {
+      Some(Action::SoftDrop) => input.hold(Action::SoftDrop),
+      Some(action) => {
+        input.press(action);
+        self.plan.pop_front();
+        self.wait = self.input_frames;
+      }
+      None => {}
+    }
+    input
+  }
+
+  // Beam search: every placement of the current piece, then of each piece after
+  // it from the best boards so far. It never looks further than the preview, so
+  // it doesn't know anything a player wouldn't.
+  fn search(&self, playfield: &Playfield) -> Option<Placement> {
+    let mut beam = vec![Node {
+      playfield: playfield.clone(),
+      placement: None,
+      reward: 0.0,
+      value: 0.0,
+    }];
+    for _ in 0..self.depth {
+      let mut children: Vec<Node> = Vec::new();
+      let mut boards: HashMap<_, usize> = HashMap::new();
+      for node in &beam {
+        for placement in node.playfield.placements() {
+          let mut child = node.playfield.clone();
+          child.place(&placement);
+          let reward = node.reward + self.reward(&node.playfield, &placement, &child);
+          let value = if child.has_lost {
+            f32::NEG_INFINITY
+          } else {
+            reward + self.evaluate(&child)
+          };
+          let child = Node {
+            placement: node.placement.clone().or(Some(placement)),
+            playfield: child,
+            reward,
+            value,
+          };
+
+          // Boards reached in different ways are only worth following once
+          match boards.get(&board_key(&child.playfield)) {
+            Some(&id) if children[id].value >= child.value => {}
+            Some(&id) => children[id] = child,
+            None => {
+              boards.insert(board_key(&child.playfield), children.len());
+              children.push(child);
+            }
+          }
+        }
+      }
+      if children.is_empty() {
+        break;
+      }
+
+      children.sort_by(|a, b| b.value.total_cmp(&a.value));
+      children.truncate(self.beam_width);
+      beam = children;
+    }
+
+    beam.swap_remove(0).placement
+  }
+
+  // What a placement earned right away
+  fn reward(&self, before: &Playfield, placement: &Placement, after: &Playfield) -> f32 {
+    let lines = (after.stats.lines - before.stats.lines) as usize;
+    let clear = LineClear {
+      lines,
+      spin_type: placement.spin_type,
+      is_all_clear: lines > 0 && matches!(after.message.message, MessageType::AllClear),
+    };
+    let attack = lines_sent(clear, after.combo, after.b2b);
+    self.weights.attack * attack as f32 + self.weights.lines * lines as f32
+  }
+
+  // How good a board is to keep playing on
+  fn evaluate(&self, playfield: &Playfield) -> f32 {
+    let features = Features::new(&playfield.grid);
+    let weights = &self.weights;
+    let danger = features
+      .height
+      .saturating_sub(Playfield::VISIBLE_HEIGHT / 2);
+    weights.height * features.height as f32
+      + weights.danger * danger as f32
+      + weights.holes * features.holes as f32
+      + weights.bumpiness * features.bumpiness as f32
+      + weights.well * features.well as f32
+      + weights.t_slots * features.t_slots as f32
+      + weights.b2b * playfield.b2b.min(1) as f32
+      + weights.combo * playfield.combo as f32
+  }
+
+  // Plays a game on each seed until it has placed `pieces` pieces or topped out.
+  // The games are versus ones, so the attack can be counted.
+  pub fn benchmark(&self, seeds: Range<u64>, pieces: u32) -> Benchmark {
+    let mut benchmark = Benchmark::default();
+    for seed in seeds {
+      let mut bot = Self::with_weights(self.difficulty, self.weights);
+      let mut playfield = Playfield::new(Rules {
+        mode: GameMode::Versus,
+        ..Rules::new(seed)
+      });
+      while playfield.stats.pieces < pieces && !playfield.has_lost {
+        playfield.update(bot.poll(&playfield));
+        benchmark.attack += playfield.take_outgoing();
+      }
+
+      benchmark.games += 1;
+      benchmark.pieces += playfield.stats.pieces;
+      benchmark.lines += playfield.stats.lines;
+      benchmark.top_outs += playfield.has_lost as u32;
+    }
+    benchmark
+  }
+}
+
+// The filled cells and the pieces to come, which is all that matters to what
+// can be done on a board
+fn board_key(playfield: &Playfield) -> ([u16; Playfield::HEIGHT], u8, u8, bool, u8) {
+  let rows = playfield.grid.map(|row| {
+    row
+      .iter()
+      .enumerate()
+      .filter(|(_, &mino)| mino != Tetromino::Empty)
+      .fold(0, |bits, (i, _)| bits | 1 << i)
+  });
+  (
+    rows,
+    playfield.falling_piece.tetromino as u8,
+    playfield.holding_piece as u8,
+    playfield.b2b > 0,
+    playfield.combo,
+  )
+}
+
+// How a bot did over a few games
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Benchmark {
+  pub games: u32,
+  pub pieces: u32,
+  pub lines: u32,
+  pub attack: u32,
+  pub top_outs: u32,
+}
+
+impl fmt::Display for Benchmark {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let per_piece = |value: u32| value as f32 / self.pieces.max(1) as f32;
+    write!(
+      f,
+      "{} games, {} pieces: {} lines ({:.2} per piece), {} attack ({:.2} per piece), {} top outs",
+      self.games,
+      self.pieces,
+      self.lines,
+      per_piece(self.lines),
+      self.attack,
+      per_piece(self.attack),
+      self.top_outs
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn features_find_holes_wells_and_t_slots() {
+    let mut playfield = Playfield::scripted("T", GameMode::Endless);
+    playfield.fill(&["##.#######", "#...######", "##........"]);
+    let features = Features::new(&playfield.grid);
+    assert_eq!(features.height, 3);
+    assert_eq!(features.holes, 1);
+    assert_eq!(features.t_slots, 1);
+
+    let mut playfield = Playfield::scripted("I", GameMode::Endless);
+    playfield.fill(&["#########."; 4]);
+    let features = Features::new(&playfield.grid);
+    assert_eq!(
+      (features.holes, features.well, features.bumpiness),
+      (0, 4, 0)
+    );
+  }
+
+  #[test]
+  fn bot_takes_the_tetris() {
+    let mut playfield = Playfield::scripted("IO", GameMode::Endless);
+    playfield.fill(&["#########."; 4]);
+    let mut bot = Bot::new(Difficulty::Hard);
+    while playfield.stats.pieces == 0 {
+      playfield.update(bot.poll(&playfield));
+    }
+    assert_eq!(playfield.stats.lines, 4);
+  }
+
+  #[test]
+  fn bot_survives_and_clears_lines() {
+    let benchmark = Bot::new(Difficulty::Medium).benchmark(0..2, 100);
+    assert_eq!(benchmark.top_outs, 0, "{}", benchmark);
+    assert_eq!(benchmark.pieces, 200);
+    assert!(benchmark.lines >= 60, "{}", benchmark);
+  }
+}
@@ -0,0 +1,272 @@
+mod protocol;
+mod rollback;
+mod spectate;
+
+use self::rollback::Rollback;
+pub use self::spectate::{Broadcast, Spectator};
+use super::{
+  playfield::input::Action,
+  versus::{draw_match, side_by_side},
+  Game, Rules,
+};
+use crate::raytris::{controls::Control, settings::Settings};
+use raylib::prelude::*;
+use std::{
+  fmt, io,
+  net::{TcpListener, TcpStream, ToSocketAddrs},
+  time::Duration,
+};
+
+#[derive(Debug)]
+pub enum NetplayError {
+  Io(io::Error),
+  Version(u8),
+  Protocol,
+  Timeout,
+  Disconnected,
+  Left,
+  Desync(u32),
+}
+
+impl fmt::Display for NetplayError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Io(error) => write!(f, "{}", error),
+      Self::Version(version) => write!(
+        f,
+        "the other game speaks version {} of the protocol, this one {}",
+        version,
+        protocol::VERSION
+      ),
+      Self::Protocol => write!(f, "the other game sent something unexpected"),
+      Self::Timeout => write!(f, "the other player stopped responding"),
+      Self::Disconnected => write!(f, "the connection was lost"),
+      Self::Left => write!(f, "the other player left"),
+      Self::Desync(frame) => write!(f, "the games went out of sync on frame {}", frame),
+    }
+  }
+}
+
+impl From<io::Error> for NetplayError {
+  fn from(error: io::Error) -> Self {
+    match error.kind() {
+      io::ErrorKind::UnexpectedEof
+      | io::ErrorKind::ConnectionReset
+      | io::ErrorKind::ConnectionAborted
+      | io::ErrorKind::BrokenPipe => Self::Disconnected,
+      _ => Self::Io(error),
+    }
+  }
+}
+
+// Versus against a player on another machine. The local player is on the left.
+pub struct Netplay {
+  games: [Game; 2],
+  rollback: Rollback,
+  error: Option<NetplayError>,
+  is_leaving: bool,
+}
+
+impl Netplay {
+  pub const DEFAULT_PORT: u16 = 7777;
+
+  // Waits for someone to join, unless the quit key is pressed first
+  pub fn host(
+    rl: &mut RaylibHandle,
+    thread: &RaylibThread,
+    rules: Rules,
+    settings: &Settings,
+  ) -> Result<Option<Self>, NetplayError> {
+    let listener = TcpListener::bind(("0.0.0.0", Self::DEFAULT_PORT))?;
+    listener.set_nonblocking(true)?;
+    let stream = loop {
+      if settings.keymap.is_pressed(rl, Control::Quit) {
+        return Ok(None);
+      }
+      match listener.accept() {
+        Ok((stream, _)) => break stream,
+        Err(error) if error.kind() == io::ErrorKind::WouldBlock => {}
+        Err(error) => return Err(error.into()),
+      }
+
+      let waiting = format!("Waiting for a player on port {}", Self::DEFAULT_PORT);
+      let help = format!("Press {} to cancel", settings.keymap.hint(Control::Quit));
+      draw_message(rl, thread, &waiting, &help);
+    };
+
+    stream.set_nonblocking(false)?;
+    let (rollback, rules) = Rollback::host(stream, rules, settings.input_delay)?;
+    Ok(Some(Self::new(rl, rollback, rules, settings)))
+  }
+
+  pub fn join(
+    rl: &mut RaylibHandle,
+    thread: &RaylibThread,
+    address: &str,
+    settings: &Settings,
+  ) -> Result<Self, NetplayError> {
+    draw_message(rl, thread, &format!("Connecting to {}", address), "");
+    let stream = connect(address, Self::DEFAULT_PORT)?;
+    let (rollback, rules) = Rollback::join(stream, settings.input_delay, settings.handling)?;
+    Ok(Self::new(rl, rollback, rules, settings))
+  }
+
+  // The local player's rules go first
+  fn new(rl: &RaylibHandle, rollback: Rollback, rules: [Rules; 2], settings: &Settings) -> Self {
+    Self {
+      games: side_by_side(rl, rules, settings),
+      rollback,
+      error: None,
+      is_leaving: false,
+    }
+  }
+
+  pub fn run(&mut self, rl: &mut RaylibHandle, thread: &RaylibThread) {
+    while !self.games[0]
+      .keyboard_input
+      .keymap
+      .is_pressed(rl, Control::Quit)
+      || !self.can_quit()
+    {
+      self.update(rl);
+      self.draw(rl, thread);
+    }
+
+    self.rollback.leave();
+    let _d = rl.begin_drawing(thread);
+  }
+
+  // The other player can't be paused, so pausing only asks whether to leave
+  fn can_quit(&self) -> bool {
+    self.is_leaving || self.error.is_some() || self.result().is_some()
+  }
+
+  // Someone may only seem to top out on a wrong guess, so the result waits for
+  // the real inputs
+  fn result(&self) -> Option<&'static str> {
+    if !self.rollback.is_settled() {
+      return None;
+    }
+    match [0, 1].map(|id| self.games[id].playfield.has_lost) {
+      [false, false] => None,
+      [false, true] => Some("YOU WIN"),
+      [true, false] => Some("YOU LOSE"),
+      [true, true] => Some("DRAW"),
+    }
+  }
+
+  fn update(&mut self, rl: &RaylibHandle) {
+    if self.games[0]
+      .keyboard_input
+      .keymap
+      .is_pressed(rl, Control::Pause)
+    {
+      self.is_leaving = !self.is_leaving;
+    }
+    if self.error.is_some() || self.result().is_some() {
+      return;
+    }
+
+    let mut input = self.games[0].keyboard_input.poll(rl);
+    input.release(Action::Restart);
+    let [local, remote] = &mut self.games;
+    let result = self.rollback.push_local(input).and_then(|()| {
+      self
+        .rollback
+        .advance([&mut local.playfield, &mut remote.playfield])
+    });
+    if let Err(error) = result {
+      self.error = Some(error);
+    }
+  }
+
+  fn draw(&self, rl: &mut RaylibHandle, thread: &RaylibThread) {
+    let mut d = rl.begin_drawing(thread);
+    let keymap = &self.games[0].keyboard_input.keymap;
+    let quit_text = format!("Press {} to quit", keymap.hint(Control::Quit));
+    let (title, text) = match (&self.error, self.result()) {
+      (Some(error @ NetplayError::Desync(_)), _) => {
+        ("OUT OF SYNC", format!("{}. {}", error, quit_text))
+      }
+      (Some(error), _) => ("CONNECTION LOST", format!("{}. {}", error, quit_text)),
+      (None, Some(result)) => (result, quit_text),
+      (None, None) if self.is_leaving => (
+        "LEAVE MATCH?",
+        format!(
+          "Press {} to leave, {} to keep playing",
+          keymap.hint(Control::Quit),
+          keymap.hint(Control::Pause)
+        ),
+      ),
+      (None, None) if self.rollback.is_stalled() => {
+        ("WAITING", "The other player's inputs are late".to_string())
+      }
+      (None, None) => ("", String::new()),
+    };
+    let labels = [String::from("YOU"), String::from("OPPONENT")];
+    draw_match(&mut d, &self.games, &labels, title, &text);
+    self.draw_stats(&mut d);
+  }
+
+  fn draw_stats(&self, d: &mut RaylibDrawHandle) {
+    let stats = self.rollback.stats();
+    let ping = match stats.ping {
+      Some(ping) => format!("{} ms", ping.as_millis()),
+      None => "-".to_string(),
+    };
+    let text = format!(
+      "Ping: {}  Rollback: {} frames  Ahead: {} frames  Delay: {} frames",
+      ping,
+      stats.rollback_frames,
+      stats.frames_ahead,
+      self.rollback.input_delay()
+    );
+    let font_size = self.games[0].block_length as i32;
+    d.draw_text(
+      &text,
+      font_size / 2,
+      font_size / 2,
+      font_size,
+      Color::DARKGRAY,
+    );
+  }
+}
+
+// The port can be left out of the address for the default one
+fn connect(address: &str, default_port: u16) -> Result<TcpStream, NetplayError> {
+  const TIMEOUT: Duration = Duration::from_secs(5);
+
+  let address = if address.contains(':') {
+    address.to_string()
+  } else {
+    format!("{}:{}", address, default_port)
+  };
+  let address = address
+    .to_socket_addrs()?
+    .next()
+    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the address could not be found"))?;
+  Ok(TcpStream::connect_timeout(&address, TIMEOUT)?)
+}
+
+fn draw_message(rl: &mut RaylibHandle, thread: &RaylibThread, message: &str, help: &str) {
+  let screen_width = rl.get_screen_width();
+  let screen_height = rl.get_screen_height();
+  let font_size = screen_height / 20;
+  let mut d = rl.begin_drawing(thread);
+
+  d.clear_background(Color::LIGHTGRAY);
+  d.draw_text(
+    message,
+    (screen_width - d.measure_text(message, font_size)) / 2,
+    screen_height / 2 - font_size,
+    font_size,
+    Color::BLACK,
+  );
+  d.draw_text(
+    help,
+    (screen_width - d.measure_text(help, font_size)) / 2,
+    screen_height / 2 + font_size,
+    font_size,
+    Color::BLACK,
+  );
+}
@@ -0,0 +1,253 @@
+use super::NetplayError;
+use crate::raytris::game::{playfield::input::InputFrame, Replay, Rules};
+use std::{
+  io::{self, Read, Write},
+  net::TcpStream,
+  sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError},
+  thread,
+  time::Duration,
+};
+
+pub const VERSION: u8 = 1;
+
+// Everything the two players, or a broadcast and its spectators, tell each other.
+// On the wire every message is its length as two little endian bytes, then a tag
+// byte and the fields. Rules are the only message that can grow, and scripted
+// sequences are kept short enough for them to fit.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Message {
+  Hello { version: u8, nonce: u64 },
+  Rules(Rules),
+  Input { frame: u32, input: InputFrame },
+  Garbage { frame: u32, lines: u32 },
+  Hash { frame: u32, checksum: u64 },
+  Ping { id: u32 },
+  Pong { id: u32 },
+  Undo { len: u32 },
+  Quit,
+}
+
+impl Message {
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut bytes = vec![0, 0];
+    match self {
+      Self::Hello { version, nonce } => {
+        bytes.extend([0, *version]);
+        bytes.extend(nonce.to_le_bytes());
+      }
+      // The rules go the way replays store them
+      Self::Rules(rules) => {
+        bytes.push(1);
+        bytes.extend(Replay::new(rules.clone()).to_bytes());
+      }
+      Self::Input { frame, input } => {
+        bytes.push(2);
+        bytes.extend(frame.to_le_bytes());
+        bytes.extend(input.bits().to_le_bytes());
+      }
+      Self::Garbage { frame, lines } => {
+        bytes.push(3);
+        bytes.extend(frame.to_le_bytes());
+        bytes.extend(lines.to_le_bytes());
+      }
+      Self::Hash { frame, checksum } => {
+        bytes.push(4);
+        bytes.extend(frame.to_le_bytes());
+        bytes.extend(checksum.to_le_bytes());
+      }
+      Self::Quit => bytes.push(5),
+      Self::Ping { id } => {
+        bytes.push(6);
+        bytes.extend(id.to_le_bytes());
+      }
+      Self::Pong { id } => {
+        bytes.push(7);
+        bytes.extend(id.to_le_bytes());
+      }
+      Self::Undo { len } => {
+        bytes.push(8);
+        bytes.extend(len.to_le_bytes());
+      }
+    }
+    let len = u16::try_from(bytes.len() - 2).expect("messages fit in a u16 length");
+    bytes[..2].copy_from_slice(&len.to_le_bytes());
+    bytes
+  }
+
+  // Takes the message without its length
+  fn from_bytes(bytes: &[u8]) -> Result<Self, NetplayError> {
+    let (&tag, fields) = bytes.split_first().ok_or(NetplayError::Protocol)?;
+    let u32_at = |at: usize| -> Result<u32, NetplayError> {
+      let field = fields.get(at..at + 4).ok_or(NetplayError::Protocol)?;
+      Ok(u32::from_le_bytes(field.try_into().unwrap()))
+    };
+    let u64_at = |at: usize| -> Result<u64, NetplayError> {
+      let field = fields.get(at..at + 8).ok_or(NetplayError::Protocol)?;
+      Ok(u64::from_le_bytes(field.try_into().unwrap()))
+    };
+
+    let (message, len) = match tag {
+      0 => (
+        Self::Hello {
+          version: *fields.first().ok_or(NetplayError::Protocol)?,
+          nonce: u64_at(1)?,
+        },
+        9,
+      ),
+      1 => {
+        let replay = Replay::from_bytes(fields).map_err(|_| NetplayError::Protocol)?;
+        (Self::Rules(replay.rules), fields.len())
+      }
+      2 => (
+        Self::Input {
+          frame: u32_at(0)?,
+          input: InputFrame::from_bits(u32_at(4)?),
+        },
+        8,
+      ),
+      3 => (
+        Self::Garbage {
+          frame: u32_at(0)?,
+          lines: u32_at(4)?,
+        },
+        8,
+      ),
+      4 => (
+        Self::Hash {
+          frame: u32_at(0)?,
+          checksum: u64_at(4)?,
+        },
+        12,
+      ),
+      5 => (Self::Quit, 0),
+      6 => (Self::Ping { id: u32_at(0)? }, 4),
+      7 => (Self::Pong { id: u32_at(0)? }, 4),
+      8 => (Self::Undo { len: u32_at(0)? }, 4),
+      _ => return Err(NetplayError::Protocol),
+    };
+    if fields.len() != len {
+      return Err(NetplayError::Protocol);
+    }
+    Ok(message)
+  }
+}
+
+// A connection to the other player. Messages are read on a thread of their own,
+// so the game never blocks waiting on the network.
+pub struct Connection {
+  stream: TcpStream,
+  messages: Receiver<Result<Message, NetplayError>>,
+}
+
+impl Connection {
+  pub fn new(stream: TcpStream) -> io::Result<Self> {
+    stream.set_nodelay(true)?;
+    let mut reader = stream.try_clone()?;
+    let (sender, messages) = mpsc::channel();
+    thread::spawn(move || loop {
+      let message = read_message(&mut reader);
+      let is_error = message.is_err();
+      if sender.send(message).is_err() || is_error {
+        break;
+      }
+    });
+
+    Ok(Self { stream, messages })
+  }
+
+  pub fn send(&mut self, message: &Message) -> Result<(), NetplayError> {
+    self
+      .stream
+      .write_all(&message.to_bytes())
+      .map_err(NetplayError::from)
+  }
+
+  pub fn try_receive(&mut self) -> Result<Option<Message>, NetplayError> {
+    match self.messages.try_recv() {
+      Ok(message) => message.map(Some),
+      Err(TryRecvError::Empty) => Ok(None),
+      Err(TryRecvError::Disconnected) => Err(NetplayError::Disconnected),
+    }
+  }
+
+  pub fn receive(&mut self, timeout: Duration) -> Result<Message, NetplayError> {
+    match self.messages.recv_timeout(timeout) {
+      Ok(message) => message,
+      Err(RecvTimeoutError::Timeout) => Err(NetplayError::Timeout),
+      Err(RecvTimeoutError::Disconnected) => Err(NetplayError::Disconnected),
+    }
+  }
+}
+
+fn read_message(reader: &mut impl Read) -> Result<Message, NetplayError> {
+  let mut len = [0; 2];
+  reader.read_exact(&mut len)?;
+  let mut bytes = vec![0; u16::from_le_bytes(len) as usize];
+  reader.read_exact(&mut bytes)?;
+  Message::from_bytes(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::raytris::game::{
+    playfield::{input::Action, randomizer::RandomizerKind},
+    GameMode,
+  };
+
+  #[test]
+  fn messages_survive_a_round_trip() {
+    let mut input = InputFrame::new();
+    input.press(Action::HardDrop);
+    input.hold(Action::ShiftLeft);
+    let messages = [
+      Message::Hello {
+        version: 1,
+        nonce: u64::MAX - 3,
+      },
+      Message::Rules(Rules {
+        mode: GameMode::Versus,
+        ..Rules::new(42)
+      }),
+      Message::Input { frame: 9, input },
+      Message::Garbage {
+        frame: 70000,
+        lines: 4,
+      },
+      Message::Hash {
+        frame: 60,
+        checksum: 0xcbf29ce484222325,
+      },
+      Message::Ping { id: 3 },
+      Message::Pong { id: 3 },
+      Message::Undo { len: 1200 },
+      Message::Quit,
+    ];
+
+    let bytes: Vec<u8> = messages.iter().flat_map(Message::to_bytes).collect();
+    let mut reader = bytes.as_slice();
+    for message in messages {
+      assert_eq!(read_message(&mut reader).unwrap(), message);
+    }
+    assert!(matches!(
+      read_message(&mut reader),
+      Err(NetplayError::Disconnected)
+    ));
+    assert!(matches!(
+      Message::from_bytes(&[2, 0, 0]),
+      Err(NetplayError::Protocol)
+    ));
+  }
+
+  #[test]
+  fn the_longest_scripted_sequence_fits_in_a_message() {
+    let letters = "I".repeat(RandomizerKind::MAX_SCRIPTED_LEN);
+    let message = Message::Rules(Rules {
+      randomizer: RandomizerKind::scripted(&letters).unwrap(),
+      ..Rules::new(0)
+    });
+    let bytes = message.to_bytes();
+    assert_eq!(read_message(&mut bytes.as_slice()).unwrap(), message);
+    assert_eq!(RandomizerKind::scripted(&(letters + "I")), None);
+  }
+}
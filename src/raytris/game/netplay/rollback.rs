@@ -0,0 +1,478 @@
+use super::{
+  protocol::{self, Connection, Message},
+  NetplayError,
+};
+use crate::raytris::game::{
+  playfield::{handling::HandlingSettings, input::InputFrame, Playfield},
+  versus::update_both,
+  Rules,
+};
+use rand::Rng;
+use std::{
+  collections::VecDeque,
+  net::TcpStream,
+  time::{Duration, Instant},
+};
+
+// Both players simulate both playfields. Rather than wait for the other player's
+// inputs, each one guesses them, holding whatever they held last, and keeps going.
+// When the real inputs show a guess was wrong, the playfields go back to how they
+// were before that frame and every frame since is simulated again.
+pub struct Rollback {
+  connection: Connection,
+  input_delay: u32,
+  // Frames simulated, guessed ones included
+  frame: u32,
+  // Frames simulated on the real inputs of both players
+  confirmed: u32,
+  local_inputs: Vec<InputFrame>,
+  remote_inputs: Vec<InputFrame>,
+  // One for every frame from `confirmed` to `frame`
+  snapshots: VecDeque<Snapshot>,
+  garbage: Check<u32>,
+  hashes: Check<u64>,
+  pings: VecDeque<(u32, Instant)>,
+  last_ping: Instant,
+  last_message: Instant,
+  stats: NetworkStats,
+}
+
+// The playfields before a frame, and what was played on it
+struct Snapshot {
+  playfields: [Playfield; 2],
+  remote_input: InputFrame,
+  sent: [u32; 2],
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NetworkStats {
+  pub ping: Option<Duration>,
+  // Frames simulated again on the last rollback
+  pub rollback_frames: u32,
+  // Frames simulated on guesses, waiting on the other player's inputs
+  pub frames_ahead: u32,
+}
+
+// Results the other player reports, checked against the ones simulated here for
+// them. Either side may be ahead, so both are queued until the other catches up.
+struct Check<T> {
+  simulated: VecDeque<(u32, T)>,
+  reported: VecDeque<(u32, T)>,
+}
+
+impl<T: PartialEq> Check<T> {
+  fn new() -> Self {
+    Self {
+      simulated: VecDeque::new(),
+      reported: VecDeque::new(),
+    }
+  }
+
+  fn verify(&mut self) -> Result<(), NetplayError> {
+    while !self.simulated.is_empty() && !self.reported.is_empty() {
+      let simulated = self.simulated.pop_front().unwrap();
+      let reported = self.reported.pop_front().unwrap();
+      if simulated != reported {
+        return Err(NetplayError::Desync(simulated.0.min(reported.0)));
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Rollback {
+  // Guessing further ahead than this would make rollbacks too jarring
+  const MAX_ROLLBACK: u32 = 8;
+  const HASH_INTERVAL: u32 = 60;
+  const PING_INTERVAL: Duration = Duration::from_secs(1);
+  const TIMEOUT: Duration = Duration::from_secs(5);
+
+  // The seed mixes the host's seed with a number from each player. Each game
+  // sends its part before reading the other's, so as long as both follow the
+  // protocol neither player picks the seed alone. The host picks the rest of the
+  // rules, then each player brings their own handling. Both sessions give the
+  // rules of their own player first.
+  pub fn host(
+    stream: TcpStream,
+    mut rules: Rules,
+    input_delay: u8,
+  ) -> Result<(Self, [Rules; 2]), NetplayError> {
+    let mut rollback = Self::new(stream, input_delay)?;
+    let nonce = rollback.send_hello()?;
+    rollback.connection.send(&Message::Rules(rules.clone()))?;
+    rules.seed ^= nonce ^ rollback.receive_hello()?;
+    let handling = rollback.receive_handling()?;
+    let guest_rules = Rules {
+      handling,
+      ..rules.clone()
+    };
+    Ok((rollback, [rules, guest_rules]))
+  }
+
+  pub fn join(
+    stream: TcpStream,
+    input_delay: u8,
+    handling: HandlingSettings,
+  ) -> Result<(Self, [Rules; 2]), NetplayError> {
+    let mut rollback = Self::new(stream, input_delay)?;
+    let nonce = rollback.send_hello()?;
+    let host_nonce = rollback.receive_hello()?;
+    let Message::Rules(mut host_rules) = rollback.connection.receive(Self::TIMEOUT)? else {
+      return Err(NetplayError::Protocol);
+    };
+    host_rules.seed ^= host_nonce ^ nonce;
+    let rules = Rules {
+      handling,
+      ..host_rules.clone()
+    };
+    rollback.connection.send(&Message::Rules(rules.clone()))?;
+    Ok((rollback, [rules, host_rules]))
+  }
+
+  // Only the handling is taken from the rules the guest sends back
+  fn receive_handling(&mut self) -> Result<HandlingSettings, NetplayError> {
+    match self.connection.receive(Self::TIMEOUT)? {
+      Message::Rules(rules) => Ok(rules.handling),
+      _ => Err(NetplayError::Protocol),
+    }
+  }
+
+  fn new(stream: TcpStream, input_delay: u8) -> Result<Self, NetplayError> {
+    Ok(Self {
+      connection: Connection::new(stream)?,
+      input_delay: input_delay as u32,
+      frame: 0,
+      confirmed: 0,
+      local_inputs: Vec::new(),
+      remote_inputs: Vec::new(),
+      snapshots: VecDeque::new(),
+      garbage: Check::new(),
+      hashes: Check::new(),
+      pings: VecDeque::new(),
+      last_ping: Instant::now(),
+      last_message: Instant::now(),
+      stats: NetworkStats::default(),
+    })
+  }
+
+  // Tells the nonce of this player
+  fn send_hello(&mut self) -> Result<u64, NetplayError> {
+    let nonce = rand::thread_rng().gen();
+    self.connection.send(&Message::Hello {
+      version: protocol::VERSION,
+      nonce,
+    })?;
+    Ok(nonce)
+  }
+
+  // Tells the nonce of the other player
+  fn receive_hello(&mut self) -> Result<u64, NetplayError> {
+    match self.connection.receive(Self::TIMEOUT)? {
+      Message::Hello { version, .. } if version != protocol::VERSION => {
+        Err(NetplayError::Version(version))
+      }
+      Message::Hello { nonce, .. } => Ok(nonce),
+      _ => Err(NetplayError::Protocol),
+    }
+  }
+
+  pub fn stats(&self) -> NetworkStats {
+    self.stats
+  }
+
+  pub fn input_delay(&self) -> u32 {
+    self.input_delay
+  }
+
+  // Too far ahead of the other player to keep guessing
+  pub fn is_stalled(&self) -> bool {
+    self.frame - self.confirmed >= Self::MAX_ROLLBACK
+  }
+
+  // Every frame simulated so far was played on real inputs, so the playfields
+  // can't change any more, even if someone topped out
+  pub fn is_settled(&self) -> bool {
+    self.confirmed == self.frame
+  }
+
+  // Schedules the input of this frame to be played after the input delay. While
+  // the game waits on the other player, the input is dropped instead of piling up.
+  pub fn push_local(&mut self, input: InputFrame) -> Result<(), NetplayError> {
+    while (self.local_inputs.len() as u32) < self.input_delay {
+      self.schedule(InputFrame::new())?;
+    }
+    if self.local_inputs.len() as u32 > self.frame + self.input_delay {
+      return Ok(());
+    }
+    self.schedule(input)
+  }
+
+  fn schedule(&mut self, input: InputFrame) -> Result<(), NetplayError> {
+    let frame = self.local_inputs.len() as u32;
+    self.connection.send(&Message::Input { frame, input })?;
+    self.local_inputs.push(input);
+    Ok(())
+  }
+
+  // Rolls back if a guess was wrong, then runs the next frame. The local
+  // playfield goes first.
+  pub fn advance(&mut self, playfields: [&mut Playfield; 2]) -> Result<(), NetplayError> {
+    self.receive()?;
+    let [local, remote] = playfields;
+
+    let known = (self.remote_inputs.len() as u32).min(self.frame);
+    let wrong_guess = (self.confirmed..known).find(|&frame| {
+      self.snapshots[(frame - self.confirmed) as usize].remote_input
+        != self.remote_inputs[frame as usize]
+    });
+    if let Some(frame) = wrong_guess {
+      let frames = self.frame - frame;
+      let id = (frame - self.confirmed) as usize;
+      let [local_snapshot, remote_snapshot] = self.snapshots[id].playfields.clone();
+      *local = local_snapshot;
+      *remote = remote_snapshot;
+      self.snapshots.truncate(id);
+      self.frame = frame;
+      // Once someone tops out there are no inputs left to play the rest on
+      for _ in 0..frames {
+        if local.has_lost || remote.has_lost {
+          break;
+        }
+        self.step(local, remote);
+      }
+      self.stats.rollback_frames = frames;
+    }
+
+    let is_over = local.has_lost || remote.has_lost;
+    if !is_over && !self.is_stalled() && self.frame < self.local_inputs.len() as u32 {
+      self.step(local, remote);
+    }
+    self.confirm(local, remote)?;
+    self.stats.frames_ahead = self.frame - self.confirmed;
+
+    self.garbage.verify()?;
+    self.hashes.verify()?;
+    if self.last_ping.elapsed() > Self::PING_INTERVAL {
+      self.ping()?;
+    }
+    if self.last_message.elapsed() > Self::TIMEOUT {
+      return Err(NetplayError::Timeout);
+    }
+    Ok(())
+  }
+
+  // Snapshots the playfields, then runs a frame on the best inputs known for it
+  fn step(&mut self, local: &mut Playfield, remote: &mut Playfield) {
+    let frame = self.frame as usize;
+    let remote_input = match self.remote_inputs.get(frame) {
+      Some(&input) => input,
+      None => self
+        .remote_inputs
+        .last()
+        .map_or(InputFrame::new(), |input| input.held()),
+    };
+
+    let playfields = [local.clone(), remote.clone()];
+    let sent = update_both([local, remote], [self.local_inputs[frame], remote_input]);
+    self.snapshots.push_back(Snapshot {
+      playfields,
+      remote_input,
+      sent,
+    });
+    self.frame += 1;
+  }
+
+  // Frames played on real inputs are final. Only those get reported and checked.
+  fn confirm(&mut self, local: &Playfield, remote: &Playfield) -> Result<(), NetplayError> {
+    let known = (self.remote_inputs.len() as u32).min(self.frame);
+    while self.confirmed < known {
+      let frame = self.confirmed;
+      let snapshot = self.snapshots.pop_front().unwrap();
+      self.confirmed += 1;
+
+      if snapshot.sent[0] > 0 {
+        self.connection.send(&Message::Garbage {
+          frame,
+          lines: snapshot.sent[0],
+        })?;
+      }
+      if snapshot.sent[1] > 0 {
+        self.garbage.simulated.push_back((frame, snapshot.sent[1]));
+      }
+      if frame.is_multiple_of(Self::HASH_INTERVAL) {
+        let [local, remote] = match self.snapshots.front() {
+          Some(next) => [&next.playfields[0], &next.playfields[1]],
+          None => [local, remote],
+        };
+        self.connection.send(&Message::Hash {
+          frame,
+          checksum: local.state_checksum(),
+        })?;
+        self.hashes.simulated.push_back((frame, remote.state_checksum()));
+      }
+    }
+    Ok(())
+  }
+
+  fn ping(&mut self) -> Result<(), NetplayError> {
+    let id = self.pings.back().map_or(0, |&(id, _)| id.wrapping_add(1));
+    self.connection.send(&Message::Ping { id })?;
+    self.pings.push_back((id, Instant::now()));
+    self.last_ping = Instant::now();
+    Ok(())
+  }
+
+  fn receive(&mut self) -> Result<(), NetplayError> {
+    while let Some(message) = self.connection.try_receive()? {
+      self.last_message = Instant::now();
+      match message {
+        Message::Input { frame, input } => {
+          if frame != self.remote_inputs.len() as u32 {
+            return Err(NetplayError::Protocol);
+          }
+          self.remote_inputs.push(input);
+        }
+        Message::Garbage { frame, lines } => self.garbage.reported.push_back((frame, lines)),
+        Message::Hash { frame, checksum } => self.hashes.reported.push_back((frame, checksum)),
+        Message::Ping { id } => self.connection.send(&Message::Pong { id })?,
+        Message::Pong { id } => {
+          while let Some((ping, sent)) = self.pings.pop_front() {
+            if ping == id {
+              self.stats.ping = Some(sent.elapsed());
+              break;
+            }
+          }
+        }
+        Message::Quit => return Err(NetplayError::Left),
+        Message::Hello { .. } | Message::Rules(_) | Message::Undo { .. } => {
+          return Err(NetplayError::Protocol)
+        }
+      }
+    }
+    Ok(())
+  }
+
+  // Lets the other player know, rather than have them wait for the timeout
+  pub fn leave(&mut self) {
+    let _ = self.connection.send(&Message::Quit);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::raytris::game::{playfield::input::Action, GameMode};
+  use rand::SeedableRng;
+  use rand_chacha::ChaCha8Rng;
+  use std::{net::TcpListener, thread};
+
+  // The guest brings a longer DAS than the host. Gives the rules of the host first.
+  fn connect(input_delay: u8) -> (Rollback, Rollback, [Rules; 2]) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let mut handling = HandlingSettings::new();
+    handling.das = 12;
+    let joining = thread::spawn(move || {
+      Rollback::join(TcpStream::connect(address).unwrap(), input_delay, handling)
+    });
+
+    let rules = Rules {
+      mode: GameMode::Versus,
+      ..Rules::new(5)
+    };
+    let (host, host_rules) =
+      Rollback::host(listener.accept().unwrap().0, rules, input_delay).unwrap();
+    let (guest, [guest_rules, guest_host_rules]) = joining.join().unwrap().unwrap();
+    assert_eq!(host_rules, [guest_host_rules, guest_rules.clone()]);
+    assert_eq!(guest_rules.handling, handling);
+    assert_eq!(host_rules[0].handling, HandlingSettings::new());
+    assert_ne!(host_rules[0].seed, 5);
+    (host, guest, host_rules)
+  }
+
+  // Plays random inputs until the frames are confirmed, someone tops out or the
+  // session fails. Tells the most frames a rollback took.
+  fn play(
+    rollback: &mut Rollback,
+    rules: [Rules; 2],
+    seed: u64,
+    frames: u32,
+  ) -> Result<u32, NetplayError> {
+    let mut playfields = rules.map(Playfield::new);
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut max_rollback_frames = 0;
+    loop {
+      let is_over = playfields.iter().any(|playfield| playfield.has_lost);
+      if rollback.confirmed >= frames || is_over && rollback.is_settled() {
+        return Ok(max_rollback_frames);
+      }
+
+      let mut input = InputFrame::new();
+      if rng.gen_bool(0.03) {
+        input.press(Action::HardDrop);
+      }
+      if rng.gen_bool(0.1) {
+        input.press(if rng.gen() {
+          Action::ShiftLeft
+        } else {
+          Action::ShiftRight
+        });
+      }
+      rollback.push_local(input)?;
+      let [local, remote] = &mut playfields;
+      rollback.advance([local, remote])?;
+      max_rollback_frames = max_rollback_frames.max(rollback.stats().rollback_frames);
+      thread::sleep(Duration::from_millis(1));
+    }
+  }
+
+  #[test]
+  fn guesses_are_rolled_back_without_a_desync() {
+    let (mut host, mut guest, rules) = connect(0);
+    let [host_rules, guest_rules] = rules.clone();
+    let guest_rules = [guest_rules, host_rules];
+    // The guest starts late, so the host plays ahead on guesses from the start
+    // instead of only when the scheduler happens to delay a message
+    let guest = thread::spawn(move || {
+      thread::sleep(Duration::from_millis(50));
+      let result = play(&mut guest, guest_rules, 2, 600);
+      (guest, result)
+    });
+    let host_result = play(&mut host, rules, 1, 600);
+    let (_guest, guest_result) = guest.join().unwrap();
+
+    assert!(host_result.unwrap() > 0);
+    assert!(guest_result.is_ok());
+  }
+
+  #[test]
+  fn leaving_ends_the_session_of_the_other_player() {
+    let (mut host, mut guest, [host_rules, guest_rules]) = connect(2);
+    host.leave();
+    assert!(matches!(
+      play(&mut guest, [guest_rules, host_rules], 2, 600),
+      Err(NetplayError::Left)
+    ));
+  }
+
+  #[test]
+  fn diverging_playfields_are_a_desync() {
+    let (mut host, mut guest, rules) = connect(2);
+    let [host_rules, guest_rules] = rules.clone();
+    let guest = thread::spawn(move || {
+      let mut playfields = [Playfield::new(guest_rules), Playfield::new(host_rules)];
+      playfields[1].score = 1;
+      let [local, remote] = &mut playfields;
+      loop {
+        guest.push_local(InputFrame::new())?;
+        guest.advance([&mut *local, &mut *remote])?;
+        thread::sleep(Duration::from_millis(1));
+      }
+    });
+
+    let host_result = play(&mut host, rules, 1, 600);
+    let guest_result: Result<(), NetplayError> = guest.join().unwrap();
+    assert!(matches!(guest_result, Err(NetplayError::Desync(0))));
+    assert!(host_result.is_err());
+  }
+}
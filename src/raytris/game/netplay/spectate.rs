@@ -0,0 +1,234 @@
+use super::{
+  connect,
+  protocol::{self, Connection, Message},
+  Netplay, NetplayError,
+};
+use crate::raytris::game::{Replay, Rules};
+use std::{
+  io::{self, Write},
+  net::{TcpListener, TcpStream},
+  sync::mpsc::{self, Sender},
+  thread,
+  time::Duration,
+};
+
+// Streams a game to anyone watching it. Spectators get the rules first, then
+// every input the player made, so they can play the game along on their side.
+pub struct Broadcast {
+  listener: TcpListener,
+  viewers: Vec<Sender<Vec<u8>>>,
+  sent: usize,
+}
+
+impl Broadcast {
+  pub const PORT: u16 = Netplay::DEFAULT_PORT + 1;
+
+  pub fn new() -> io::Result<Self> {
+    Self::bind(Self::PORT)
+  }
+
+  fn bind(port: u16) -> io::Result<Self> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    listener.set_nonblocking(true)?;
+    Ok(Self {
+      listener,
+      viewers: Vec::new(),
+      sent: 0,
+    })
+  }
+
+  pub fn viewers(&self) -> usize {
+    self.viewers.len()
+  }
+
+  // Sends the frames played since the last update, and lets new spectators in.
+  // Frames that were undone get taken back first, so an undo needs an update of
+  // its own before anything is played over it.
+  pub fn update(&mut self, replay: &Replay) {
+    let mut bytes = Vec::new();
+    if replay.len() < self.sent {
+      self.sent = replay.len();
+      bytes.extend(
+        Message::Undo {
+          len: self.sent as u32,
+        }
+        .to_bytes(),
+      );
+    }
+    bytes.extend(frames(replay, self.sent));
+    self.sent = replay.len();
+    if !bytes.is_empty() {
+      self
+        .viewers
+        .retain(|viewer| viewer.send(bytes.clone()).is_ok());
+    }
+
+    while let Ok((stream, _)) = self.listener.accept() {
+      if let Ok(viewer) = Self::greet(stream, replay) {
+        self.viewers.push(viewer);
+      }
+    }
+  }
+
+  // Each spectator is written to on a thread of their own, so a slow one can't
+  // hold the game up
+  fn greet(mut stream: TcpStream, replay: &Replay) -> io::Result<Sender<Vec<u8>>> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    let mut bytes = Message::Hello {
+      version: protocol::VERSION,
+      nonce: 0,
+    }
+    .to_bytes();
+    bytes.extend(Message::Rules(replay.rules.clone()).to_bytes());
+    bytes.extend(frames(replay, 0));
+
+    let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+    sender.send(bytes).unwrap();
+    thread::spawn(move || {
+      for bytes in receiver {
+        if stream.write_all(&bytes).is_err() {
+          break;
+        }
+      }
+    });
+    Ok(sender)
+  }
+}
+
+impl Drop for Broadcast {
+  fn drop(&mut self) {
+    for viewer in &self.viewers {
+      let _ = viewer.send(Message::Quit.to_bytes());
+    }
+  }
+}
+
+fn frames(replay: &Replay, start: usize) -> Vec<u8> {
+  (start..replay.len())
+    .flat_map(|frame| {
+      Message::Input {
+        frame: frame as u32,
+        input: replay.frame(frame),
+      }
+      .to_bytes()
+    })
+    .collect()
+}
+
+// Watches a broadcast, putting the frames it sends in a replay
+pub struct Spectator {
+  connection: Connection,
+  is_live: bool,
+}
+
+impl Spectator {
+  // Frames the playback stays behind the broadcast, so it doesn't stutter
+  // whenever the network does
+  pub const DELAY: usize = 30;
+  const TIMEOUT: Duration = Duration::from_secs(5);
+
+  // The port can be left out of the address for the default one
+  pub fn connect(address: &str) -> Result<(Self, Rules), NetplayError> {
+    let mut connection = Connection::new(connect(address, Broadcast::PORT)?)?;
+    match connection.receive(Self::TIMEOUT)? {
+      Message::Hello { version, .. } if version != protocol::VERSION => {
+        return Err(NetplayError::Version(version))
+      }
+      Message::Hello { .. } => {}
+      _ => return Err(NetplayError::Protocol),
+    }
+    match connection.receive(Self::TIMEOUT)? {
+      Message::Rules(rules) => Ok((
+        Self {
+          connection,
+          is_live: true,
+        },
+        rules,
+      )),
+      _ => Err(NetplayError::Protocol),
+    }
+  }
+
+  // Once the broadcast ends, the replay has every frame there will be
+  pub fn is_live(&self) -> bool {
+    self.is_live
+  }
+
+  // Takes the frames that arrived since the last call. Tells the shortest length
+  // the replay was cut to, if the player undid something.
+  pub fn receive(&mut self, replay: &mut Replay) -> Option<usize> {
+    let mut undone = None;
+    while self.is_live {
+      match self.connection.try_receive() {
+        Ok(None) => break,
+        Ok(Some(Message::Input { frame, input })) if frame as usize == replay.len() => {
+          replay.push(input)
+        }
+        Ok(Some(Message::Undo { len })) if len as usize <= replay.len() => {
+          replay.truncate(len as usize);
+          undone = Some(replay.len().min(undone.unwrap_or(usize::MAX)));
+        }
+        _ => self.is_live = false,
+      }
+    }
+    undone
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::raytris::game::playfield::input::{Action, InputFrame};
+
+  fn push_frames(replay: &mut Replay, frames: usize) {
+    for frame in 0..frames {
+      let mut input = InputFrame::new();
+      if frame % 7 == 0 {
+        input.press(Action::HardDrop);
+      }
+      replay.push(input);
+    }
+  }
+
+  #[test]
+  fn spectators_follow_the_broadcast_through_undos() {
+    let mut broadcast = Broadcast::bind(0).unwrap();
+    let port = broadcast.listener.local_addr().unwrap().port();
+    let mut replay = Replay::new(Rules::new(7));
+    push_frames(&mut replay, 50);
+
+    let spectators: Vec<_> = (0..2)
+      .map(|_| {
+        thread::spawn(move || {
+          let (mut spectator, rules) = Spectator::connect(&format!("127.0.0.1:{}", port)).unwrap();
+          let mut replay = Replay::new(rules);
+          while spectator.is_live() {
+            spectator.receive(&mut replay);
+            thread::sleep(Duration::from_millis(1));
+          }
+          replay
+        })
+      })
+      .collect();
+
+    while broadcast.viewers() < 2 {
+      broadcast.update(&replay);
+      thread::sleep(Duration::from_millis(1));
+    }
+    push_frames(&mut replay, 20);
+    broadcast.update(&replay);
+    replay.truncate(40);
+    broadcast.update(&replay);
+    push_frames(&mut replay, 10);
+    broadcast.update(&replay);
+    drop(broadcast);
+
+    for spectator in spectators {
+      let watched = spectator.join().unwrap();
+      assert_eq!(watched.rules, replay.rules);
+      assert_eq!(watched.len(), replay.len());
+      assert!((0..replay.len()).all(|frame| watched.frame(frame) == replay.frame(frame)));
+    }
+  }
+}
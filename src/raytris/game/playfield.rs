@@ -1,13 +1,31 @@
+pub mod attack;
 pub mod falling_piece;
+pub mod finesse;
+pub mod garbage;
+pub mod gravity;
+pub mod handling;
+pub mod input;
+pub mod mode;
+pub mod moves;
 pub mod next_queue;
-
-use rand::Rng;
-use raylib::prelude::*;
+pub mod randomizer;
+pub mod rules;
+pub mod scoring;
+pub mod stats;
 
 use self::{
-  falling_piece::{FallingPiece, RotationType, Shift, Tetromino},
+  attack::lines_sent,
+  falling_piece::{CoordinatePair, FallingPiece, Orientation, RotationType, Shift, Tetromino},
+  finesse::{Finesse, Trainer},
+  garbage::Garbage,
+  input::{Action, InputFrame},
+  mode::GameMode,
   next_queue::NextQueue,
+  rules::Rules,
+  scoring::{LineClear, ScoreBreakdown},
+  stats::Stats,
 };
+use std::{collections::VecDeque, mem};
 
 #[derive(Clone, Copy, Debug)]
 pub enum MessageType {
@@ -19,8 +37,8 @@ pub enum MessageType {
   Empty,
 }
 
-#[derive(Clone, Copy, Debug)]
-enum SpinType {
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum SpinType {
   No,
   Proper,
   Mini,
@@ -62,15 +80,31 @@ pub struct Playfield {
   pub(super) holding_piece: Tetromino,
   pub(super) next_queue: NextQueue,
   pub(super) can_swap: bool,
-  frames_since_last_fall: u8,
+  fall_progress: f32,
   lock_delay_frames: u8,
   lock_delay_moves: u8,
   signed_frames_pressed: i32,
+  das_cut_frames: u8,
+  last_move_was_rotation: bool,
+  last_kick: usize,
   pub(super) combo: u8,
   pub(super) has_lost: bool,
   pub(super) score: u64,
+  pub(super) breakdown: ScoreBreakdown,
   pub(super) b2b: u16,
   pub(super) message: LineClearMessage,
+  pub(super) level: u32,
+  pub(super) is_finished: bool,
+  pub(super) garbage_cleared: u32,
+  garbage: Garbage,
+  garbage_risen: u32,
+  garbage_timer: u32,
+  pub(super) incoming: VecDeque<u32>,
+  outgoing: u32,
+  pub(super) finesse: Finesse,
+  pub(super) trainer: Option<Trainer>,
+  pub(super) stats: Stats,
+  rules: Rules,
 }
 
 impl Playfield {
@@ -79,152 +113,241 @@ impl Playfield {
   pub const VISIBLE_HEIGHT: usize = 20;
   const PIECE_SPAWN_POSITION: (i8, i8) =
     ((Self::WIDTH - 1) as i8 / 2, Self::VISIBLE_HEIGHT as i8 - 1);
-  const DAS: u8 = 7;
-  const SOFT_DROP_FRAMES: u8 = 1;
-  const GRAVITY_FRAMES: u8 = 20;
-  const MAX_LOCK_DELAY_FRAMES: u8 = 30;
-  const MAX_LOCK_DELAY_MOVES: u8 = 15;
+  const LINES_PER_LEVEL: u32 = 10;
+  const LAST_KICK: usize = 4;
+  const MAX_GARBAGE_PER_LOCK: u32 = 8;
 
-  pub fn new(rng: &mut impl Rng) -> Self {
-    Self {
+  pub fn new(rules: Rules) -> Self {
+    let mut playfield = Self {
       grid: [[Tetromino::Empty; Self::WIDTH]; Self::HEIGHT],
       falling_piece: FallingPiece::new(Tetromino::Empty, Self::PIECE_SPAWN_POSITION),
       holding_piece: Tetromino::Empty,
-      next_queue: NextQueue::new(rng),
+      next_queue: NextQueue::new(rules.seed, rules.randomizer.clone()),
       can_swap: true,
-      frames_since_last_fall: 0,
+      fall_progress: 0.0,
       lock_delay_frames: 0,
       lock_delay_moves: 0,
       signed_frames_pressed: 0,
+      das_cut_frames: 0,
+      last_move_was_rotation: false,
+      last_kick: 0,
       combo: 0,
       has_lost: false,
       score: 0,
+      breakdown: ScoreBreakdown::default(),
       b2b: 0,
       message: LineClearMessage::new(),
-    }
+      level: rules.mode.start_level(),
+      is_finished: false,
+      garbage_cleared: 0,
+      garbage: Garbage::new(rules.seed, rules.mode.messiness()),
+      garbage_risen: 0,
+      garbage_timer: 0,
+      incoming: VecDeque::new(),
+      outgoing: 0,
+      finesse: Finesse::new(),
+      trainer: (rules.mode == GameMode::Finesse).then(|| Trainer::new(rules.seed)),
+      stats: Stats::new(),
+      rules,
+    };
+    playfield.refill_garbage();
+    playfield
   }
 
-  pub fn restart(&mut self, rng: &mut impl Rng) {
+  // Restarting deals the same pieces again, so a seed can be practiced or raced.
+  // Endless games keep their score, every other mode is ranked from scratch.
+  pub fn restart(&mut self) {
     let last_score = self.score;
-    *self = Self::new(rng);
-    self.score = last_score;
+    *self = Self::new(self.rules.clone());
+    if self.rules.mode == GameMode::Endless {
+      self.score = last_score;
+    }
+  }
+
+  pub fn rules(&self) -> &Rules {
+    &self.rules
   }
 
-  pub fn update(&mut self, rl: &RaylibHandle, rng: &mut impl Rng) -> bool {
-    if rl.is_key_pressed(KeyboardKey::KEY_R) {
-      self.restart(rng);
+  // FNV-1a hash of the score, lines and grid, to tell whether a replay ended
+  // the same way it was recorded
+  pub fn checksum(&self) -> u64 {
+    fnv1a(self.checksum_bytes())
+  }
+
+  // FNV-1a hash of everything that decides how the game goes on, for netplay to
+  // tell the two games apart as soon as they diverge rather than once it shows
+  // on the grid
+  pub fn state_checksum(&self) -> u64 {
+    let piece = &self.falling_piece;
+    let bytes = self
+      .checksum_bytes()
+      .chain([
+        piece.tetromino as u8,
+        piece.orientation as u8,
+        piece.position.0 as u8,
+        piece.position.1 as u8,
+        self.holding_piece as u8,
+        self.can_swap as u8,
+        self.combo,
+      ])
+      .chain((0..NextQueue::NEXT_QUEUE_SIZE).map(|id| self.next_queue[id] as u8))
+      .chain(self.level.to_le_bytes())
+      .chain(self.b2b.to_le_bytes())
+      .chain(self.incoming.iter().flat_map(|lines| lines.to_le_bytes()));
+
+    fnv1a(bytes)
+  }
+
+  fn checksum_bytes(&self) -> impl Iterator<Item = u8> + '_ {
+    self
+      .score
+      .to_le_bytes()
+      .into_iter()
+      .chain(self.stats.lines.to_le_bytes())
+      .chain(self.grid.iter().flatten().map(|&mino| mino as u8))
+  }
+
+  pub fn update(&mut self, input: InputFrame) -> bool {
+    if input.is_pressed(Action::Restart) {
+      self.restart();
     }
-    if self.has_lost {
+    if self.has_lost || self.is_finished {
       return false;
     }
 
-    if rl.is_key_pressed(KeyboardKey::KEY_C) && self.can_swap {
+    self.stats.record(input);
+    let has_locked = self.update_falling_piece(input);
+    if has_locked {
+      self.refill_garbage();
+      // The trainer has every piece placed on an empty field
+      if self.trainer.is_some() {
+        self.grid = [[Tetromino::Empty; Self::WIDTH]; Self::HEIGHT];
+      }
+    }
+    self.update_trainer();
+    self.raise_garbage();
+    self.is_finished = self.rules.mode.is_complete(self);
+    has_locked
+  }
+
+  fn update_falling_piece(&mut self, input: InputFrame) -> bool {
+    if input.is_pressed(Action::Hold) && self.can_swap {
       self.swap_tetromino();
     }
 
     self.update_timers();
-    self.next_queue.push_new_bag_if_needed(rng);
 
     if self.falling_piece.tetromino == Tetromino::Empty {
       let new_tetromino = self.next_queue.get_next_tetromino();
       self.falling_piece = FallingPiece::new(new_tetromino, Playfield::PIECE_SPAWN_POSITION);
-      self.frames_since_last_fall = 0;
+      self.fall_progress = 0.0;
       self.lock_delay_frames = 0;
       self.lock_delay_moves = 0;
+      self.last_move_was_rotation = false;
+    }
+    // Holding or spawning can bring out a piece with no room left for it
+    if !self.fits(&self.falling_piece) {
+      self.has_lost = true;
+      return false;
     }
+    self.finesse.record(input);
 
-    if rl.is_key_pressed(KeyboardKey::KEY_LEFT) {
+    if input.is_pressed(Action::ShiftLeft) {
       self.shift_falling_piece(Shift::Left);
-    } else if rl.is_key_pressed(KeyboardKey::KEY_RIGHT) {
+    } else if input.is_pressed(Action::ShiftRight) {
       self.shift_falling_piece(Shift::Right);
     }
 
-    if rl.is_key_down(KeyboardKey::KEY_LEFT) {
+    let held_shift = if input.is_down(Action::ShiftLeft) {
       if self.signed_frames_pressed < 0 {
         self.signed_frames_pressed = 0;
       }
       self.signed_frames_pressed += 1;
-      while self.signed_frames_pressed > Self::DAS as i32 {
-        if !self.shift_falling_piece(Shift::Left) {
-          break;
-        }
-      }
-    } else if rl.is_key_down(KeyboardKey::KEY_RIGHT) {
+      Some(Shift::Left)
+    } else if input.is_down(Action::ShiftRight) {
       if self.signed_frames_pressed > 0 {
         self.signed_frames_pressed = 0;
       }
       self.signed_frames_pressed -= 1;
-      while -self.signed_frames_pressed > Self::DAS as i32 {
-        if !self.shift_falling_piece(Shift::Right) {
-          break;
-        }
-      }
+      Some(Shift::Right)
     } else {
       self.signed_frames_pressed = 0;
+      None
+    };
+
+    if self.das_cut_frames > 0 {
+      self.das_cut_frames -= 1;
+    } else if let Some(shift) = held_shift {
+      self.auto_shift(shift);
     }
 
-    if rl.is_key_pressed(KeyboardKey::KEY_UP) {
+    if input.is_pressed(Action::RotateClockwise) {
       self.check_rotation_collision(RotationType::Clockwise);
-    } else if rl.is_key_pressed(KeyboardKey::KEY_Z) {
+    } else if input.is_pressed(Action::RotateCounterClockwise) {
       self.check_rotation_collision(RotationType::CounterClockwise);
-    } else if rl.is_key_pressed(KeyboardKey::KEY_A) {
+    } else if input.is_pressed(Action::RotateOneEighty) {
       self.check_rotation_collision(RotationType::OneEighty);
     };
 
-    let mut old_piece = self.falling_piece;
-
-    if rl.is_key_pressed(KeyboardKey::KEY_SPACE) {
-      while self.check_falling_collisions() {
-        old_piece = self.falling_piece;
-        self.score += 2;
-        self.falling_piece.fall();
+    if input.is_pressed(Action::HardDrop) {
+      let mut cells_dropped = 0;
+      while self.try_fall() {
+        cells_dropped += 1;
+      }
+      // Dropping away from where the piece was rotated isn't a spin
+      if cells_dropped > 0 {
+        self.last_move_was_rotation = false;
       }
 
-      self.falling_piece = old_piece;
-      self.solidify_falling_piece();
+      let points = self.rules.scoring.hard_drop_points(cells_dropped);
+      self.score += points;
+      self.breakdown.drops += points;
+      self.judge_finesse();
+      self.lock_falling_piece();
       self.lock_delay_moves = 0;
       self.lock_delay_frames = 0;
-      self.clear_lines();
 
       return true;
     }
 
-    let mut is_fall_step = false;
-
-    if rl.is_key_down(KeyboardKey::KEY_DOWN) {
-      if self.frames_since_last_fall >= Self::SOFT_DROP_FRAMES {
-        self.frames_since_last_fall = 0;
-        is_fall_step = true;
-      }
-    } else if self.frames_since_last_fall >= Self::GRAVITY_FRAMES {
-      self.frames_since_last_fall = 0;
-      is_fall_step = true;
+    let mut cells_per_frame = self.rules.gravity.cells_per_frame(self.level);
+    if input.is_down(Action::SoftDrop) {
+      cells_per_frame = self.rules.handling.sdf.cells_per_frame(cells_per_frame);
     }
 
-    self.falling_piece.fall();
-    if self.check_falling_collisions() {
-      if is_fall_step {
-        self.lock_delay_frames = 0;
-        self.lock_delay_moves = 0;
-      } else {
-        self.falling_piece = old_piece
+    self.fall_progress += cells_per_frame;
+    while self.fall_progress >= 1.0 {
+      self.fall_progress -= 1.0;
+      if !self.try_fall() {
+        self.fall_progress = 0.0;
+        break;
       }
 
-      return false;
+      self.lock_delay_frames = 0;
+      self.lock_delay_moves = 0;
+      self.last_move_was_rotation = false;
+      if input.is_down(Action::SoftDrop) {
+        let points = self.rules.scoring.soft_drop_points(1);
+        self.score += points;
+        self.breakdown.drops += points;
+      }
     }
 
-    let mut has_piece_solidified = false;
+    let old_piece = self.falling_piece;
+    self.falling_piece.fall();
+    let is_grounded = !self.check_falling_collisions();
     self.falling_piece = old_piece;
 
-    if self.lock_delay_frames > Self::MAX_LOCK_DELAY_FRAMES
-      || self.lock_delay_moves > Self::MAX_LOCK_DELAY_MOVES
+    if is_grounded
+      && (self.lock_delay_frames > self.rules.handling.lock_delay
+        || self.lock_delay_moves > self.rules.handling.max_lock_resets)
     {
-      self.solidify_falling_piece();
-      has_piece_solidified = true;
-      self.clear_lines();
+      self.judge_finesse();
+      self.lock_falling_piece();
+      return true;
     }
 
-    has_piece_solidified
+    false
   }
 
   fn swap_tetromino(&mut self) {
@@ -232,110 +355,171 @@ impl Playfield {
     self.falling_piece = FallingPiece::new(self.holding_piece, Self::PIECE_SPAWN_POSITION);
     self.holding_piece = current_tetromino;
     self.can_swap = false;
-    self.frames_since_last_fall = 0;
+    self.fall_progress = 0.0;
     self.lock_delay_frames = 0;
     self.lock_delay_moves = 0;
+    self.last_move_was_rotation = false;
+    self.finesse.start_piece();
   }
 
   fn update_timers(&mut self) {
-    self.frames_since_last_fall += 1;
-    self.lock_delay_frames += 1;
+    self.lock_delay_frames = self.lock_delay_frames.saturating_add(1);
     if self.message.timer > 0 {
       self.message.timer -= 1;
     }
   }
 
+  // Once DAS is charged the piece moves every ARR frames, or straight to the wall
+  // when ARR is 0.
+  fn auto_shift(&mut self, shift: Shift) {
+    let frames_charged = self.signed_frames_pressed.abs() - self.rules.handling.das as i32;
+    if frames_charged <= 0 {
+      return;
+    }
+
+    if self.rules.handling.arr == 0 {
+      while self.shift_falling_piece(shift) {}
+    } else if (frames_charged - 1) % self.rules.handling.arr as i32 == 0 {
+      self.shift_falling_piece(shift);
+    }
+  }
+
   fn shift_falling_piece(&mut self, shift: Shift) -> bool {
     let old_piece = self.falling_piece;
     self.falling_piece.shift(shift);
 
-    let mut passed_check = true;
-    for pair in &self.falling_piece.tetromino_map {
-      let i = pair.0 + self.falling_piece.position.0;
-      let j = pair.1 + self.falling_piece.position.1;
-
-      if i < 0
-        || i >= Playfield::WIDTH as i8
-        || self.grid[j as usize][i as usize] != Tetromino::Empty
-      {
-        passed_check = false;
-        break;
-      }
-    }
-
+    let passed_check = self.fits(&self.falling_piece);
     if !passed_check {
       self.falling_piece = old_piece;
     } else {
       self.lock_delay_frames = 0;
       self.lock_delay_moves += 1;
+      self.last_move_was_rotation = false;
     }
 
     passed_check
   }
 
   fn check_rotation_collision(&mut self, rotation_type: RotationType) {
-    let mut could_rotate = false;
-    let old_piece = self.falling_piece.clone();
-    let start_offset_values = self.falling_piece.get_offset_table();
-
-    self.falling_piece.turn(rotation_type);
-    let end_offset_values = self.falling_piece.get_offset_table();
-
-    for offset_number in 0..start_offset_values.len() {
-      let mut passed = true;
-
-      let new_horizontal_position = self.falling_piece.position.0
-        + start_offset_values[offset_number].0
-        - end_offset_values[offset_number].0;
-      let new_vertical_position = self.falling_piece.position.1
-        - start_offset_values[offset_number].1
-        + end_offset_values[offset_number].1;
-
-      for coordinates in &self.falling_piece.tetromino_map {
-        let i = coordinates.0 + new_horizontal_position;
-        let j = coordinates.1 + new_vertical_position;
-
-        if i < 0
-          || i >= Self::WIDTH as i8
-          || j >= Self::HEIGHT as i8
-          || self.grid[j as usize][i as usize] != Tetromino::Empty
-        {
-          passed = false;
-          break;
-        }
-      }
+    let Some((piece, kick)) = self.rotated(self.falling_piece, rotation_type) else {
+      return;
+    };
 
-      if passed {
-        self.falling_piece.position.0 = new_horizontal_position;
-        self.falling_piece.position.1 = new_vertical_position;
-        could_rotate = true;
-        break;
-      }
-    }
+    self.falling_piece = piece;
+    self.last_kick = kick;
+    self.lock_delay_frames = 0;
+    self.lock_delay_moves += 1;
+    self.last_move_was_rotation = true;
+    self.das_cut_frames = self.rules.handling.dcd;
+  }
 
-    if !could_rotate {
-      self.falling_piece = old_piece;
-    } else {
-      self.lock_delay_frames = 0;
-      self.lock_delay_moves += 1;
+  // The piece turned to the first of its kicks that fits, and which kick that was
+  fn rotated(
+    &self,
+    piece: FallingPiece,
+    rotation_type: RotationType,
+  ) -> Option<(FallingPiece, usize)> {
+    let start_offset_values = piece.get_offset_table();
+    let mut turned_piece = piece;
+    turned_piece.turn(rotation_type);
+    let end_offset_values = turned_piece.get_offset_table();
+
+    (0..start_offset_values.len()).find_map(|offset_number| {
+      let mut kicked_piece = turned_piece;
+      kicked_piece.position.0 +=
+        start_offset_values[offset_number].0 - end_offset_values[offset_number].0;
+      kicked_piece.position.1 +=
+        end_offset_values[offset_number].1 - start_offset_values[offset_number].1;
+      self
+        .fits(&kicked_piece)
+        .then_some((kicked_piece, offset_number))
+    })
+  }
+
+  fn try_fall(&mut self) -> bool {
+    let old_piece = self.falling_piece;
+    self.falling_piece.fall();
+    if self.check_falling_collisions() {
+      return true;
     }
+
+    self.falling_piece = old_piece;
+    false
   }
 
   fn check_falling_collisions(&self) -> bool {
-    for pair in &self.falling_piece.tetromino_map {
-      let i = pair.0 + self.falling_piece.position.0;
-      let j = pair.1 + self.falling_piece.position.1;
-      if j > (Self::HEIGHT - 1) as i8 || self.grid[j as usize][i as usize] != Tetromino::Empty {
-        return false;
-      }
+    self.fits(&self.falling_piece)
+  }
+
+  // Cells outside the grid, including above its hidden top rows, count as filled
+  fn is_cell_empty(&self, i: i8, j: i8) -> bool {
+    i >= 0
+      && i < Self::WIDTH as i8
+      && j >= 0
+      && j < Self::HEIGHT as i8
+      && self.grid[j as usize][i as usize] == Tetromino::Empty
+  }
+
+  fn fits(&self, piece: &FallingPiece) -> bool {
+    piece
+      .tetromino_map
+      .iter()
+      .all(|&(i, j)| self.is_cell_empty(i + piece.position.0, j + piece.position.1))
+  }
+
+  fn lock_falling_piece(&mut self) {
+    let spin_type = self.get_spin_type();
+    self.solidify_falling_piece();
+    if self.clear_lines(spin_type) == 0 {
+      self.take_garbage();
     }
+  }
 
-    true
+  fn get_spin_type(&self) -> SpinType {
+    let kick = self.last_move_was_rotation.then_some(self.last_kick);
+    self.spin_type(&self.falling_piece, kick)
+  }
+
+  // 3-corner rule: a T that last moved by rotating and has three of the four cells
+  // diagonal to its center filled is a T-spin. It only counts as a proper one when both
+  // corners next to the pointing side are filled, or when the last kick was used.
+  // `kick` is the kick of the last move, if it was a rotation.
+  fn spin_type(&self, piece: &FallingPiece, kick: Option<usize>) -> SpinType {
+    let Some(kick) = kick else {
+      return SpinType::No;
+    };
+    if piece.tetromino != Tetromino::T {
+      return SpinType::No;
+    }
+
+    let (x, y) = piece.position;
+    let is_filled = |(i, j): CoordinatePair| !self.is_cell_empty(i + x, j + y);
+
+    let filled_corners = [(-1, -1), (1, -1), (1, 1), (-1, 1)]
+      .into_iter()
+      .filter(|&corner| is_filled(corner))
+      .count();
+    if filled_corners < 3 {
+      return SpinType::No;
+    }
+
+    let front_corners = match piece.orientation {
+      Orientation::Up => [(-1, -1), (1, -1)],
+      Orientation::Right => [(1, -1), (1, 1)],
+      Orientation::Down => [(1, 1), (-1, 1)],
+      Orientation::Left => [(-1, 1), (-1, -1)],
+    };
+
+    if front_corners.into_iter().all(is_filled) || kick == Self::LAST_KICK {
+      SpinType::Proper
+    } else {
+      SpinType::Mini
+    }
   }
 
   fn solidify_falling_piece(&mut self) {
     let mut passed = false;
-    for pair in &mut self.falling_piece.tetromino_map {
+    for pair in &self.falling_piece.tetromino_map {
       let i = pair.0 + self.falling_piece.position.0;
       let j = pair.1 + self.falling_piece.position.1;
       self.grid[j as usize][i as usize] = self.falling_piece.tetromino;
@@ -349,23 +533,16 @@ impl Playfield {
     self.falling_piece = FallingPiece::new(new_tetromino, Self::PIECE_SPAWN_POSITION);
     self.can_swap = true;
 
-    for coordinates in &self.falling_piece.tetromino_map {
-      let i = coordinates.0 + self.falling_piece.position.0;
-      let j = coordinates.1 + self.falling_piece.position.1;
-      if self.grid[j as usize][i as usize] != Tetromino::Empty {
-        passed = false;
-      }
-      break;
-    }
-
-    self.has_lost = !passed;
+    self.has_lost = !passed || !self.fits(&self.falling_piece);
+    self.das_cut_frames = self.rules.handling.dcd;
 
-    self.frames_since_last_fall = 0;
+    self.fall_progress = 0.0;
     self.lock_delay_frames = 0;
     self.lock_delay_moves = 0;
+    self.last_move_was_rotation = false;
   }
 
-  fn clear_lines(&mut self) {
+  fn clear_lines(&mut self, spin_type: SpinType) -> usize {
     let mut rows_to_clear = vec![];
     for j in 0..Self::HEIGHT {
       let mut all_true = true;
@@ -384,44 +561,49 @@ impl Playfield {
       }
     }
 
-    let size = rows_to_clear.len();
-
-    if size == 0 {
-      self.combo = 0;
-      return;
-    }
+    let lines = rows_to_clear.len();
+    self.garbage_cleared += rows_to_clear
+      .iter()
+      .filter(|&&j| self.grid[j].contains(&Tetromino::Garbage))
+      .count() as u32;
+    self.clear_rows(&mut rows_to_clear, 0);
 
-    if size != 4 {
-      self.combo = 0;
-    } else {
-      self.b2b += 1;
+    let line_clear = LineClear {
+      lines,
+      spin_type,
+      is_all_clear: lines > 0 && self.is_all_clear(),
+    };
+    let points = self.rules.scoring.score_lock(
+      line_clear,
+      self.level as u64,
+      &mut self.combo,
+      &mut self.b2b,
+    );
+    self.score += points.total();
+    self.breakdown.add(line_clear, points);
+    let attack = lines_sent(line_clear, self.combo, self.b2b);
+    self.stats.lock(line_clear, attack, self.combo, self.b2b);
+    if self.rules.mode == GameMode::Versus {
+      self.send(attack);
     }
 
-    self.combo += 1;
-    self.score += self.combo as u64 * 50;
+    self.level = self.rules.mode.start_level() + self.stats.lines / Self::LINES_PER_LEVEL;
 
-    let b2b_factor = if self.b2b >= 2 { 1.5 } else { 1.0 };
-
-    if size == 1 {
-      self.message = MessageType::Single.into();
-      self.score += (100.0 * b2b_factor) as u64;
-    } else if size == 2 {
-      self.message = MessageType::Double.into();
-      self.score += (300.0 * b2b_factor) as u64;
-    } else if size == 3 {
-      self.message = MessageType::Triple.into();
-      self.score += (500.0 * b2b_factor) as u64;
-    } else if size == 4 {
-      self.message = MessageType::Tetris.into();
-      self.score += (800.0 * b2b_factor) as u64;
+    if lines == 0 && spin_type == SpinType::No {
+      return lines;
     }
 
-    self.clear_rows(&mut rows_to_clear, 0);
-
-    if self.is_all_clear() {
-      self.message = MessageType::AllClear.into();
-      self.score += (3500.0 * b2b_factor) as u64;
+    self.message = match lines {
+      _ if line_clear.is_all_clear => MessageType::AllClear,
+      0 => MessageType::Empty,
+      1 => MessageType::Single,
+      2 => MessageType::Double,
+      3 => MessageType::Triple,
+      _ => MessageType::Tetris,
     }
+    .into();
+    self.message.spin_type = spin_type;
+    lines
   }
 
   fn clear_rows(&mut self, row_ids: &mut Vec<usize>, count: usize) {
@@ -443,35 +625,599 @@ impl Playfield {
     self.clear_rows(row_ids, count + 1);
   }
 
+  fn refill_garbage(&mut self) {
+    let rows = self.rules.mode.missing_garbage(self);
+    self.add_garbage(rows as usize);
+  }
+
+  fn raise_garbage(&mut self) {
+    let Some(interval) = self.rules.mode.garbage_interval(self.garbage_risen) else {
+      return;
+    };
+
+    self.garbage_timer += 1;
+    if self.garbage_timer >= interval {
+      self.garbage_timer = 0;
+      self.garbage_risen += 1;
+      self.add_garbage(1);
+    }
+  }
+
+  // How close the next row of rising garbage is, or how much garbage is on its way
+  // in versus, from 0 to 1
+  pub fn garbage_warning(&self) -> Option<f32> {
+    if self.rules.mode == GameMode::Versus {
+      let incoming: u32 = self.incoming.iter().sum();
+      return Some((incoming as f32 / Self::VISIBLE_HEIGHT as f32).min(1.0));
+    }
+
+    let interval = self.rules.mode.garbage_interval(self.garbage_risen)?;
+    Some(self.garbage_timer as f32 / interval as f32)
+  }
+
+  // Lines sent cancel the garbage on its way to this playfield first
+  fn send(&mut self, mut lines: u32) {
+    while let Some(incoming) = self.incoming.front_mut() {
+      let cancelled = lines.min(*incoming);
+      *incoming -= cancelled;
+      lines -= cancelled;
+      if *incoming > 0 {
+        break;
+      }
+      self.incoming.pop_front();
+    }
+    self.outgoing += lines;
+  }
+
+  pub fn receive_garbage(&mut self, lines: u32) {
+    if lines > 0 {
+      self.incoming.push_back(lines);
+    }
+  }
+
+  pub fn take_outgoing(&mut self) -> u32 {
+    mem::take(&mut self.outgoing)
+  }
+
+  // Garbage on its way only rises once a piece locks without clearing a line. Each
+  // attack has its hole in a column of its own.
+  fn take_garbage(&mut self) {
+    let mut rows = 0;
+    while rows < Self::MAX_GARBAGE_PER_LOCK {
+      let Some(incoming) = self.incoming.front_mut() else {
+        break;
+      };
+      let attack = (*incoming).min(Self::MAX_GARBAGE_PER_LOCK - rows);
+      *incoming -= attack;
+      if *incoming == 0 {
+        self.incoming.pop_front();
+      }
+
+      self.garbage.move_hole();
+      self.add_garbage(attack as usize);
+      rows += attack;
+    }
+  }
+
+  // Pushes the whole grid up to make room for garbage at the bottom. The falling
+  // piece rises along with the stack when it would end up inside it. Anything
+  // pushed out of the top of the grid tops the player out.
+  fn add_garbage(&mut self, rows: usize) {
+    if rows == 0 {
+      return;
+    }
+
+    let rows = rows.min(Self::HEIGHT);
+    if self.grid[..rows]
+      .iter()
+      .flatten()
+      .any(|&mino| mino != Tetromino::Empty)
+    {
+      self.has_lost = true;
+    }
+
+    self.grid.copy_within(rows.., 0);
+    for j in Self::HEIGHT - rows..Self::HEIGHT {
+      self.grid[j] = self.garbage.next_row();
+    }
+
+    if self.falling_piece.tetromino == Tetromino::Empty {
+      return;
+    }
+    for _ in 0..rows {
+      if self.fits(&self.falling_piece) {
+        break;
+      }
+      self.falling_piece.position.1 -= 1;
+    }
+    if !self.fits(&self.falling_piece) {
+      self.has_lost = true;
+    }
+  }
+
+  // Rows with garbage left in them
+  fn garbage_rows(&self) -> u32 {
+    self
+      .grid
+      .iter()
+      .filter(|row| row.contains(&Tetromino::Garbage))
+      .count() as u32
+  }
+
   fn is_all_clear(&self) -> bool {
     self
       .grid
       .iter()
-      .all(|row| row.iter().all(|mino| *mino != Tetromino::Empty))
+      .all(|row| row.iter().all(|mino| *mino == Tetromino::Empty))
   }
 
   pub fn get_ghost_piece(&self) -> FallingPiece {
-    let mut ghost_piece = self.falling_piece;
-    let mut old_ghost_piece = self.falling_piece;
+    self.get_landed(self.falling_piece)
+  }
+
+  // Where a piece ends up if it drops straight down
+  fn get_landed(&self, mut piece: FallingPiece) -> FallingPiece {
     loop {
-      ghost_piece.fall();
-      let mut passed = true;
-      for pair in &ghost_piece.tetromino_map {
-        let i = pair.0 + ghost_piece.position.0;
-        let j = pair.1 + ghost_piece.position.1;
-        if j > Playfield::HEIGHT as i8 - 1 || self.grid[j as usize][i as usize] != Tetromino::Empty
-        {
-          passed = false;
-          break;
+      let mut fallen_piece = piece;
+      fallen_piece.fall();
+      if !self.fits(&fallen_piece) {
+        return piece;
+      }
+      piece = fallen_piece;
+    }
+  }
+}
+
+fn fnv1a(bytes: impl Iterator<Item = u8>) -> u64 {
+  bytes.fold(0xcbf29ce484222325, |hash, byte| {
+    (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+  })
+}
+
+#[cfg(test)]
+impl Playfield {
+  // Deals `pieces` in order, with the first one already falling
+  pub fn scripted(pieces: &str, mode: GameMode) -> Self {
+    let mut playfield = Self::new(Rules {
+      randomizer: randomizer::RandomizerKind::scripted(pieces).unwrap(),
+      mode,
+      ..Rules::new(0)
+    });
+    playfield.update(InputFrame::new());
+    playfield
+  }
+
+  // Rows from the bottom of the field up, with # for a filled cell
+  pub fn fill(&mut self, rows: &[&str]) {
+    for (id, row) in rows.iter().enumerate() {
+      for (i, cell) in row.chars().enumerate() {
+        if cell == '#' {
+          self.grid[Self::HEIGHT - 1 - id][i] = Tetromino::Garbage;
         }
       }
-      if !passed {
-        ghost_piece = old_ghost_piece;
-        break;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    gravity::GravityCurve,
+    handling::{HandlingSettings, SoftDropSpeed},
+    mode::Record,
+    randomizer::RandomizerKind,
+    *,
+  };
+  use rand::{Rng, SeedableRng};
+  use rand_chacha::ChaCha8Rng;
+
+  const ACTIONS: [Action; 9] = [
+    Action::ShiftLeft,
+    Action::ShiftRight,
+    Action::RotateClockwise,
+    Action::RotateCounterClockwise,
+    Action::RotateOneEighty,
+    Action::SoftDrop,
+    Action::HardDrop,
+    Action::Hold,
+    Action::Restart,
+  ];
+
+  fn pressed(action: Action) -> InputFrame {
+    let mut input = InputFrame::new();
+    input.press(action);
+    input
+  }
+
+  fn fill_row(playfield: &mut Playfield, j: usize) {
+    playfield.grid[j] = [Tetromino::J; Playfield::WIDTH];
+  }
+
+  #[test]
+  fn clearing_rows_shifts_the_rows_above_down() {
+    let mut playfield = Playfield::scripted("O", GameMode::Endless);
+    fill_row(&mut playfield, 39);
+    fill_row(&mut playfield, 37);
+    playfield.grid[38][0] = Tetromino::L;
+    playfield.grid[36][3] = Tetromino::T;
+    playfield.grid[35][9] = Tetromino::S;
+
+    playfield.clear_lines(SpinType::No);
+
+    assert_eq!(playfield.stats.lines, 2);
+    assert_eq!(playfield.grid[39][0], Tetromino::L);
+    assert_eq!(playfield.grid[38][3], Tetromino::T);
+    assert_eq!(playfield.grid[37][9], Tetromino::S);
+    let filled_cells = playfield
+      .grid
+      .iter()
+      .flatten()
+      .filter(|&&mino| mino != Tetromino::Empty)
+      .count();
+    assert_eq!(filled_cells, 3);
+  }
+
+  #[test]
+  fn clearing_every_filled_row_is_an_all_clear() {
+    let mut playfield = Playfield::scripted("O", GameMode::Endless);
+    for j in 36..Playfield::HEIGHT {
+      fill_row(&mut playfield, j);
+    }
+
+    playfield.clear_lines(SpinType::No);
+
+    assert!(playfield.is_all_clear());
+    assert!(matches!(playfield.message.message, MessageType::AllClear));
+    assert_eq!(playfield.stats.lines, 4);
+  }
+
+  #[test]
+  fn rotating_then_hard_dropping_is_not_a_spin() {
+    let mut playfield = Playfield::scripted("TO", GameMode::Endless);
+    playfield.update(pressed(Action::RotateClockwise));
+    // Three corners will be filled where the T lands
+    let x = playfield.falling_piece.position.0 as usize;
+    for j in Playfield::HEIGHT - 3..Playfield::HEIGHT {
+      playfield.grid[j][x - 1] = Tetromino::Garbage;
+    }
+    playfield.grid[Playfield::HEIGHT - 1][x + 1] = Tetromino::Garbage;
+
+    assert!(playfield.update(pressed(Action::HardDrop)));
+
+    assert_eq!(playfield.stats.t_spin_minis, 0);
+    assert_eq!(playfield.stats.t_spins, [0; 4]);
+  }
+
+  #[test]
+  fn hard_dropped_i_clears_a_line() {
+    let mut playfield = Playfield::scripted("IO", GameMode::Endless);
+    fill_row(&mut playfield, 39);
+    for i in 3..7 {
+      playfield.grid[39][i] = Tetromino::Empty;
+    }
+
+    assert!(playfield.update(pressed(Action::HardDrop)));
+
+    assert_eq!(playfield.stats.lines, 1);
+    assert!(playfield.is_all_clear());
+    assert_eq!(playfield.falling_piece.tetromino, Tetromino::O);
+
+    let breakdown = &playfield.breakdown;
+    assert_eq!(breakdown.clears[0].count, 1);
+    assert!(breakdown.all_clear > 0 && breakdown.drops > 0);
+    assert_eq!(
+      breakdown.clears[0].points + breakdown.all_clear + breakdown.drops,
+      playfield.score
+    );
+  }
+
+  #[test]
+  fn blocked_spawn_tops_out() {
+    let mut playfield = Playfield::scripted("OI", GameMode::Endless);
+    playfield.falling_piece.position.1 = Playfield::HEIGHT as i8 - 1;
+    playfield.grid[Playfield::VISIBLE_HEIGHT - 1][3] = Tetromino::Z;
+
+    playfield.solidify_falling_piece();
+
+    assert!(playfield.has_lost);
+    assert!(!playfield.update(pressed(Action::HardDrop)));
+  }
+
+  #[test]
+  fn holding_into_a_blocked_spawn_tops_out() {
+    let mut playfield = Playfield::scripted("OI", GameMode::Endless);
+    playfield.grid[Playfield::VISIBLE_HEIGHT - 1][3] = Tetromino::Z;
+
+    let mut input = pressed(Action::Hold);
+    input.press(Action::HardDrop);
+    assert!(!playfield.update(input));
+
+    assert!(playfield.has_lost);
+    assert_eq!(playfield.score, 0);
+  }
+
+  #[test]
+  fn locking_above_the_visible_field_tops_out() {
+    let mut playfield = Playfield::scripted("OI", GameMode::Endless);
+    playfield.falling_piece.position.1 = Playfield::VISIBLE_HEIGHT as i8 - 5;
+
+    playfield.solidify_falling_piece();
+
+    assert!(playfield.has_lost);
+  }
+
+  #[test]
+  fn locking_on_the_floor_keeps_playing() {
+    let mut playfield = Playfield::scripted("OI", GameMode::Endless);
+    playfield.falling_piece.position.1 = Playfield::HEIGHT as i8 - 1;
+
+    playfield.solidify_falling_piece();
+
+    assert!(!playfield.has_lost);
+    assert_eq!(playfield.falling_piece.tetromino, Tetromino::I);
+  }
+
+  #[test]
+  fn hold_swaps_once_per_piece() {
+    let mut playfield = Playfield::scripted("TIO", GameMode::Endless);
+    assert_eq!(playfield.falling_piece.tetromino, Tetromino::T);
+
+    playfield.update(pressed(Action::Hold));
+    assert_eq!(playfield.falling_piece.tetromino, Tetromino::I);
+    assert_eq!(playfield.holding_piece, Tetromino::T);
+    assert!(!playfield.can_swap);
+
+    playfield.update(pressed(Action::Hold));
+    assert_eq!(playfield.falling_piece.tetromino, Tetromino::I);
+    assert_eq!(playfield.holding_piece, Tetromino::T);
+
+    playfield.update(pressed(Action::HardDrop));
+    assert!(playfield.can_swap);
+    assert_eq!(playfield.falling_piece.tetromino, Tetromino::O);
+
+    playfield.update(pressed(Action::Hold));
+    assert_eq!(playfield.falling_piece.tetromino, Tetromino::T);
+    assert_eq!(playfield.holding_piece, Tetromino::O);
+    assert_eq!(
+      playfield.falling_piece.position,
+      Playfield::PIECE_SPAWN_POSITION
+    );
+  }
+
+  #[test]
+  fn restart_deals_the_same_pieces() {
+    let mut playfield = Playfield::new(Rules::new(42));
+    playfield.update(InputFrame::new());
+    let first_piece = playfield.falling_piece.tetromino;
+    let preview: Vec<_> = (0..NextQueue::NEXT_QUEUE_SIZE)
+      .map(|id| playfield.next_queue[id])
+      .collect();
+
+    for _ in 0..5 {
+      playfield.update(pressed(Action::HardDrop));
+    }
+    playfield.update(pressed(Action::Restart));
+
+    assert_eq!(playfield.falling_piece.tetromino, first_piece);
+    for (id, &tetromino) in preview.iter().enumerate() {
+      assert_eq!(playfield.next_queue[id], tetromino);
+    }
+  }
+
+  // Property test: whatever the player does, the playfield never indexes out of the
+  // grid, and the falling piece always stays inside it on empty cells.
+  #[test]
+  fn random_inputs_never_leave_the_grid() {
+    let randomizers = [
+      RandomizerKind::SevenBag,
+      RandomizerKind::PureRandom,
+      RandomizerKind::History,
+      RandomizerKind::NesReroll,
+      RandomizerKind::scripted("IIIIOZ").unwrap(),
+    ];
+    let gravities = [
+      GravityCurve::Guideline,
+      GravityCurve::Nes,
+      GravityCurve::TwentyG,
+    ];
+
+    for seed in 0..48 {
+      let mut rng = ChaCha8Rng::seed_from_u64(seed);
+      let mut handling = HandlingSettings::new();
+      handling.das = rng.gen_range(HandlingSettings::DAS_RANGE);
+      handling.arr = rng.gen_range(HandlingSettings::ARR_RANGE);
+      handling.dcd = rng.gen_range(HandlingSettings::DCD_RANGE);
+      handling.lock_delay = rng.gen_range(HandlingSettings::LOCK_DELAY_RANGE);
+      if rng.gen_bool(0.3) {
+        handling.sdf = SoftDropSpeed::Instant;
+      }
+
+      let mut playfield = Playfield::new(Rules {
+        randomizer: randomizers[seed as usize % randomizers.len()].clone(),
+        gravity: gravities[seed as usize % gravities.len()],
+        handling,
+        ..Rules::new(seed)
+      });
+
+      for _ in 0..2000 {
+        let mut input = InputFrame::new();
+        for action in ACTIONS {
+          let press_chance = match action {
+            Action::Restart => 0.001,
+            Action::HardDrop => 0.02,
+            _ => 0.1,
+          };
+          if rng.gen_bool(press_chance) {
+            input.press(action);
+          } else if action != Action::Restart && rng.gen_bool(0.2) {
+            input.hold(action);
+          }
+        }
+
+        playfield.update(input);
+        playfield.get_ghost_piece();
+        assert!(
+          playfield.has_lost || playfield.fits(&playfield.falling_piece),
+          "seed {}: {:?} is out of place",
+          seed,
+          playfield.falling_piece
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn sprint_ends_on_the_fortieth_line() {
+    let mut playfield = Playfield::new(Rules {
+      mode: GameMode::Sprint,
+      ..Rules::new(0)
+    });
+    playfield.update(InputFrame::new());
+    playfield.stats.lines = GameMode::SPRINT_LINES - 1;
+    fill_row(&mut playfield, 39);
+    playfield.update(pressed(Action::HardDrop));
+    assert_eq!(playfield.stats.lines, GameMode::SPRINT_LINES);
+    assert!(playfield.is_finished);
+
+    let frames = playfield.stats.frames;
+    assert!(!playfield.update(pressed(Action::HardDrop)));
+    assert_eq!(playfield.stats.frames, frames);
+    assert_eq!(
+      GameMode::Sprint.record(&playfield).unwrap().to_string(),
+      "0:00.033"
+    );
+  }
+
+  #[test]
+  fn ultra_ends_when_time_is_up() {
+    let mut playfield = Playfield::new(Rules {
+      mode: GameMode::Ultra,
+      ..Rules::new(0)
+    });
+    for _ in 0..GameMode::ULTRA_FRAMES {
+      assert!(!playfield.is_finished);
+      playfield.update(InputFrame::new());
+    }
+    assert!(playfield.is_finished);
+    assert!(!playfield.has_lost);
+
+    let score = playfield.score;
+    playfield.update(pressed(Action::HardDrop));
+    assert_eq!(playfield.score, score);
+    assert_eq!(
+      GameMode::Ultra.record(&playfield),
+      Some(Record::Score(score))
+    );
+  }
+
+  #[test]
+  fn marathon_levels_up_every_ten_lines_from_the_start_level() {
+    let mut playfield = Playfield::new(Rules {
+      mode: GameMode::Marathon {
+        start_level: 5,
+        goal: Some(150),
+      },
+      ..Rules::new(0)
+    });
+    playfield.update(InputFrame::new());
+    assert_eq!(playfield.level, 5);
+
+    for (lines, level) in [(9, 5), (10, 6), (149, 19)] {
+      playfield.stats.lines = lines - 1;
+      fill_row(&mut playfield, 39);
+      playfield.clear_lines(SpinType::No);
+      assert_eq!(playfield.level, level);
+    }
+    playfield.update(InputFrame::new());
+    assert!(!playfield.is_finished);
+
+    playfield.stats.lines = 150;
+    playfield.update(InputFrame::new());
+    assert!(playfield.is_finished);
+  }
+
+  #[test]
+  fn dig_refills_garbage_until_the_goal_is_in_sight() {
+    for (goal, rows_after_refill) in [(40, GameMode::DIG_ROWS), (10, GameMode::DIG_ROWS - 1)] {
+      let mut playfield = Playfield::new(Rules {
+        mode: GameMode::Dig {
+          goal,
+          messiness: 50,
+        },
+        ..Rules::new(3)
+      });
+      assert_eq!(playfield.garbage_rows(), GameMode::DIG_ROWS);
+
+      for mino in &mut playfield.grid[Playfield::HEIGHT - 1] {
+        if *mino == Tetromino::Empty {
+          *mino = Tetromino::J;
+        }
+      }
+      playfield.clear_lines(SpinType::No);
+      assert_eq!(playfield.garbage_cleared, 1);
+
+      playfield.refill_garbage();
+      assert_eq!(playfield.garbage_rows(), rows_after_refill);
+      assert!(!playfield.has_lost);
+    }
+  }
+
+  #[test]
+  fn rising_garbage_lifts_the_falling_piece() {
+    let mut playfield = Playfield::scripted("O", GameMode::Endless);
+    let landed_piece = playfield.get_ghost_piece();
+    playfield.falling_piece = landed_piece;
+
+    playfield.add_garbage(3);
+    assert!(!playfield.has_lost);
+    assert!(playfield.fits(&playfield.falling_piece));
+    assert_eq!(
+      playfield.falling_piece.position.1,
+      landed_piece.position.1 - 3
+    );
+    assert_eq!(playfield.garbage_rows(), 3);
+
+    playfield.grid[0][0] = Tetromino::J;
+    playfield.add_garbage(1);
+    assert!(playfield.has_lost);
+  }
+
+  #[test]
+  fn survival_raises_garbage_faster_and_faster() {
+    let mut playfield = Playfield::new(Rules {
+      mode: GameMode::Survival,
+      ..Rules::new(0)
+    });
+    let mut rises = vec![0];
+    for frame in 1..=3000 {
+      let risen = playfield.garbage_risen;
+      playfield.update(InputFrame::new());
+      if playfield.garbage_risen > risen {
+        rises.push(frame);
       }
-      old_ghost_piece = ghost_piece;
     }
 
-    ghost_piece
+    assert_eq!(rises.len(), 6);
+    assert!(rises
+      .windows(3)
+      .all(|frames| frames[2] - frames[1] < frames[1] - frames[0]));
+    assert!(!playfield.has_lost);
+  }
+
+  #[test]
+  fn state_checksum_tells_apart_what_the_grid_does_not_show() {
+    let playfield = Playfield::scripted("TIOSZ", GameMode::Versus);
+    let changes: [fn(&mut Playfield); 4] = [
+      |playfield| playfield.incoming.push_back(2),
+      |playfield| playfield.next_queue = NextQueue::new(1, RandomizerKind::SevenBag),
+      |playfield| playfield.falling_piece.shift(Shift::Left),
+      |playfield| playfield.b2b = 1,
+    ];
+    for change in changes {
+      let mut changed = playfield.clone();
+      change(&mut changed);
+      assert_eq!(changed.checksum(), playfield.checksum());
+      assert_ne!(changed.state_checksum(), playfield.state_checksum());
+    }
   }
 }
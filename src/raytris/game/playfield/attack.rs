@@ -0,0 +1,61 @@
+use super::{scoring::LineClear, SpinType};
+
+const COMBO_LINES: [u32; 12] = [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+const B2B_LINES: u32 = 1;
+const ALL_CLEAR_LINES: u32 = 10;
+
+// Lines of garbage a lock sends to the opponent in versus. `combo` and `b2b` are
+// the counters after the lock, as `ScoringSystem::score_lock` leaves them.
+pub fn lines_sent(clear: LineClear, combo: u8, b2b: u16) -> u32 {
+  if clear.lines == 0 {
+    return 0;
+  }
+
+  let mut lines = match (clear.spin_type, clear.lines) {
+    (SpinType::Proper, lines) => 2 * lines.min(3) as u32,
+    (SpinType::Mini, lines) => lines as u32 - 1,
+    (SpinType::No, 4) => 4,
+    (SpinType::No, lines) => lines as u32 - 1,
+  };
+  if clear.is_difficult() && b2b >= 2 {
+    lines += B2B_LINES;
+  }
+  let combo = (combo as usize).clamp(1, COMBO_LINES.len());
+  lines += COMBO_LINES[combo - 1];
+  if clear.is_all_clear {
+    lines += ALL_CLEAR_LINES;
+  }
+  lines
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn clear(lines: usize, spin_type: SpinType) -> LineClear {
+    LineClear {
+      lines,
+      spin_type,
+      is_all_clear: false,
+    }
+  }
+
+  #[test]
+  fn attack_table_matches_the_guideline() {
+    assert_eq!(lines_sent(clear(0, SpinType::Proper), 0, 0), 0);
+    assert_eq!(lines_sent(clear(1, SpinType::No), 1, 0), 0);
+    assert_eq!(lines_sent(clear(2, SpinType::No), 1, 0), 1);
+    assert_eq!(lines_sent(clear(4, SpinType::No), 1, 1), 4);
+    assert_eq!(lines_sent(clear(2, SpinType::Proper), 1, 1), 4);
+    assert_eq!(lines_sent(clear(2, SpinType::Proper), 1, 2), 5);
+    assert_eq!(lines_sent(clear(1, SpinType::Mini), 1, 1), 0);
+    assert_eq!(lines_sent(clear(1, SpinType::No), 5, 0), 2);
+    assert_eq!(lines_sent(clear(1, SpinType::No), 40, 0), 5);
+
+    let all_clear = LineClear {
+      is_all_clear: true,
+      ..clear(4, SpinType::No)
+    };
+    assert_eq!(lines_sent(all_clear, 1, 1), 14);
+  }
+}
@@ -8,9 +8,10 @@ pub enum Tetromino {
   J,
   L,
   Empty,
+  Garbage,
 }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Orientation {
   Up,
   Left,
@@ -36,6 +37,45 @@ pub type OffsetTable = [CoordinatePair; 5];
 pub type TetrominoMap = [CoordinatePair; 4];
 
 impl Tetromino {
+  // The ones that can be dealt, in order
+  pub const ALL: [Self; 7] = [
+    Self::I,
+    Self::O,
+    Self::T,
+    Self::Z,
+    Self::S,
+    Self::J,
+    Self::L,
+  ];
+
+  // Pieces go by their letter on the command line and in replays
+  pub fn from_letter(letter: char) -> Option<Self> {
+    match letter.to_ascii_uppercase() {
+      'I' => Some(Self::I),
+      'O' => Some(Self::O),
+      'T' => Some(Self::T),
+      'Z' => Some(Self::Z),
+      'S' => Some(Self::S),
+      'J' => Some(Self::J),
+      'L' => Some(Self::L),
+      _ => None,
+    }
+  }
+
+  // None for the cells that aren't pieces
+  pub fn letter(self) -> Option<char> {
+    match self {
+      Self::I => Some('I'),
+      Self::O => Some('O'),
+      Self::T => Some('T'),
+      Self::Z => Some('Z'),
+      Self::S => Some('S'),
+      Self::J => Some('J'),
+      Self::L => Some('L'),
+      Self::Empty | Self::Garbage => None,
+    }
+  }
+
   pub fn initial_tetromino_map(self) -> TetrominoMap {
     use Tetromino::*;
     match self {
@@ -61,7 +101,8 @@ impl Tetromino {
       Z => rcolor(239, 32, 41, 255),
       J => rcolor(90, 101, 173, 255),
       L => rcolor(239, 121, 33, 255),
-      _ => Color::BLANK,
+      Garbage => rcolor(105, 105, 105, 255),
+      Empty => Color::BLANK,
     }
   }
 }
@@ -88,6 +129,13 @@ impl FallingPiece {
     }
   }
 
+  // Where the piece is on the grid
+  pub fn cells(&self) -> TetrominoMap {
+    self
+      .tetromino_map
+      .map(|(i, j)| (i + self.position.0, j + self.position.1))
+  }
+
   pub fn fall(&mut self) {
     self.position.1 += 1;
   }
@@ -143,3 +191,222 @@ impl FallingPiece {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const JLSTZ_KICKS: [(Orientation, RotationType, OffsetTable); 8] = [
+    (
+      Up,
+      RotationType::Clockwise,
+      [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    ),
+    (
+      Right,
+      RotationType::CounterClockwise,
+      [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    ),
+    (
+      Right,
+      RotationType::Clockwise,
+      [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    ),
+    (
+      Down,
+      RotationType::CounterClockwise,
+      [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    ),
+    (
+      Down,
+      RotationType::Clockwise,
+      [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    ),
+    (
+      Left,
+      RotationType::CounterClockwise,
+      [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+    ),
+    (
+      Left,
+      RotationType::Clockwise,
+      [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+    ),
+    (
+      Up,
+      RotationType::CounterClockwise,
+      [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    ),
+  ];
+
+  const I_KICKS: [(Orientation, RotationType, OffsetTable); 8] = [
+    (
+      Up,
+      RotationType::Clockwise,
+      [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+    ),
+    (
+      Right,
+      RotationType::CounterClockwise,
+      [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    ),
+    (
+      Right,
+      RotationType::Clockwise,
+      [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    ),
+    (
+      Down,
+      RotationType::CounterClockwise,
+      [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    ),
+    (
+      Down,
+      RotationType::Clockwise,
+      [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    ),
+    (
+      Left,
+      RotationType::CounterClockwise,
+      [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+    ),
+    (
+      Left,
+      RotationType::Clockwise,
+      [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    ),
+    (
+      Up,
+      RotationType::CounterClockwise,
+      [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    ),
+  ];
+
+  fn piece_facing(tetromino: Tetromino, orientation: Orientation) -> FallingPiece {
+    let mut piece = FallingPiece::new(tetromino, (4, 20));
+    while piece.orientation != orientation {
+      piece.turn(RotationType::Clockwise);
+    }
+    piece
+  }
+
+  // The kicks tried by the playfield, relative to the first one and with y pointing up
+  fn kicks(tetromino: Tetromino, orientation: Orientation, rotation: RotationType) -> OffsetTable {
+    let mut piece = piece_facing(tetromino, orientation);
+    let start = piece.get_offset_table();
+    piece.turn(rotation);
+    let end = piece.get_offset_table();
+
+    let first = (start[0].0 - end[0].0, start[0].1 - end[0].1);
+    let mut kicks = [(0, 0); 5];
+    for id in 0..kicks.len() {
+      kicks[id] = (
+        start[id].0 - end[id].0 - first.0,
+        start[id].1 - end[id].1 - first.1,
+      );
+    }
+    kicks
+  }
+
+  fn cells(piece: &FallingPiece) -> Vec<CoordinatePair> {
+    let mut cells: Vec<_> = piece
+      .tetromino_map
+      .iter()
+      .map(|(i, j)| (i + piece.position.0, j + piece.position.1))
+      .collect();
+    cells.sort();
+    cells
+  }
+
+  #[test]
+  fn jlstz_kicks_match_srs() {
+    use Tetromino::*;
+    for tetromino in [J, L, S, T, Z] {
+      for (orientation, rotation, expected) in JLSTZ_KICKS {
+        assert_eq!(
+          kicks(tetromino, orientation, rotation),
+          expected,
+          "{:?} {:?} {:?}",
+          tetromino,
+          orientation,
+          rotation
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn i_kicks_match_srs() {
+    for (orientation, rotation, expected) in I_KICKS {
+      assert_eq!(
+        kicks(Tetromino::I, orientation, rotation),
+        expected,
+        "{:?} {:?}",
+        orientation,
+        rotation
+      );
+    }
+  }
+
+  #[test]
+  fn o_never_moves_when_rotated() {
+    for orientation in [Up, Right, Down, Left] {
+      for rotation in [
+        RotationType::Clockwise,
+        RotationType::CounterClockwise,
+        RotationType::OneEighty,
+      ] {
+        let mut piece = piece_facing(Tetromino::O, orientation);
+        let before = cells(&piece);
+        let start = piece.get_offset_table();
+        piece.turn(rotation);
+        let end = piece.get_offset_table();
+        for id in 0..start.len() {
+          let mut kicked_piece = piece;
+          kicked_piece.position.0 += start[id].0 - end[id].0;
+          kicked_piece.position.1 += end[id].1 - start[id].1;
+          assert_eq!(cells(&kicked_piece), before);
+        }
+      }
+    }
+  }
+
+  // Rotating the I in open space keeps it inside its 4x4 box, like SRS does
+  #[test]
+  fn i_rotates_around_the_center_of_its_box() {
+    let mut piece = FallingPiece::new(Tetromino::I, (4, 20));
+    let expected = [
+      [(5, 19), (5, 20), (5, 21), (5, 22)],
+      [(3, 21), (4, 21), (5, 21), (6, 21)],
+      [(4, 19), (4, 20), (4, 21), (4, 22)],
+      [(3, 20), (4, 20), (5, 20), (6, 20)],
+    ];
+    for cells_after_turn in expected {
+      let start = piece.get_offset_table();
+      piece.turn(RotationType::Clockwise);
+      let end = piece.get_offset_table();
+      piece.position.0 += start[0].0 - end[0].0;
+      piece.position.1 += end[0].1 - start[0].1;
+      assert_eq!(cells(&piece), cells_after_turn);
+    }
+  }
+
+  #[test]
+  fn four_turns_are_a_full_circle() {
+    use Tetromino::*;
+    for tetromino in [I, O, T, S, Z, J, L] {
+      for rotation in [RotationType::Clockwise, RotationType::CounterClockwise] {
+        let mut piece = FallingPiece::new(tetromino, (4, 20));
+        for _ in 0..4 {
+          piece.turn(rotation);
+        }
+        assert_eq!(piece, FallingPiece::new(tetromino, (4, 20)));
+      }
+
+      let mut piece = FallingPiece::new(tetromino, (4, 20));
+      piece.turn(RotationType::OneEighty);
+      piece.turn(RotationType::OneEighty);
+      assert_eq!(piece, FallingPiece::new(tetromino, (4, 20)));
+    }
+  }
+}
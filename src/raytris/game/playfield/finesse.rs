@@ -0,0 +1,401 @@
+use super::{
+  falling_piece::{FallingPiece, Orientation, RotationType, Shift, Tetromino, TetrominoMap},
+  input::{Action, InputFrame},
+  rules::Rules,
+  Playfield,
+};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::{
+  collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+  sync::OnceLock,
+};
+
+// A key press as finesse counts them. Holding a shift key until the piece stops at
+// the wall is a single press, however far the piece goes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Press {
+  Left,
+  Right,
+  DasLeft,
+  DasRight,
+  Clockwise,
+  CounterClockwise,
+  OneEighty,
+}
+
+impl Press {
+  const ALL: [Self; 7] = [
+    Self::Left,
+    Self::Right,
+    Self::DasLeft,
+    Self::DasRight,
+    Self::Clockwise,
+    Self::CounterClockwise,
+    Self::OneEighty,
+  ];
+  // The keys a piece is moved with, each press of which counts
+  const KEYS: [Action; 5] = [
+    Action::ShiftLeft,
+    Action::ShiftRight,
+    Action::RotateClockwise,
+    Action::RotateCounterClockwise,
+    Action::RotateOneEighty,
+  ];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Self::Left => "Left",
+      Self::Right => "Right",
+      Self::DasLeft => "DAS left",
+      Self::DasRight => "DAS right",
+      Self::Clockwise => "CW",
+      Self::CounterClockwise => "CCW",
+      Self::OneEighty => "180",
+    }
+  }
+}
+
+// Somewhere a piece can be hard dropped to on an empty field, and the fewest
+// presses that take it there
+#[derive(Clone, PartialEq, Debug)]
+pub struct Spot {
+  pub piece: FallingPiece,
+  pub path: Vec<Press>,
+}
+
+// How many keys a piece took, against the fewest that put it in the same spot
+#[derive(Clone, PartialEq, Debug)]
+pub struct Judgement {
+  pub tetromino: Tetromino,
+  pub presses: u32,
+  pub path: Vec<Press>,
+}
+
+impl Judgement {
+  pub fn faults(&self) -> u32 {
+    self.presses.saturating_sub(self.path.len() as u32)
+  }
+}
+
+// Pieces of a tetromino that were judged, and the faults made placing them
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct FinesseCount {
+  pub pieces: u32,
+  pub faults: u32,
+}
+
+// Counts the keys pressed for the falling piece, and judges each piece as it
+// locks. Pieces that were soft dropped are left out, since they may have been
+// tucked or spun somewhere a hard drop can't reach.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Finesse {
+  presses: u32,
+  soft_dropped: bool,
+  // In the order of `Tetromino::ALL`
+  pub counts: [FinesseCount; 7],
+  pub last: Option<Judgement>,
+}
+
+impl Finesse {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn record(&mut self, input: InputFrame) {
+    self.presses += Press::KEYS
+      .iter()
+      .filter(|&&action| input.is_pressed(action))
+      .count() as u32;
+    self.soft_dropped |= input.is_down(Action::SoftDrop);
+  }
+
+  // Held pieces start over, as if they had just spawned
+  pub fn start_piece(&mut self) {
+    self.presses = 0;
+    self.soft_dropped = false;
+  }
+
+  pub fn presses(&self) -> u32 {
+    self.presses
+  }
+
+  pub fn pieces(&self) -> u32 {
+    self.counts.iter().map(|count| count.pieces).sum()
+  }
+
+  pub fn faults(&self) -> u32 {
+    self.counts.iter().map(|count| count.faults).sum()
+  }
+}
+
+// The finesse trainer picks a spot for every piece to go to, on a field that is
+// emptied after each one. Its generator runs on a stream of the game's seed of
+// its own, like garbage does.
+#[derive(Clone, Debug)]
+pub struct Trainer {
+  rng: ChaCha8Rng,
+  pub target: Option<Spot>,
+  pub misses: u32,
+}
+
+impl Trainer {
+  pub fn new(seed: u64) -> Self {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_stream(2);
+    Self {
+      rng,
+      target: None,
+      misses: 0,
+    }
+  }
+}
+
+// Every spot a tetromino can be hard dropped to on an empty field. They don't
+// depend on the game, so they are only searched for once.
+fn finesse_spots(tetromino: Tetromino) -> &'static [Spot] {
+  static SPOTS: OnceLock<[Vec<Spot>; 7]> = OnceLock::new();
+  let spots = SPOTS.get_or_init(|| {
+    let field = Playfield::new(Rules::new(0));
+    Tetromino::ALL.map(|tetromino| field.search_spots(tetromino))
+  });
+  &spots[tetromino as usize]
+}
+
+// Where a piece lands with nothing under it but the floor
+fn landed_on_empty_field(mut piece: FallingPiece) -> FallingPiece {
+  let bottom = piece.cells().iter().map(|&(_, j)| j).max().unwrap();
+  piece.position.1 += Playfield::HEIGHT as i8 - 1 - bottom;
+  piece
+}
+
+impl Playfield {
+  // Breadth first, so the first path found to a spot is one of the shortest.
+  // Only meant for an empty field.
+  fn search_spots(&self, tetromino: Tetromino) -> Vec<Spot> {
+    let start = FallingPiece::new(tetromino, Self::PIECE_SPAWN_POSITION);
+    let key = |piece: &FallingPiece| (piece.position, piece.orientation);
+    let mut paths: HashMap<((i8, i8), Orientation), Vec<Press>> =
+      HashMap::from([(key(&start), Vec::new())]);
+    let mut pieces = VecDeque::from([start]);
+    let mut spots = Vec::new();
+    let mut seen = HashSet::new();
+    while let Some(piece) = pieces.pop_front() {
+      let path = paths[&key(&piece)].clone();
+      let landed = self.get_landed(piece);
+      if seen.insert(spot_key(&landed)) {
+        spots.push(Spot {
+          piece: landed,
+          path: path.clone(),
+        });
+      }
+
+      for press in Press::ALL {
+        let Some(next) = self.pressed(piece, press) else {
+          continue;
+        };
+        if let Entry::Vacant(entry) = paths.entry(key(&next)) {
+          let mut next_path = path.clone();
+          next_path.push(press);
+          entry.insert(next_path);
+          pieces.push_back(next);
+        }
+      }
+    }
+    spots
+  }
+
+  // Where a press takes a piece, if it moves at all
+  fn pressed(&self, mut piece: FallingPiece, press: Press) -> Option<FallingPiece> {
+    let rotation_type = match press {
+      Press::Left | Press::Right | Press::DasLeft | Press::DasRight => {
+        let shift = match press {
+          Press::Left | Press::DasLeft => Shift::Left,
+          _ => Shift::Right,
+        };
+        let start = piece;
+        loop {
+          let mut shifted = piece;
+          shifted.shift(shift);
+          if !self.fits(&shifted) {
+            break;
+          }
+          piece = shifted;
+          if matches!(press, Press::Left | Press::Right) {
+            break;
+          }
+        }
+        return (piece != start).then_some(piece);
+      }
+      Press::Clockwise => RotationType::Clockwise,
+      Press::CounterClockwise => RotationType::CounterClockwise,
+      Press::OneEighty => RotationType::OneEighty,
+    };
+    self.rotated(piece, rotation_type).map(|(piece, _)| piece)
+  }
+
+  // Judges the falling piece against the spot it would take on an empty field,
+  // right before it locks
+  pub(super) fn judge_finesse(&mut self) {
+    let piece = self.falling_piece;
+    let spot = spot_key(&landed_on_empty_field(piece));
+
+    if let Some(trainer) = &mut self.trainer {
+      if trainer
+        .target
+        .take()
+        .is_some_and(|target| spot_key(&target.piece) != spot)
+      {
+        trainer.misses += 1;
+      }
+    }
+
+    let finesse = &mut self.finesse;
+    let best = finesse_spots(piece.tetromino)
+      .iter()
+      .find(|best| spot_key(&best.piece) == spot);
+    if let (Some(best), false) = (best, finesse.soft_dropped) {
+      let judgement = Judgement {
+        tetromino: piece.tetromino,
+        presses: finesse.presses,
+        path: best.path.clone(),
+      };
+      let count = &mut finesse.counts[piece.tetromino as usize];
+      count.pieces += 1;
+      count.faults += judgement.faults();
+      self.stats.finesse_faults += judgement.faults();
+      finesse.last = Some(judgement);
+    }
+    finesse.start_piece();
+  }
+
+  // Gives the falling piece a spot to go to, when training
+  pub(super) fn update_trainer(&mut self) {
+    let tetromino = self.falling_piece.tetromino;
+    let Some(trainer) = &self.trainer else {
+      return;
+    };
+    if tetromino == Tetromino::Empty
+      || trainer
+        .target
+        .as_ref()
+        .is_some_and(|target| target.piece.tetromino == tetromino)
+    {
+      return;
+    }
+
+    let spots = finesse_spots(tetromino);
+    let trainer = self.trainer.as_mut().unwrap();
+    let id = trainer.rng.gen_range(0..spots.len());
+    trainer.target = Some(spots[id].clone());
+  }
+}
+
+// The cells a piece covers, which is all that tells spots apart. S, Z and I have
+// two orientations each that can cover the same cells.
+fn spot_key(piece: &FallingPiece) -> TetrominoMap {
+  let mut cells = piece.cells();
+  cells.sort();
+  cells
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::raytris::game::playfield::mode::GameMode;
+
+  // Taps are followed by a frame with nothing pressed, and DAS is held until the
+  // piece reaches the wall
+  fn play(playfield: &mut Playfield, presses: &[Press]) {
+    for &press in presses {
+      let action = match press {
+        Press::Left | Press::DasLeft => Action::ShiftLeft,
+        Press::Right | Press::DasRight => Action::ShiftRight,
+        Press::Clockwise => Action::RotateClockwise,
+        Press::CounterClockwise => Action::RotateCounterClockwise,
+        Press::OneEighty => Action::RotateOneEighty,
+      };
+      let mut input = InputFrame::new();
+      input.press(action);
+      playfield.update(input);
+      if matches!(press, Press::DasLeft | Press::DasRight) {
+        let mut input = InputFrame::new();
+        input.hold(action);
+        for _ in 0..=playfield.rules().handling.das {
+          playfield.update(input);
+        }
+      }
+      playfield.update(InputFrame::new());
+    }
+    let mut input = InputFrame::new();
+    input.press(Action::HardDrop);
+    playfield.update(input);
+  }
+
+  #[test]
+  fn every_spot_takes_three_presses_at_most() {
+    for (tetromino, count) in [
+      (Tetromino::O, 9),
+      (Tetromino::I, 17),
+      (Tetromino::T, 34),
+      (Tetromino::S, 17),
+      (Tetromino::L, 34),
+    ] {
+      let spots = finesse_spots(tetromino);
+      assert_eq!(spots.len(), count, "{:?}", tetromino);
+      assert!(
+        spots.iter().all(|spot| spot.path.len() <= 3),
+        "{:?}",
+        tetromino
+      );
+    }
+
+    let t_spots = finesse_spots(Tetromino::T);
+    let left_wall = t_spots
+      .iter()
+      .find(|spot| spot.piece.orientation == Orientation::Up && spot.piece.position.0 == 1)
+      .unwrap();
+    assert_eq!(left_wall.path, [Press::DasLeft]);
+  }
+
+  #[test]
+  fn extra_presses_are_faults() {
+    let mut playfield = Playfield::scripted("TTT", GameMode::Endless);
+    play(&mut playfield, &[Press::Left; 4]);
+    let last = playfield.finesse.last.clone().unwrap();
+    assert_eq!((last.presses, last.faults()), (4, 3));
+    assert_eq!(last.path, [Press::DasLeft]);
+
+    play(&mut playfield, &[Press::DasLeft]);
+    assert_eq!(playfield.finesse.last.as_ref().unwrap().faults(), 0);
+    assert_eq!(playfield.finesse.pieces(), 2);
+    assert_eq!(playfield.finesse.faults(), 3);
+    assert_eq!(playfield.finesse.counts[Tetromino::T as usize].pieces, 2);
+  }
+
+  #[test]
+  fn trainer_targets_each_piece_on_an_empty_field() {
+    let mut playfield = Playfield::scripted("TIOSZJL", GameMode::Finesse);
+    for _ in 0..14 {
+      let target = playfield.trainer.as_ref().unwrap().target.clone().unwrap();
+      assert_eq!(target.piece.tetromino, playfield.falling_piece.tetromino);
+      play(&mut playfield, &target.path);
+      assert!(playfield
+        .grid
+        .iter()
+        .flatten()
+        .all(|&mino| mino == Tetromino::Empty));
+    }
+    assert_eq!(playfield.trainer.as_ref().unwrap().misses, 0);
+    assert_eq!(
+      (playfield.finesse.pieces(), playfield.finesse.faults()),
+      (14, 0)
+    );
+
+    // Dropping a piece where it spawns misses, unless that happened to be the target
+    let target = playfield.trainer.as_ref().unwrap().target.clone().unwrap();
+    play(&mut playfield, &[]);
+    let misses = playfield.trainer.as_ref().unwrap().misses;
+    assert_eq!(misses, !target.path.is_empty() as u32);
+  }
+}
@@ -0,0 +1,75 @@
+use super::{falling_piece::Tetromino, Playfield};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+pub type GarbageRow = [Tetromino; Playfield::WIDTH];
+
+// Deals rows of garbage, full except for a single hole. Messiness is the chance, in
+// percent, that the hole moves between one row and the next. Its generator runs on
+// its own stream of the game's seed, so garbage never changes the pieces dealt.
+#[derive(Clone, Debug)]
+pub struct Garbage {
+  rng: ChaCha8Rng,
+  hole: usize,
+  messiness: u8,
+}
+
+impl Garbage {
+  pub fn new(seed: u64, messiness: u8) -> Self {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_stream(1);
+    let hole = rng.gen_range(0..Playfield::WIDTH);
+    Self {
+      rng,
+      hole,
+      messiness,
+    }
+  }
+
+  pub fn next_row(&mut self) -> GarbageRow {
+    if self.rng.gen_range(0..100) < self.messiness {
+      self.move_hole();
+    }
+
+    let mut row = [Tetromino::Garbage; Playfield::WIDTH];
+    row[self.hole] = Tetromino::Empty;
+    row
+  }
+
+  // Moves the hole to any column but the one it is in now
+  pub fn move_hole(&mut self) {
+    let hole = self.rng.gen_range(0..Playfield::WIDTH - 1);
+    self.hole = if hole >= self.hole { hole + 1 } else { hole };
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn holes(messiness: u8, count: usize) -> Vec<usize> {
+    let mut garbage = Garbage::new(11, messiness);
+    (0..count)
+      .map(|_| {
+        let row = garbage.next_row();
+        assert_eq!(
+          row.iter().filter(|&&mino| mino == Tetromino::Empty).count(),
+          1
+        );
+        row
+          .iter()
+          .position(|&mino| mino == Tetromino::Empty)
+          .unwrap()
+      })
+      .collect()
+  }
+
+  #[test]
+  fn messiness_decides_how_often_the_hole_moves() {
+    let clean = holes(0, 50);
+    assert!(clean.iter().all(|&hole| hole == clean[0]));
+
+    let messy = holes(100, 50);
+    assert!(messy.windows(2).all(|pair| pair[0] != pair[1]));
+  }
+}
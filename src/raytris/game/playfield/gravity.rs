@@ -0,0 +1,79 @@
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GravityCurve {
+  Guideline,
+  Nes,
+  TwentyG,
+}
+
+impl GravityCurve {
+  const MAX_CELLS_PER_FRAME: f32 = 20.0;
+  // The guideline formula is only meant up to here, past it the speed goes negative
+  const GUIDELINE_MAX_LEVEL: u32 = 20;
+  const NES_FRAMES_PER_CELL: [u8; 30] = [
+    48, 43, 38, 33, 28, 23, 18, 13, 8, 6, 5, 5, 5, 4, 4, 4, 3, 3, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+    1,
+  ];
+
+  pub fn next(&self) -> Self {
+    match self {
+      Self::Guideline => Self::Nes,
+      Self::Nes => Self::TwentyG,
+      Self::TwentyG => Self::Guideline,
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Self::Guideline => "Guideline",
+      Self::Nes => "NES",
+      Self::TwentyG => "20G",
+    }
+  }
+
+  // Levels start at 1. Any speed above 20G is the same as 20G, since the piece
+  // reaches the floor of the visible field in a single frame.
+  pub fn cells_per_frame(&self, level: u32) -> f32 {
+    let level = level.max(1);
+    let cells_per_frame = match self {
+      Self::Guideline => {
+        let exponent = (level.min(Self::GUIDELINE_MAX_LEVEL) - 1) as f32;
+        let seconds_per_cell = (0.8 - exponent * 0.007).powf(exponent);
+        1.0 / (seconds_per_cell * 60.0)
+      }
+      Self::Nes => {
+        let index = (level as usize - 1).min(Self::NES_FRAMES_PER_CELL.len() - 1);
+        1.0 / Self::NES_FRAMES_PER_CELL[index] as f32
+      }
+      Self::TwentyG => Self::MAX_CELLS_PER_FRAME,
+    };
+
+    cells_per_frame.min(Self::MAX_CELLS_PER_FRAME)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn pieces_keep_falling_at_any_level() {
+    for gravity in [
+      GravityCurve::Guideline,
+      GravityCurve::Nes,
+      GravityCurve::TwentyG,
+    ] {
+      let mut last = 0.0;
+      for level in 1..1000 {
+        let cells_per_frame = gravity.cells_per_frame(level);
+        assert!(
+          cells_per_frame >= last,
+          "{:?} slows down at {}",
+          gravity,
+          level
+        );
+        assert!(cells_per_frame <= GravityCurve::MAX_CELLS_PER_FRAME);
+        last = cells_per_frame;
+      }
+    }
+  }
+}
@@ -0,0 +1,62 @@
+use std::ops::RangeInclusive;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SoftDropSpeed {
+  Factor(u8),
+  Instant,
+}
+
+// Every duration is measured in frames, at 60 frames per second.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct HandlingSettings {
+  pub das: u8,
+  pub arr: u8,
+  pub dcd: u8,
+  pub sdf: SoftDropSpeed,
+  pub lock_delay: u8,
+  pub max_lock_resets: u8,
+}
+
+impl HandlingSettings {
+  pub const DAS_RANGE: RangeInclusive<u8> = 0..=60;
+  pub const ARR_RANGE: RangeInclusive<u8> = 0..=20;
+  pub const DCD_RANGE: RangeInclusive<u8> = 0..=20;
+  pub const LOCK_DELAY_RANGE: RangeInclusive<u8> = 1..=120;
+  pub const MAX_LOCK_RESETS_RANGE: RangeInclusive<u8> = 0..=30;
+  pub const MAX_SOFT_DROP_FACTOR: u8 = 40;
+
+  pub fn new() -> Self {
+    Self {
+      das: 7,
+      arr: 0,
+      dcd: 0,
+      sdf: SoftDropSpeed::Factor(20),
+      lock_delay: 30,
+      max_lock_resets: 15,
+    }
+  }
+}
+
+impl SoftDropSpeed {
+  pub fn cells_per_frame(&self, gravity: f32) -> f32 {
+    match self {
+      Self::Factor(factor) => gravity * *factor as f32,
+      Self::Instant => f32::INFINITY,
+    }
+  }
+
+  pub fn increase(&self) -> Self {
+    match self {
+      Self::Factor(factor) if *factor >= HandlingSettings::MAX_SOFT_DROP_FACTOR => Self::Instant,
+      Self::Factor(factor) => Self::Factor(factor + 1),
+      Self::Instant => Self::Instant,
+    }
+  }
+
+  pub fn decrease(&self) -> Self {
+    match self {
+      Self::Factor(factor) => Self::Factor(factor.saturating_sub(1).max(1)),
+      Self::Instant => Self::Factor(HandlingSettings::MAX_SOFT_DROP_FACTOR),
+    }
+  }
+}
@@ -0,0 +1,73 @@
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+  ShiftLeft,
+  ShiftRight,
+  RotateClockwise,
+  RotateCounterClockwise,
+  RotateOneEighty,
+  SoftDrop,
+  HardDrop,
+  Hold,
+  Restart,
+}
+
+impl Action {
+  const fn bit(self) -> u16 {
+    1 << self as u16
+  }
+}
+
+// Everything the playfield needs to know about the player for a single frame.
+// `down` holds the actions being held, `pressed` the ones that started this frame.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct InputFrame {
+  down: u16,
+  pressed: u16,
+}
+
+impl InputFrame {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn press(&mut self, action: Action) {
+    self.down |= action.bit();
+    self.pressed |= action.bit();
+  }
+
+  pub fn hold(&mut self, action: Action) {
+    self.down |= action.bit();
+  }
+
+  pub fn release(&mut self, action: Action) {
+    self.down &= !action.bit();
+    self.pressed &= !action.bit();
+  }
+
+  // The same actions still held on the next frame, none of them pressed again
+  pub fn held(self) -> Self {
+    Self {
+      down: self.down,
+      pressed: 0,
+    }
+  }
+
+  pub fn is_down(self, action: Action) -> bool {
+    self.down & action.bit() != 0
+  }
+
+  pub fn is_pressed(self, action: Action) -> bool {
+    self.pressed & action.bit() != 0
+  }
+
+  pub fn bits(self) -> u32 {
+    self.down as u32 | (self.pressed as u32) << 16
+  }
+
+  pub fn from_bits(bits: u32) -> Self {
+    Self {
+      down: bits as u16,
+      pressed: (bits >> 16) as u16,
+    }
+  }
+}
@@ -0,0 +1,186 @@
+use super::Playfield;
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GameMode {
+  Endless,
+  Sprint,
+  Ultra,
+  Marathon { start_level: u32, goal: Option<u32> },
+  Dig { goal: u32, messiness: u8 },
+  Survival,
+  // Placing pieces on an empty field, at spots picked by the finesse trainer
+  Finesse,
+  Versus,
+}
+
+// What a finished game is ranked by
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Record {
+  Time(u32),
+  Score(u64),
+}
+
+impl GameMode {
+  pub const SPRINT_LINES: u32 = 40;
+  pub const ULTRA_FRAMES: u32 = 120 * 60;
+  pub const MARATHON_GOALS: [Option<u32>; 3] = [Some(150), Some(200), None];
+  pub const MAX_START_LEVEL: u32 = 15;
+  pub const DIG_GOALS: [u32; 3] = [10, 40, 100];
+  pub const DIG_MESSINESS: [u8; 4] = [0, 25, 50, 100];
+  // Dig keeps this many rows of garbage on the field, until there are none left to dig
+  pub const DIG_ROWS: u32 = 10;
+  // Survival raises a row every ten seconds at first, then a third of a second
+  // sooner with every row, down to a row every second
+  const SURVIVAL_FIRST_INTERVAL: u32 = 600;
+  const SURVIVAL_SPEEDUP: u32 = 20;
+  const SURVIVAL_LAST_INTERVAL: u32 = 60;
+  const SURVIVAL_MESSINESS: u8 = 30;
+
+  pub fn next(&self) -> Self {
+    match self {
+      Self::Endless => Self::Sprint,
+      Self::Sprint => Self::Ultra,
+      Self::Ultra => Self::Marathon {
+        start_level: 1,
+        goal: Self::MARATHON_GOALS[0],
+      },
+      Self::Marathon { .. } => Self::Dig {
+        goal: Self::DIG_GOALS[0],
+        messiness: Self::DIG_MESSINESS[0],
+      },
+      Self::Dig { .. } => Self::Survival,
+      Self::Survival => Self::Finesse,
+      Self::Finesse => Self::Versus,
+      Self::Versus => Self::Endless,
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Self::Endless => "Endless",
+      Self::Sprint => "Sprint",
+      Self::Ultra => "Ultra",
+      Self::Marathon { .. } => "Marathon",
+      Self::Dig { .. } => "Dig",
+      Self::Survival => "Survival",
+      Self::Finesse => "Finesse",
+      Self::Versus => "Versus",
+    }
+  }
+
+  // The name along with the options of the mode, which are ranked separately
+  pub fn description(&self) -> String {
+    match self {
+      Self::Marathon { start_level, goal } => match goal {
+        Some(goal) => format!("Marathon from level {} to {} lines", start_level, goal),
+        None => format!("Endless marathon from level {}", start_level),
+      },
+      Self::Dig { goal, messiness } => format!("Dig {} lines, {}% messy", goal, messiness),
+      _ => self.name().to_string(),
+    }
+  }
+
+  pub fn messiness(&self) -> u8 {
+    match self {
+      Self::Dig { messiness, .. } => *messiness,
+      Self::Survival => Self::SURVIVAL_MESSINESS,
+      _ => 0,
+    }
+  }
+
+  // Frames until the next row of garbage rises, once `rows` have already risen
+  pub fn garbage_interval(&self, rows: u32) -> Option<u32> {
+    match self {
+      Self::Survival => Some(
+        Self::SURVIVAL_FIRST_INTERVAL
+          .saturating_sub(rows.saturating_mul(Self::SURVIVAL_SPEEDUP))
+          .max(Self::SURVIVAL_LAST_INTERVAL),
+      ),
+      _ => None,
+    }
+  }
+
+  // How many rows of garbage have to be added to the field right now
+  pub fn missing_garbage(&self, playfield: &Playfield) -> u32 {
+    match self {
+      Self::Dig { goal, .. } => {
+        let on_field = playfield.garbage_rows();
+        let left = goal.saturating_sub(playfield.garbage_cleared + on_field);
+        Self::DIG_ROWS.saturating_sub(on_field).min(left)
+      }
+      _ => 0,
+    }
+  }
+
+  pub fn start_level(&self) -> u32 {
+    match self {
+      Self::Marathon { start_level, .. } => *start_level,
+      _ => 1,
+    }
+  }
+
+  // Whether the player has reached the goal of the mode
+  pub fn is_complete(&self, playfield: &Playfield) -> bool {
+    match self {
+      Self::Endless | Self::Survival | Self::Finesse | Self::Versus => false,
+      Self::Sprint => playfield.stats.lines >= Self::SPRINT_LINES,
+      Self::Ultra => playfield.stats.frames >= Self::ULTRA_FRAMES,
+      Self::Marathon { goal, .. } => goal.is_some_and(|goal| playfield.stats.lines >= goal),
+      Self::Dig { goal, .. } => playfield.garbage_cleared >= *goal,
+    }
+  }
+
+  // Games without a goal have nothing to rank them by
+  pub fn record(&self, playfield: &Playfield) -> Option<Record> {
+    match self {
+      Self::Endless | Self::Survival | Self::Finesse | Self::Versus => None,
+      Self::Sprint | Self::Dig { .. } => Some(Record::Time(playfield.stats.frames)),
+      Self::Ultra | Self::Marathon { goal: Some(_), .. } => Some(Record::Score(playfield.score)),
+      Self::Marathon { goal: None, .. } => None,
+    }
+  }
+}
+
+impl Record {
+  pub fn value(self) -> u64 {
+    match self {
+      Self::Time(frames) => frames as u64,
+      Self::Score(score) => score,
+    }
+  }
+
+  // A record of the same kind, for values read back from the records file
+  pub fn with_value(self, value: u64) -> Self {
+    match self {
+      Self::Time(_) => Self::Time(value.min(u32::MAX as u64) as u32),
+      Self::Score(_) => Self::Score(value),
+    }
+  }
+
+  pub fn beats(self, other: Self) -> bool {
+    match self {
+      Self::Time(_) => self.value() < other.value(),
+      Self::Score(_) => self.value() > other.value(),
+    }
+  }
+}
+
+// Times are counted in frames, at 60 frames per second
+impl fmt::Display for Record {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Self::Time(frames) => {
+        let milliseconds = frames as u64 * 1000 / 60;
+        write!(
+          f,
+          "{}:{:02}.{:03}",
+          milliseconds / 60_000,
+          milliseconds / 1000 % 60,
+          milliseconds % 1000
+        )
+      }
+      Self::Score(score) => write!(f, "{}", score),
+    }
+  }
+}
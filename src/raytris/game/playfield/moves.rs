@@ -0,0 +1,266 @@
+use super::{
+  falling_piece::{FallingPiece, Orientation, RotationType, Shift, Tetromino},
+  input::Action,
+  Playfield, SpinType,
+};
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
+
+// Somewhere a piece can lock, and the fewest inputs that take it there. The
+// inputs start with a hold when the held piece is the one placed, and end with a
+// hard drop. A soft drop stands for holding it until the piece lands.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Placement {
+  pub piece: FallingPiece,
+  pub hold: bool,
+  pub inputs: Vec<Action>,
+  pub spin_type: SpinType,
+  // The kick of the last move, if it was a rotation, so placing it spins the same
+  kick: Option<usize>,
+}
+
+// A piece on its way, and the kick of its last move if it was a rotation
+#[derive(Clone, Copy)]
+struct State {
+  piece: FallingPiece,
+  kick: Option<usize>,
+}
+
+impl State {
+  // Only whether the last kick was used matters later on, for spins
+  fn key(&self) -> ((i8, i8), Orientation, Option<bool>) {
+    (
+      self.piece.position,
+      self.piece.orientation,
+      self.kick.map(|kick| kick == Playfield::LAST_KICK),
+    )
+  }
+}
+
+impl Playfield {
+  const MOVES: [Action; 6] = [
+    Action::ShiftLeft,
+    Action::ShiftRight,
+    Action::RotateClockwise,
+    Action::RotateCounterClockwise,
+    Action::RotateOneEighty,
+    Action::SoftDrop,
+  ];
+
+  // Every distinct spot the falling piece, or the one hold would swap in, can
+  // lock in. Pieces are moved as if gravity didn't get a say, unless it pulls
+  // them down a cell every frame, in which case they land after every move.
+  pub fn placements(&self) -> Vec<Placement> {
+    let mut placements = Vec::new();
+    if self.falling_piece.tetromino == Tetromino::Empty || self.has_lost {
+      return placements;
+    }
+
+    let kick = self.last_move_was_rotation.then_some(self.last_kick);
+    self.search_placements(self.falling_piece, kick, false, &mut placements);
+    if self.can_swap {
+      let held = match self.holding_piece {
+        Tetromino::Empty => self.next_queue[0],
+        held => held,
+      };
+      let piece = FallingPiece::new(held, Self::PIECE_SPAWN_POSITION);
+      if self.fits(&piece) {
+        self.search_placements(piece, None, true, &mut placements);
+      }
+    }
+    placements
+  }
+
+  // Breadth first, so the first way found to any spot is the shortest
+  fn search_placements(
+    &self,
+    piece: FallingPiece,
+    kick: Option<usize>,
+    hold: bool,
+    placements: &mut Vec<Placement>,
+  ) {
+    let lands_at_once = self.rules.gravity.cells_per_frame(self.level) >= 1.0;
+    let settle = |state: State| {
+      if lands_at_once {
+        self.soft_dropped(state).unwrap_or(state)
+      } else {
+        state
+      }
+    };
+
+    let start = settle(State { piece, kick });
+    let mut paths = HashMap::new();
+    paths.insert(start.key(), if hold { vec![Action::Hold] } else { vec![] });
+    let mut states = VecDeque::from([start]);
+    let mut spots = HashSet::new();
+    while let Some(state) = states.pop_front() {
+      let path = paths[&state.key()].clone();
+
+      // A hard drop keeps the last move, so a spin in the air still counts
+      let landed = self.get_landed(state.piece);
+      let spin_type = self.spin_type(&landed, state.kick);
+      let mut spot = landed.cells();
+      spot.sort();
+      if spots.insert((spot, spin_type)) {
+        let mut inputs = path.clone();
+        inputs.push(Action::HardDrop);
+        placements.push(Placement {
+          piece: landed,
+          hold,
+          inputs,
+          spin_type,
+          kick: state.kick,
+        });
+      }
+
+      for action in Self::MOVES {
+        let Some(next) = self.moved(state, action).map(settle) else {
+          continue;
+        };
+        if let Entry::Vacant(entry) = paths.entry(next.key()) {
+          let mut next_path = path.clone();
+          next_path.push(action);
+          entry.insert(next_path);
+          states.push_back(next);
+        }
+      }
+    }
+  }
+
+  // Where a move takes a piece, if it can move at all
+  fn moved(&self, state: State, action: Action) -> Option<State> {
+    let rotation_type = match action {
+      Action::ShiftLeft | Action::ShiftRight => {
+        let mut piece = state.piece;
+        piece.shift(match action {
+          Action::ShiftLeft => Shift::Left,
+          _ => Shift::Right,
+        });
+        return self.fits(&piece).then_some(State { piece, kick: None });
+      }
+      Action::SoftDrop => return self.soft_dropped(state),
+      Action::RotateClockwise => RotationType::Clockwise,
+      Action::RotateCounterClockwise => RotationType::CounterClockwise,
+      Action::RotateOneEighty => RotationType::OneEighty,
+      _ => return None,
+    };
+    let (piece, kick) = self.rotated(state.piece, rotation_type)?;
+    Some(State {
+      piece,
+      kick: Some(kick),
+    })
+  }
+
+  // Falling takes the rotation out of the last move, like it does under gravity
+  fn soft_dropped(&self, state: State) -> Option<State> {
+    let piece = self.get_landed(state.piece);
+    (piece != state.piece).then_some(State { piece, kick: None })
+  }
+
+  // Locks a piece where a placement puts it, as if its inputs had been played.
+  // No frames go by, so dropping it doesn't score.
+  pub fn place(&mut self, placement: &Placement) {
+    if placement.hold {
+      self.swap_tetromino();
+      if self.falling_piece.tetromino == Tetromino::Empty {
+        self.next_queue.get_next_tetromino();
+      }
+    }
+
+    self.falling_piece = placement.piece;
+    self.last_move_was_rotation = placement.kick.is_some();
+    self.last_kick = placement.kick.unwrap_or(0);
+    self.lock_falling_piece();
+    self.refill_garbage();
+    self.is_finished = self.rules.mode.is_complete(self);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::raytris::game::playfield::{input::InputFrame, mode::GameMode};
+
+  fn playfield(pieces: &str, rows: &[&str]) -> Playfield {
+    let mut playfield = Playfield::scripted(pieces, GameMode::Endless);
+    playfield.fill(rows);
+    playfield
+  }
+
+  // Plays the inputs a frame at a time, a tap every other frame and soft drop
+  // held until the piece lands
+  fn play(playfield: &mut Playfield, placement: &Placement) {
+    let pieces = playfield.stats.pieces;
+    for &action in &placement.inputs {
+      let mut input = InputFrame::new();
+      if action == Action::SoftDrop {
+        while playfield.falling_piece != playfield.get_ghost_piece() {
+          input.hold(Action::SoftDrop);
+          playfield.update(input);
+        }
+      } else {
+        input.press(action);
+        playfield.update(input);
+        playfield.update(InputFrame::new());
+      }
+    }
+    assert_eq!(playfield.stats.pieces, pieces + 1, "{:?}", placement.inputs);
+  }
+
+  #[test]
+  fn every_spot_on_an_empty_field() {
+    for (piece, spots) in [("O", 9), ("I", 17), ("T", 34), ("S", 17)] {
+      let playfield = playfield(piece, &[]);
+      let placements: Vec<_> = playfield
+        .placements()
+        .into_iter()
+        .filter(|placement| !placement.hold)
+        .collect();
+      assert_eq!(placements.len(), spots, "{}", piece);
+      assert_eq!(placements[0].inputs, [Action::HardDrop]);
+      // Taps move a cell each, so the walls are the furthest away
+      assert!(placements
+        .iter()
+        .all(|placement| placement.inputs.len() <= 7));
+    }
+  }
+
+  #[test]
+  fn placements_are_reached_by_their_inputs() {
+    let rows = [
+      "####.#####",
+      "###...####",
+      "#.....##..",
+      "#......#..",
+      ".......#..",
+    ];
+    for piece in "IOTSZJL".chars() {
+      let start = playfield(&format!("{}I", piece), &rows);
+      for placement in start.placements() {
+        let mut played = start.clone();
+        play(&mut played, &placement);
+        let mut placed = start.clone();
+        placed.place(&placement);
+        assert_eq!(played.grid, placed.grid, "{:?}", placement);
+        assert_eq!(played.stats.lines, placed.stats.lines);
+      }
+    }
+  }
+
+  #[test]
+  fn tucks_and_spins_are_found() {
+    // The T has to go under the overhang, then turn into the slot
+    let mut playfield = playfield("TI", &["####.#####", "###...####", "####......"]);
+    let t_spin = playfield
+      .placements()
+      .into_iter()
+      .find(|placement| placement.spin_type == SpinType::Proper)
+      .unwrap();
+    assert!(t_spin.inputs.contains(&Action::SoftDrop));
+    assert!(!t_spin.hold);
+
+    let lines = playfield.stats.lines;
+    playfield.place(&t_spin);
+    assert_eq!(playfield.stats.lines, lines + 2);
+    assert_eq!(playfield.falling_piece.tetromino, Tetromino::I);
+  }
+}
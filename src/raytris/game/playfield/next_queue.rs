@@ -1,46 +1,143 @@
-use std::ops::Index;
+use std::{collections::VecDeque, ops::Index};
 
-use super::falling_piece::Tetromino;
-use rand::{seq::SliceRandom, Rng};
+use super::{
+  falling_piece::Tetromino,
+  randomizer::{Randomizer, RandomizerKind},
+};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
 
+// The queue owns its random number generator, so the same seed always deals the
+// same pieces, and cloning the queue (for undo) also clones where it is in the sequence.
 #[derive(Clone, Debug)]
 pub struct NextQueue {
-  queue: Vec<Tetromino>,
+  queue: VecDeque<Tetromino>,
+  randomizer: Box<dyn Randomizer>,
+  rng: ChaCha8Rng,
 }
 
 impl NextQueue {
   pub const NEXT_QUEUE_SIZE: usize = 5;
-  pub const SIZE_OF_BAG: usize = 7;
 
-  pub fn new(rng: &mut impl Rng) -> Self {
-    use Tetromino::*;
-    let mut queue = vec![I, O, T, S, Z, L, J];
-    queue.shuffle(rng);
-    Self { queue }
-  }
-
-  fn push_new_bag(&mut self, rng: &mut impl Rng) {
-    use super::falling_piece::Tetromino::*;
-    let mut new_bag = vec![I, O, T, S, Z, J, L];
-    new_bag.shuffle(rng);
-    new_bag.append(&mut self.queue);
-    self.queue = new_bag;
+  pub fn new(seed: u64, kind: RandomizerKind) -> Self {
+    let mut next_queue = Self {
+      queue: VecDeque::new(),
+      randomizer: kind.build(),
+      rng: ChaCha8Rng::seed_from_u64(seed),
+    };
+    next_queue.fill();
+    next_queue
   }
 
-  pub fn push_new_bag_if_needed(&mut self, rng: &mut impl Rng) {
-    if self.queue.len() < Self::SIZE_OF_BAG {
-      self.push_new_bag(rng);
+  // Keeps the preview full, whatever the randomizer
+  fn fill(&mut self) {
+    while self.queue.len() <= Self::NEXT_QUEUE_SIZE {
+      let tetromino = self.randomizer.next_tetromino(&mut self.rng);
+      self.queue.push_back(tetromino);
     }
   }
 
   pub fn get_next_tetromino(&mut self) -> Tetromino {
-    self.queue.pop().unwrap()
+    let tetromino = self.queue.pop_front().unwrap();
+    self.fill();
+    tetromino
   }
 }
 
 impl Index<usize> for NextQueue {
   type Output = Tetromino;
   fn index(&self, index: usize) -> &Self::Output {
-    &self.queue[self.queue.len() - 1 - index]
+    &self.queue[index]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn deal(seed: u64, kind: RandomizerKind, count: usize) -> Vec<Tetromino> {
+    let mut next_queue = NextQueue::new(seed, kind);
+    (0..count)
+      .map(|_| next_queue.get_next_tetromino())
+      .collect()
+  }
+
+  fn assert_fair(pieces: &[Tetromino], bag_size: usize) {
+    for bag in pieces.chunks(bag_size) {
+      for tetromino in [
+        Tetromino::I,
+        Tetromino::O,
+        Tetromino::T,
+        Tetromino::S,
+        Tetromino::Z,
+        Tetromino::J,
+        Tetromino::L,
+      ] {
+        let copies = bag.iter().filter(|&&piece| piece == tetromino).count();
+        assert_eq!(copies, bag_size / 7, "{:?} in {:?}", tetromino, bag);
+      }
+    }
+  }
+
+  #[test]
+  fn seven_bag_deals_every_piece_once_per_bag() {
+    for seed in 0..20 {
+      assert_fair(&deal(seed, RandomizerKind::SevenBag, 7 * 20), 7);
+    }
+  }
+
+  #[test]
+  fn fourteen_bag_deals_every_piece_twice_per_bag() {
+    for seed in 0..20 {
+      assert_fair(&deal(seed, RandomizerKind::FourteenBag, 14 * 10), 14);
+    }
+  }
+
+  #[test]
+  fn same_seed_deals_the_same_pieces() {
+    for kind in [
+      RandomizerKind::SevenBag,
+      RandomizerKind::FourteenBag,
+      RandomizerKind::PureRandom,
+      RandomizerKind::History,
+      RandomizerKind::NesReroll,
+    ] {
+      assert_eq!(deal(7, kind.clone(), 100), deal(7, kind.clone(), 100));
+      assert_ne!(deal(7, kind.clone(), 100), deal(8, kind, 100));
+    }
+  }
+
+  #[test]
+  fn history_never_starts_with_an_overhang_piece() {
+    for seed in 0..200 {
+      let first = deal(seed, RandomizerKind::History, 1)[0];
+      assert!(![Tetromino::S, Tetromino::Z, Tetromino::O].contains(&first));
+    }
+  }
+
+  #[test]
+  fn scripted_sequence_repeats() {
+    let pieces = deal(0, RandomizerKind::scripted("tio").unwrap(), 6);
+    use Tetromino::*;
+    assert_eq!(pieces, [T, I, O, T, I, O]);
+    assert_eq!(RandomizerKind::scripted("TIX"), None);
+  }
+
+  #[test]
+  fn preview_shows_the_pieces_dealt_next() {
+    for kind in [RandomizerKind::SevenBag, RandomizerKind::NesReroll] {
+      let mut next_queue = NextQueue::new(3, kind);
+      for _ in 0..30 {
+        let preview: Vec<_> = (0..NextQueue::NEXT_QUEUE_SIZE)
+          .map(|id| next_queue[id])
+          .collect();
+        let mut future = next_queue.clone();
+        let upcoming: Vec<_> = (0..NextQueue::NEXT_QUEUE_SIZE)
+          .map(|_| future.get_next_tetromino())
+          .collect();
+        assert_eq!(upcoming, preview);
+        next_queue.get_next_tetromino();
+      }
+    }
   }
 }
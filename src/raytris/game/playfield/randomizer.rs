@@ -0,0 +1,229 @@
+use super::falling_piece::Tetromino;
+use rand::{seq::SliceRandom, Rng};
+use rand_chacha::ChaCha8Rng;
+use std::{collections::VecDeque, fmt::Debug};
+
+// Decides which piece comes next. All randomness has to come from `rng`, which
+// belongs to the next queue, so that a seed always deals the same pieces.
+pub trait Randomizer: Debug + Send {
+  fn next_tetromino(&mut self, rng: &mut ChaCha8Rng) -> Tetromino;
+  fn clone_box(&self) -> Box<dyn Randomizer>;
+}
+
+impl Clone for Box<dyn Randomizer> {
+  fn clone(&self) -> Self {
+    self.clone_box()
+  }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum RandomizerKind {
+  SevenBag,
+  FourteenBag,
+  PureRandom,
+  History,
+  NesReroll,
+  Scripted(Vec<Tetromino>),
+}
+
+impl RandomizerKind {
+  // Long enough for any drill, short enough to fit the rules in one netplay message
+  pub const MAX_SCRIPTED_LEN: usize = 1000;
+
+  // Scripted sequences come from game modes or the command line, not from the menu
+  pub fn next(&self) -> Self {
+    match self {
+      Self::SevenBag => Self::FourteenBag,
+      Self::FourteenBag => Self::PureRandom,
+      Self::PureRandom => Self::History,
+      Self::History => Self::NesReroll,
+      Self::NesReroll | Self::Scripted(_) => Self::SevenBag,
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Self::SevenBag => "7-bag",
+      Self::FourteenBag => "14-bag",
+      Self::PureRandom => "Random",
+      Self::History => "TGM history",
+      Self::NesReroll => "NES",
+      Self::Scripted(_) => "Scripted",
+    }
+  }
+
+  // Reads a sequence like "IOTSZJL", as given on the command line
+  pub fn scripted(letters: &str) -> Option<Self> {
+    if letters.chars().count() > Self::MAX_SCRIPTED_LEN {
+      return None;
+    }
+    let sequence = letters
+      .chars()
+      .map(Tetromino::from_letter)
+      .collect::<Option<Vec<_>>>()?;
+
+    (!sequence.is_empty()).then_some(Self::Scripted(sequence))
+  }
+
+  // The scripted sequence in the same letters `scripted` reads, empty for the others
+  pub fn letters(&self) -> String {
+    match self {
+      Self::Scripted(sequence) => sequence
+        .iter()
+        .filter_map(|tetromino| tetromino.letter())
+        .collect(),
+      _ => String::new(),
+    }
+  }
+
+  pub fn build(&self) -> Box<dyn Randomizer> {
+    match self {
+      Self::SevenBag => Box::new(Bag::new(1)),
+      Self::FourteenBag => Box::new(Bag::new(2)),
+      Self::PureRandom => Box::new(PureRandom),
+      Self::History => Box::new(History::new()),
+      Self::NesReroll => Box::new(NesReroll { last: None }),
+      Self::Scripted(sequence) => Box::new(Scripted {
+        sequence: sequence.clone(),
+        position: 0,
+      }),
+    }
+  }
+}
+
+// Every piece shows up `copies` times before the bag is refilled
+#[derive(Clone, Debug)]
+struct Bag {
+  copies: usize,
+  bag: Vec<Tetromino>,
+}
+
+impl Bag {
+  fn new(copies: usize) -> Self {
+    Self {
+      copies,
+      bag: vec![],
+    }
+  }
+}
+
+impl Randomizer for Bag {
+  fn next_tetromino(&mut self, rng: &mut ChaCha8Rng) -> Tetromino {
+    if self.bag.is_empty() {
+      self.bag = Tetromino::ALL.repeat(self.copies);
+      self.bag.shuffle(rng);
+    }
+    self.bag.pop().unwrap()
+  }
+
+  fn clone_box(&self) -> Box<dyn Randomizer> {
+    Box::new(self.clone())
+  }
+}
+
+#[derive(Clone, Debug)]
+struct PureRandom;
+
+impl Randomizer for PureRandom {
+  fn next_tetromino(&mut self, rng: &mut ChaCha8Rng) -> Tetromino {
+    *Tetromino::ALL.choose(rng).unwrap()
+  }
+
+  fn clone_box(&self) -> Box<dyn Randomizer> {
+    Box::new(self.clone())
+  }
+}
+
+// TGM: rolls up to `ROLLS` times to find a piece that is not among the last
+// four, and never starts with a piece that can only be placed with an overhang.
+#[derive(Clone, Debug)]
+struct History {
+  history: VecDeque<Tetromino>,
+  is_first: bool,
+}
+
+impl History {
+  const ROLLS: usize = 6;
+
+  fn new() -> Self {
+    use Tetromino::*;
+    Self {
+      history: VecDeque::from([Z, S, Z, S]),
+      is_first: true,
+    }
+  }
+}
+
+impl Randomizer for History {
+  fn next_tetromino(&mut self, rng: &mut ChaCha8Rng) -> Tetromino {
+    let tetromino = if self.is_first {
+      self.is_first = false;
+      *[Tetromino::I, Tetromino::T, Tetromino::J, Tetromino::L]
+        .choose(rng)
+        .unwrap()
+    } else {
+      let mut tetromino = *Tetromino::ALL.choose(rng).unwrap();
+      for _ in 1..Self::ROLLS {
+        if !self.history.contains(&tetromino) {
+          break;
+        }
+        tetromino = *Tetromino::ALL.choose(rng).unwrap();
+      }
+      tetromino
+    };
+
+    self.history.pop_front();
+    self.history.push_back(tetromino);
+    tetromino
+  }
+
+  fn clone_box(&self) -> Box<dyn Randomizer> {
+    Box::new(self.clone())
+  }
+}
+
+// NES: rolls an eighth "reroll" side, and rerolls once more on it or on a repeat
+#[derive(Clone, Debug)]
+struct NesReroll {
+  last: Option<Tetromino>,
+}
+
+impl Randomizer for NesReroll {
+  fn next_tetromino(&mut self, rng: &mut ChaCha8Rng) -> Tetromino {
+    let roll = rng.gen_range(0..=Tetromino::ALL.len());
+    let tetromino = match Tetromino::ALL.get(roll) {
+      Some(&tetromino) if Some(tetromino) != self.last => tetromino,
+      _ => *Tetromino::ALL.choose(rng).unwrap(),
+    };
+
+    self.last = Some(tetromino);
+    tetromino
+  }
+
+  fn clone_box(&self) -> Box<dyn Randomizer> {
+    Box::new(self.clone())
+  }
+}
+
+// Repeats a fixed sequence forever, for drills and reproducing bug reports
+#[derive(Clone, Debug)]
+struct Scripted {
+  sequence: Vec<Tetromino>,
+  position: usize,
+}
+
+impl Randomizer for Scripted {
+  fn next_tetromino(&mut self, rng: &mut ChaCha8Rng) -> Tetromino {
+    if self.sequence.is_empty() {
+      return PureRandom.next_tetromino(rng);
+    }
+
+    let tetromino = self.sequence[self.position];
+    self.position = (self.position + 1) % self.sequence.len();
+    tetromino
+  }
+
+  fn clone_box(&self) -> Box<dyn Randomizer> {
+    Box::new(self.clone())
+  }
+}
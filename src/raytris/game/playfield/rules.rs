@@ -0,0 +1,40 @@
+use super::{
+  gravity::GravityCurve, handling::HandlingSettings, mode::GameMode, randomizer::RandomizerKind,
+  scoring::ScoringSystem,
+};
+
+// Everything that decides how a game plays out besides the player's input
+#[derive(Clone, PartialEq, Debug)]
+pub struct Rules {
+  pub seed: u64,
+  pub randomizer: RandomizerKind,
+  pub scoring: ScoringSystem,
+  pub gravity: GravityCurve,
+  pub handling: HandlingSettings,
+  pub mode: GameMode,
+}
+
+impl Rules {
+  pub fn new(seed: u64) -> Self {
+    Self {
+      seed,
+      randomizer: RandomizerKind::SevenBag,
+      scoring: ScoringSystem::Guideline,
+      gravity: GravityCurve::Guideline,
+      handling: HandlingSettings::new(),
+      mode: GameMode::Endless,
+    }
+  }
+
+  // Games are only ranked against games played with the same rules. Handling is
+  // left out since it is up to the player, like their keymap.
+  pub fn record_name(&self) -> String {
+    format!(
+      "{}, {} pieces, {} scoring, {} gravity",
+      self.mode.description(),
+      self.randomizer.name(),
+      self.scoring.name(),
+      self.gravity.name()
+    )
+  }
+}
@@ -0,0 +1,195 @@
+use super::SpinType;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ScoringSystem {
+  Guideline,
+  Legacy,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LineClear {
+  pub lines: usize,
+  pub spin_type: SpinType,
+  pub is_all_clear: bool,
+}
+
+// The points earned by a single lock, split by where they came from
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Points {
+  pub clear: u64,
+  pub b2b: u64,
+  pub combo: u64,
+  pub all_clear: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct ClearCount {
+  pub count: u32,
+  pub points: u64,
+}
+
+// Where the score of a game came from, for the results screen
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ScoreBreakdown {
+  pub clears: [ClearCount; LineClear::NAMES.len()],
+  pub b2b: u64,
+  pub combo: u64,
+  pub all_clear: u64,
+  pub drops: u64,
+}
+
+impl LineClear {
+  pub const NAMES: [&'static str; 11] = [
+    "Single",
+    "Double",
+    "Triple",
+    "Tetris",
+    "T-Spin Mini",
+    "T-Spin Mini Single",
+    "T-Spin Mini Double",
+    "T-Spin",
+    "T-Spin Single",
+    "T-Spin Double",
+    "T-Spin Triple",
+  ];
+
+  // Where the clear goes in `NAMES`. Locking without a clear or a spin isn't one.
+  pub fn kind(&self) -> Option<usize> {
+    match (self.spin_type, self.lines) {
+      (SpinType::No, 0) => None,
+      (SpinType::No, lines) => Some(lines.min(4) - 1),
+      (SpinType::Mini, lines) => Some(4 + lines.min(2)),
+      (SpinType::Proper, lines) => Some(7 + lines.min(3)),
+    }
+  }
+
+  pub fn is_difficult(&self) -> bool {
+    self.lines == 4 || (self.lines > 0 && self.spin_type != SpinType::No)
+  }
+
+  fn base_score(&self) -> u64 {
+    match (self.spin_type, self.lines) {
+      (SpinType::Proper, 0) => 400,
+      (SpinType::Proper, 1) => 800,
+      (SpinType::Proper, 2) => 1200,
+      (SpinType::Proper, _) => 1600,
+      (SpinType::Mini, 0) => 100,
+      (SpinType::Mini, 1) => 200,
+      (SpinType::Mini, _) => 400,
+      (SpinType::No, 0) => 0,
+      (SpinType::No, 1) => 100,
+      (SpinType::No, 2) => 300,
+      (SpinType::No, 3) => 500,
+      (SpinType::No, _) => 800,
+    }
+  }
+}
+
+impl Points {
+  pub fn total(&self) -> u64 {
+    self.clear + self.b2b + self.combo + self.all_clear
+  }
+}
+
+impl ScoreBreakdown {
+  pub fn add(&mut self, clear: LineClear, points: Points) {
+    if let Some(kind) = clear.kind() {
+      self.clears[kind].count += 1;
+      self.clears[kind].points += points.clear;
+    }
+    self.b2b += points.b2b;
+    self.combo += points.combo;
+    self.all_clear += points.all_clear;
+  }
+}
+
+impl ScoringSystem {
+  pub fn next(&self) -> Self {
+    match self {
+      Self::Guideline => Self::Legacy,
+      Self::Legacy => Self::Guideline,
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Self::Guideline => "Guideline",
+      Self::Legacy => "Legacy",
+    }
+  }
+
+  // Updates the combo and back to back counters after a piece locks and returns the
+  // points it earned. Both counters are 1 on the first clear of a chain.
+  pub fn score_lock(&self, clear: LineClear, level: u64, combo: &mut u8, b2b: &mut u16) -> Points {
+    let mut points = Points::default();
+    if clear.lines == 0 {
+      *combo = 0;
+      points.clear = match self {
+        Self::Guideline => clear.base_score() * level,
+        Self::Legacy => clear.base_score(),
+      };
+      return points;
+    }
+
+    match self {
+      Self::Guideline => {
+        if clear.is_difficult() {
+          *b2b += 1;
+        } else {
+          *b2b = 0;
+        }
+        *combo += 1;
+
+        let is_b2b = clear.is_difficult() && *b2b >= 2;
+        points.clear = clear.base_score() * level;
+        if is_b2b {
+          points.b2b = points.clear / 2;
+        }
+        points.combo = 50 * (*combo as u64 - 1) * level;
+
+        if clear.is_all_clear {
+          points.all_clear = level
+            * match clear.lines {
+              1 => 800,
+              2 => 1200,
+              3 => 1800,
+              _ if is_b2b => 3200,
+              _ => 2000,
+            };
+        }
+
+        points
+      }
+      Self::Legacy => {
+        if clear.is_difficult() {
+          *b2b += 1;
+        } else {
+          *combo = 0;
+        }
+        *combo += 1;
+
+        let b2b_factor = if *b2b >= 2 { 1.5 } else { 1.0 };
+        points.combo = *combo as u64 * 50;
+        points.clear = clear.base_score();
+        points.b2b = (clear.base_score() as f64 * b2b_factor) as u64 - points.clear;
+
+        if clear.is_all_clear {
+          points.all_clear = (3500.0 * b2b_factor) as u64;
+        }
+
+        points
+      }
+    }
+  }
+
+  pub fn soft_drop_points(&self, cells: u64) -> u64 {
+    match self {
+      Self::Guideline => cells,
+      Self::Legacy => 0,
+    }
+  }
+
+  pub fn hard_drop_points(&self, cells: u64) -> u64 {
+    2 * cells
+  }
+}
@@ -0,0 +1,240 @@
+use super::{
+  falling_piece::Tetromino,
+  input::{Action, InputFrame},
+  scoring::LineClear,
+  Playfield, SpinType,
+};
+
+// How a game is going, kept up to date by the playfield as pieces lock, so it
+// can be shown while playing and looked back at once the game is over
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Stats {
+  pub frames: u32,
+  pub pieces: u32,
+  pub lines: u32,
+  // Lines the clears would send in versus, whatever the mode
+  pub attack: u32,
+  pub keys: u32,
+  pub finesse_faults: u32,
+  // Proper T-spins by the lines they cleared, from none to three
+  pub t_spins: [u32; 4],
+  pub t_spin_minis: u32,
+  pub max_combo: u8,
+  // Counted like the HUD does, from the second difficult clear in a row
+  pub max_b2b: u16,
+}
+
+impl Stats {
+  // Restarting isn't played, so it isn't a key
+  const KEYS: [Action; 8] = [
+    Action::ShiftLeft,
+    Action::ShiftRight,
+    Action::RotateClockwise,
+    Action::RotateCounterClockwise,
+    Action::RotateOneEighty,
+    Action::SoftDrop,
+    Action::HardDrop,
+    Action::Hold,
+  ];
+
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn record(&mut self, input: InputFrame) {
+    self.frames += 1;
+    self.keys += Self::KEYS
+      .iter()
+      .filter(|&&action| input.is_pressed(action))
+      .count() as u32;
+  }
+
+  // `combo` and `b2b` are the counters after the lock
+  pub fn lock(&mut self, clear: LineClear, attack: u32, combo: u8, b2b: u16) {
+    self.pieces += 1;
+    self.lines += clear.lines as u32;
+    self.attack += attack;
+    match clear.spin_type {
+      SpinType::Proper => self.t_spins[clear.lines.min(3)] += 1,
+      SpinType::Mini => self.t_spin_minis += 1,
+      SpinType::No => {}
+    }
+    self.max_combo = self.max_combo.max(combo);
+    self.max_b2b = self.max_b2b.max(b2b.saturating_sub(1));
+  }
+
+  fn seconds(&self) -> f32 {
+    self.frames as f32 / 60.0
+  }
+
+  pub fn pieces_per_second(&self) -> f32 {
+    if self.frames == 0 {
+      return 0.0;
+    }
+    self.pieces as f32 / self.seconds()
+  }
+
+  pub fn attack_per_minute(&self) -> f32 {
+    if self.frames == 0 {
+      return 0.0;
+    }
+    60.0 * self.attack as f32 / self.seconds()
+  }
+
+  pub fn keys_per_piece(&self) -> f32 {
+    if self.pieces == 0 {
+      return 0.0;
+    }
+    self.keys as f32 / self.pieces as f32
+  }
+}
+
+// The pieces placed and the height of the stack every second, for graphs of
+// how a game went
+#[derive(Clone, Debug, Default)]
+pub struct Timeline {
+  samples: Vec<Sample>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Sample {
+  frames: u32,
+  pieces: u32,
+  height: usize,
+}
+
+impl Timeline {
+  const INTERVAL: u32 = 60;
+  // Seconds the pieces per second are averaged over, so the graph isn't all spikes
+  const WINDOW: usize = 5;
+
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  // The clock going back means the playfield restarted, so the samples of the
+  // last game are dropped
+  pub fn record(&mut self, playfield: &Playfield) {
+    let frames = playfield.stats.frames;
+    if self.samples.last().is_some_and(|last| last.frames > frames) {
+      self.samples.clear();
+    }
+    let is_recorded = self
+      .samples
+      .last()
+      .is_some_and(|last| last.frames == frames);
+    if frames == 0 || !frames.is_multiple_of(Self::INTERVAL) || is_recorded {
+      return;
+    }
+
+    let height = playfield
+      .grid
+      .iter()
+      .position(|row| row.iter().any(|&mino| mino != Tetromino::Empty))
+      .map_or(0, |j| Playfield::HEIGHT - j);
+    self.samples.push(Sample {
+      frames,
+      pieces: playfield.stats.pieces,
+      height,
+    });
+  }
+
+  // Forgets the samples taken after `frames`, when a move is undone
+  pub fn rewind(&mut self, frames: u32) {
+    self.samples.retain(|sample| sample.frames <= frames);
+  }
+
+  pub fn pieces_per_second(&self) -> Vec<f32> {
+    (0..self.samples.len())
+      .map(|id| {
+        let sample = self.samples[id];
+        let (frames, pieces) = match id.checked_sub(Self::WINDOW) {
+          Some(start) => (self.samples[start].frames, self.samples[start].pieces),
+          None => (0, 0),
+        };
+        let seconds = sample.frames.saturating_sub(frames) as f32 / 60.0;
+        if seconds > 0.0 {
+          sample.pieces.saturating_sub(pieces) as f32 / seconds
+        } else {
+          0.0
+        }
+      })
+      .collect()
+  }
+
+  pub fn heights(&self) -> Vec<f32> {
+    self
+      .samples
+      .iter()
+      .map(|sample| sample.height as f32)
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::raytris::game::playfield::mode::GameMode;
+
+  #[test]
+  fn rates_follow_the_counts() {
+    let mut stats = Stats::new();
+    assert_eq!(stats.pieces_per_second(), 0.0);
+    assert_eq!(stats.keys_per_piece(), 0.0);
+
+    let mut input = InputFrame::new();
+    input.press(Action::ShiftLeft);
+    input.press(Action::HardDrop);
+    input.press(Action::Restart);
+    stats.record(input);
+    for _ in 1..120 {
+      stats.record(input.held());
+    }
+    let clear = LineClear {
+      lines: 2,
+      spin_type: SpinType::Proper,
+      is_all_clear: false,
+    };
+    stats.lock(clear, 4, 1, 1);
+    stats.lock(clear, 5, 2, 2);
+
+    assert_eq!(stats.keys, 2);
+    assert_eq!(stats.pieces_per_second(), 1.0);
+    assert_eq!(stats.attack_per_minute(), 270.0);
+    assert_eq!(stats.keys_per_piece(), 1.0);
+    assert_eq!(stats.t_spins, [0, 0, 2, 0]);
+    assert_eq!((stats.max_combo, stats.max_b2b), (2, 1));
+  }
+
+  #[test]
+  fn timeline_samples_every_second() {
+    let mut playfield = Playfield::scripted("O", GameMode::Endless);
+    let mut timeline = Timeline::new();
+    for frame in 1..=360 {
+      let mut input = InputFrame::new();
+      if frame % 60 == 30 {
+        input.press(Action::HardDrop);
+      }
+      playfield.update(input);
+      timeline.record(&playfield);
+    }
+
+    assert_eq!(timeline.pieces_per_second(), [1.0; 6]);
+    assert_eq!(timeline.heights(), [2.0, 4.0, 6.0, 8.0, 10.0, 12.0]);
+    timeline.rewind(150);
+    assert_eq!(timeline.heights(), [2.0, 4.0]);
+
+    let mut restart = InputFrame::new();
+    restart.press(Action::Restart);
+    for frame in 0..60 {
+      playfield.update(if frame == 0 {
+        restart
+      } else {
+        InputFrame::new()
+      });
+      timeline.record(&playfield);
+    }
+    assert_eq!(timeline.heights(), [0.0]);
+    assert_eq!(timeline.pieces_per_second(), [0.0]);
+  }
+}
@@ -0,0 +1,558 @@
+use super::playfield::{
+  gravity::GravityCurve,
+  handling::{HandlingSettings, SoftDropSpeed},
+  input::InputFrame,
+  mode::GameMode,
+  randomizer::RandomizerKind,
+  rules::Rules,
+  scoring::ScoringSystem,
+  stats::Stats,
+  Playfield,
+};
+use crate::raytris::settings::config_directory;
+use std::{
+  fmt,
+  fs::{self, OpenOptions},
+  io::{self, Write},
+  path::{Path, PathBuf},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+// Everything needed to play a game again exactly as it happened: the rules it was
+// played with and the input of every frame the playfield was updated.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Replay {
+  pub rules: Rules,
+  // How the game went by the end of the recording, kept with it as its results
+  pub stats: Stats,
+  frames: Vec<InputFrame>,
+  checksum: u64,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+  Io(io::Error),
+  NotAReplay,
+  UnsupportedVersion(u8),
+  Corrupt,
+  Desync,
+}
+
+impl fmt::Display for ReplayError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Io(error) => write!(f, "{}", error),
+      Self::NotAReplay => write!(f, "not a replay file"),
+      Self::UnsupportedVersion(version) => write!(f, "replay version {} is not supported", version),
+      Self::Corrupt => write!(f, "the replay file is damaged"),
+      Self::Desync => write!(f, "the replay does not end the way it was recorded"),
+    }
+  }
+}
+
+impl From<io::Error> for ReplayError {
+  fn from(error: io::Error) -> Self {
+    Self::Io(error)
+  }
+}
+
+impl Replay {
+  const MAGIC: &'static [u8; 3] = b"RTR";
+  const VERSION: u8 = 1;
+  const EXTENSION: &'static str = "rtr";
+  // A day of play, far more than any game lasts. Anything longer is a damaged
+  // file or a peer that means harm, not something to allocate for.
+  const MAX_FRAMES: usize = 24 * 60 * 60 * 60;
+
+  pub fn new(rules: Rules) -> Self {
+    Self {
+      rules,
+      stats: Stats::new(),
+      frames: vec![],
+      checksum: 0,
+    }
+  }
+
+  pub fn playfield(&self) -> Playfield {
+    Playfield::new(self.rules.clone())
+  }
+
+  pub fn len(&self) -> usize {
+    self.frames.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.frames.is_empty()
+  }
+
+  pub fn frame(&self, id: usize) -> InputFrame {
+    self.frames[id]
+  }
+
+  pub fn push(&mut self, input: InputFrame) {
+    self.frames.push(input);
+  }
+
+  // Undoing a move also takes the frames that led to it out of the recording
+  pub fn truncate(&mut self, len: usize) {
+    self.frames.truncate(len);
+  }
+
+  pub fn finish(&mut self, playfield: &Playfield) {
+    self.checksum = playfield.checksum();
+    self.stats = playfield.stats.clone();
+  }
+
+  pub fn simulate(&self) -> Playfield {
+    let mut playfield = self.playfield();
+    for &input in &self.frames {
+      playfield.update(input);
+    }
+    playfield
+  }
+
+  pub fn save(&self) -> Result<PathBuf, ReplayError> {
+    let directory = Self::directory()?;
+    fs::create_dir_all(&directory)?;
+
+    let seconds = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_or(0, |time| time.as_secs());
+    // Games saved within the same second get numbered instead of overwriting each other
+    let mut path = directory.join(format!("replay-{}.{}", seconds, Self::EXTENSION));
+    let mut copy = 1;
+    let mut file = loop {
+      match OpenOptions::new().write(true).create_new(true).open(&path) {
+        Ok(file) => break file,
+        Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+          copy += 1;
+          path = directory.join(format!("replay-{}-{}.{}", seconds, copy, Self::EXTENSION));
+        }
+        Err(error) => return Err(error.into()),
+      }
+    };
+    file.write_all(&self.to_bytes())?;
+    Ok(path)
+  }
+
+  pub fn load(path: &Path) -> Result<Self, ReplayError> {
+    let replay = Self::from_bytes(&fs::read(path)?)?;
+    let playfield = replay.simulate();
+    if playfield.checksum() != replay.checksum || playfield.stats != replay.stats {
+      return Err(ReplayError::Desync);
+    }
+    Ok(replay)
+  }
+
+  pub fn latest() -> Result<PathBuf, ReplayError> {
+    fs::read_dir(Self::directory()?)?
+      .filter_map(|entry| entry.ok())
+      .map(|entry| entry.path())
+      .filter(|path| {
+        path
+          .extension()
+          .is_some_and(|extension| extension == Self::EXTENSION)
+      })
+      .max_by_key(|path| {
+        fs::metadata(path)
+          .and_then(|metadata| metadata.modified())
+          .ok()
+      })
+      .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no replays yet").into())
+  }
+
+  fn directory() -> Result<PathBuf, ReplayError> {
+    match config_directory() {
+      Some(directory) => Ok(directory.join("replays")),
+      None => Err(io::Error::new(io::ErrorKind::NotFound, "no configuration directory").into()),
+    }
+  }
+
+  // The header holds the rules, then the inputs follow as runs of identical frames,
+  // since most frames repeat the one before.
+  pub(super) fn to_bytes(&self) -> Vec<u8> {
+    let mut bytes = Self::MAGIC.to_vec();
+    bytes.push(Self::VERSION);
+    let rules = &self.rules;
+    bytes.extend(rules.seed.to_le_bytes());
+    bytes.extend(self.checksum.to_le_bytes());
+    bytes.push(match rules.scoring {
+      ScoringSystem::Guideline => 0,
+      ScoringSystem::Legacy => 1,
+    });
+    bytes.push(match rules.gravity {
+      GravityCurve::Guideline => 0,
+      GravityCurve::Nes => 1,
+      GravityCurve::TwentyG => 2,
+    });
+
+    let handling = &rules.handling;
+    let sdf = match handling.sdf {
+      SoftDropSpeed::Factor(factor) => factor,
+      SoftDropSpeed::Instant => 0,
+    };
+    bytes.extend([
+      handling.das,
+      handling.arr,
+      handling.dcd,
+      sdf,
+      handling.lock_delay,
+      handling.max_lock_resets,
+    ]);
+
+    bytes.push(match &rules.randomizer {
+      RandomizerKind::SevenBag => 0,
+      RandomizerKind::FourteenBag => 1,
+      RandomizerKind::PureRandom => 2,
+      RandomizerKind::History => 3,
+      RandomizerKind::NesReroll => 4,
+      RandomizerKind::Scripted(_) => 5,
+    });
+    let letters = rules.randomizer.letters();
+    push_varint(&mut bytes, letters.len() as u64);
+    bytes.extend(letters.bytes());
+    bytes.push(match rules.mode {
+      GameMode::Endless => 0,
+      GameMode::Sprint => 1,
+      GameMode::Ultra => 2,
+      GameMode::Marathon { .. } => 3,
+      GameMode::Dig { .. } => 4,
+      GameMode::Survival => 5,
+      GameMode::Versus => 6,
+      GameMode::Finesse => 7,
+    });
+    match rules.mode {
+      GameMode::Marathon { start_level, goal } => {
+        push_varint(&mut bytes, start_level as u64);
+        push_varint(&mut bytes, goal.unwrap_or(0) as u64);
+      }
+      GameMode::Dig { goal, messiness } => {
+        push_varint(&mut bytes, goal as u64);
+        bytes.push(messiness);
+      }
+      _ => {}
+    }
+
+    let stats = &self.stats;
+    let counts = [
+      stats.frames,
+      stats.pieces,
+      stats.lines,
+      stats.attack,
+      stats.keys,
+      stats.finesse_faults,
+    ];
+    let maxima = [
+      stats.t_spin_minis,
+      stats.max_combo as u32,
+      stats.max_b2b as u32,
+    ];
+    for value in counts.into_iter().chain(stats.t_spins).chain(maxima) {
+      push_varint(&mut bytes, value as u64);
+    }
+
+    push_varint(&mut bytes, self.frames.len() as u64);
+    let mut frames = self.frames.iter().peekable();
+    while let Some(&input) = frames.next() {
+      let mut run = 1;
+      while frames.next_if_eq(&&input).is_some() {
+        run += 1;
+      }
+      push_varint(&mut bytes, input.bits() as u64);
+      push_varint(&mut bytes, run);
+    }
+
+    bytes
+  }
+
+  pub(super) fn from_bytes(bytes: &[u8]) -> Result<Self, ReplayError> {
+    let Some(bytes) = bytes.strip_prefix(Self::MAGIC) else {
+      return Err(ReplayError::NotAReplay);
+    };
+    let mut reader = Reader { bytes };
+    let version = reader.byte()?;
+    if version != Self::VERSION {
+      return Err(ReplayError::UnsupportedVersion(version));
+    }
+
+    let seed = reader.u64()?;
+    let checksum = reader.u64()?;
+    let scoring = match reader.byte()? {
+      0 => ScoringSystem::Guideline,
+      1 => ScoringSystem::Legacy,
+      _ => return Err(ReplayError::Corrupt),
+    };
+    let gravity = match reader.byte()? {
+      0 => GravityCurve::Guideline,
+      1 => GravityCurve::Nes,
+      2 => GravityCurve::TwentyG,
+      _ => return Err(ReplayError::Corrupt),
+    };
+
+    let [das, arr, dcd, sdf, lock_delay, max_lock_resets] = reader.take(6)?.try_into().unwrap();
+    if !HandlingSettings::DAS_RANGE.contains(&das)
+      || !HandlingSettings::ARR_RANGE.contains(&arr)
+      || !HandlingSettings::DCD_RANGE.contains(&dcd)
+      || sdf > HandlingSettings::MAX_SOFT_DROP_FACTOR
+      || !HandlingSettings::LOCK_DELAY_RANGE.contains(&lock_delay)
+      || !HandlingSettings::MAX_LOCK_RESETS_RANGE.contains(&max_lock_resets)
+    {
+      return Err(ReplayError::Corrupt);
+    }
+    let handling = HandlingSettings {
+      das,
+      arr,
+      dcd,
+      sdf: match sdf {
+        0 => SoftDropSpeed::Instant,
+        factor => SoftDropSpeed::Factor(factor),
+      },
+      lock_delay,
+      max_lock_resets,
+    };
+
+    let kind = reader.byte()?;
+    let letters_len = reader.varint()? as usize;
+    let letters =
+      std::str::from_utf8(reader.take(letters_len)?).map_err(|_| ReplayError::Corrupt)?;
+    let randomizer = match kind {
+      0 => RandomizerKind::SevenBag,
+      1 => RandomizerKind::FourteenBag,
+      2 => RandomizerKind::PureRandom,
+      3 => RandomizerKind::History,
+      4 => RandomizerKind::NesReroll,
+      5 => RandomizerKind::scripted(letters).ok_or(ReplayError::Corrupt)?,
+      _ => return Err(ReplayError::Corrupt),
+    };
+    let mode = match reader.byte()? {
+      0 => GameMode::Endless,
+      1 => GameMode::Sprint,
+      2 => GameMode::Ultra,
+      3 => GameMode::Marathon {
+        start_level: reader
+          .varint()?
+          .try_into()
+          .map_err(|_| ReplayError::Corrupt)?,
+        goal: match reader.varint()? {
+          0 => None,
+          goal => Some(goal.try_into().map_err(|_| ReplayError::Corrupt)?),
+        },
+      },
+      4 => GameMode::Dig {
+        goal: reader
+          .varint()?
+          .try_into()
+          .map_err(|_| ReplayError::Corrupt)?,
+        messiness: reader.byte()?,
+      },
+      5 => GameMode::Survival,
+      6 => GameMode::Versus,
+      7 => GameMode::Finesse,
+      _ => return Err(ReplayError::Corrupt),
+    };
+
+    let mut values = [0; 13];
+    for value in &mut values {
+      *value = u32::try_from(reader.varint()?).map_err(|_| ReplayError::Corrupt)?;
+    }
+    let [frames, pieces, lines, attack, keys, finesse_faults, none, single, double, triple, t_spin_minis, max_combo, max_b2b] =
+      values;
+    let stats = Stats {
+      frames,
+      pieces,
+      lines,
+      attack,
+      keys,
+      finesse_faults,
+      t_spins: [none, single, double, triple],
+      t_spin_minis,
+      max_combo: max_combo.try_into().map_err(|_| ReplayError::Corrupt)?,
+      max_b2b: max_b2b.try_into().map_err(|_| ReplayError::Corrupt)?,
+    };
+
+    let len = usize::try_from(reader.varint()?).map_err(|_| ReplayError::Corrupt)?;
+    if len > Self::MAX_FRAMES {
+      return Err(ReplayError::Corrupt);
+    }
+    let mut frames = Vec::with_capacity(len.min(1 << 20));
+    while frames.len() < len {
+      let bits = u32::try_from(reader.varint()?).map_err(|_| ReplayError::Corrupt)?;
+      let end = usize::try_from(reader.varint()?)
+        .ok()
+        .and_then(|run| frames.len().checked_add(run))
+        .filter(|&end| end > frames.len() && end <= len)
+        .ok_or(ReplayError::Corrupt)?;
+      frames.resize(end, InputFrame::from_bits(bits));
+    }
+
+    Ok(Self {
+      rules: Rules {
+        seed,
+        randomizer,
+        scoring,
+        gravity,
+        handling,
+        mode,
+      },
+      stats,
+      frames,
+      checksum,
+    })
+  }
+}
+
+fn push_varint(bytes: &mut Vec<u8>, mut value: u64) {
+  while value >= 0x80 {
+    bytes.push(value as u8 | 0x80);
+    value >>= 7;
+  }
+  bytes.push(value as u8);
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, len: usize) -> Result<&'a [u8], ReplayError> {
+    if self.bytes.len() < len {
+      return Err(ReplayError::Corrupt);
+    }
+    let (taken, rest) = self.bytes.split_at(len);
+    self.bytes = rest;
+    Ok(taken)
+  }
+
+  fn byte(&mut self) -> Result<u8, ReplayError> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u64(&mut self) -> Result<u64, ReplayError> {
+    Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+  }
+
+  fn varint(&mut self) -> Result<u64, ReplayError> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+      let byte = self.byte()?;
+      value |= ((byte & 0x7f) as u64) << shift;
+      if byte & 0x80 == 0 {
+        return Ok(value);
+      }
+    }
+    Err(ReplayError::Corrupt)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::raytris::game::playfield::input::Action;
+  use rand::{Rng, SeedableRng};
+  use rand_chacha::ChaCha8Rng;
+
+  fn recorded_game(seed: u64, randomizer: RandomizerKind, mode: GameMode) -> Replay {
+    let mut replay = Replay::new(Rules {
+      randomizer,
+      gravity: GravityCurve::Nes,
+      mode,
+      ..Rules::new(seed)
+    });
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut playfield = replay.playfield();
+    for _ in 0..3000 {
+      let mut input = InputFrame::new();
+      if rng.gen_bool(0.05) {
+        input.press(Action::HardDrop);
+      }
+      if rng.gen_bool(0.3) {
+        input.hold(Action::ShiftLeft);
+      }
+      if rng.gen_bool(0.1) {
+        input.press(Action::RotateClockwise);
+      }
+      playfield.update(input);
+      replay.push(input);
+    }
+    replay.finish(&playfield);
+    replay
+  }
+
+  #[test]
+  fn replays_survive_a_round_trip() {
+    for (randomizer, mode) in [
+      (RandomizerKind::History, GameMode::Sprint),
+      (
+        RandomizerKind::scripted("SZT").unwrap(),
+        GameMode::Marathon {
+          start_level: 7,
+          goal: None,
+        },
+      ),
+    ] {
+      let replay = recorded_game(5, randomizer, mode);
+      let bytes = replay.to_bytes();
+      assert!(bytes.len() < replay.len() * 2);
+      assert_eq!(Replay::from_bytes(&bytes).unwrap(), replay);
+    }
+  }
+
+  #[test]
+  fn replaying_reaches_the_recorded_state() {
+    let replay = recorded_game(9, RandomizerKind::SevenBag, GameMode::Ultra);
+    assert_eq!(replay.simulate().checksum(), replay.checksum);
+    assert_eq!(replay.simulate().stats, replay.stats);
+    assert!(replay.stats.pieces > 0);
+
+    let mut tampered = replay.clone();
+    tampered.truncate(100);
+    assert_ne!(tampered.simulate().checksum(), replay.checksum);
+  }
+
+  #[test]
+  fn broken_files_are_rejected() {
+    let bytes = recorded_game(1, RandomizerKind::SevenBag, GameMode::Endless).to_bytes();
+
+    assert!(matches!(
+      Replay::from_bytes(b"PNG"),
+      Err(ReplayError::NotAReplay)
+    ));
+    let mut future_version = bytes.clone();
+    future_version[3] = 9;
+    assert!(matches!(
+      Replay::from_bytes(&future_version),
+      Err(ReplayError::UnsupportedVersion(9))
+    ));
+    assert!(matches!(
+      Replay::from_bytes(&bytes[..bytes.len() - 3]),
+      Err(ReplayError::Corrupt)
+    ));
+  }
+
+  #[test]
+  fn impossible_lengths_and_handling_are_rejected() {
+    let mut empty = Replay::new(Rules::new(0)).to_bytes();
+    assert_eq!(empty.pop(), Some(0));
+    let corrupt = |tail: &[u64]| {
+      let mut bytes = empty.clone();
+      for &value in tail {
+        push_varint(&mut bytes, value);
+      }
+      matches!(Replay::from_bytes(&bytes), Err(ReplayError::Corrupt))
+    };
+
+    assert!(corrupt(&[1 << 40, 0, 1 << 40]));
+    assert!(corrupt(&[10, 0, u64::MAX]));
+    assert!(corrupt(&[10, 0, 0]));
+    assert!(!corrupt(&[10, 0, 10]));
+
+    // The lock delay, right after the seed, checksum, scoring, gravity and four
+    // other handling bytes
+    let mut no_lock_delay = Replay::new(Rules::new(0)).to_bytes();
+    no_lock_delay[26] = 0;
+    assert!(matches!(
+      Replay::from_bytes(&no_lock_delay),
+      Err(ReplayError::Corrupt)
+    ));
+  }
+}
@@ -0,0 +1,412 @@
+use super::{
+  playfield::{input::*, Playfield},
+  Bot, Difficulty, Game, KeyboardInput, Rules,
+};
+use crate::raytris::{
+  controls::{Control, Keymap},
+  settings::Settings,
+};
+use raylib::prelude::*;
+
+// Where a player's inputs come from. Without gamepads both players share the
+// keyboard, player two on their own keymap. A gamepad reads the buttons of the
+// keymap of the player it plays for.
+enum PlayerInput {
+  Keyboard(KeyboardInput),
+  Gamepad { gamepad: i32, keymap: Keymap },
+  Bot(Bot),
+}
+
+impl PlayerInput {
+  fn poll(&mut self, rl: &RaylibHandle, playfield: &Playfield) -> InputFrame {
+    let mut input = match self {
+      Self::Keyboard(keyboard_input) => keyboard_input.poll(rl),
+      Self::Gamepad { gamepad, keymap } => {
+        let mut input = InputFrame::new();
+        for control in Control::ALL {
+          let Some(action) = control.action() else {
+            continue;
+          };
+
+          if keymap.is_button_pressed(rl, *gamepad, control) {
+            input.press(action);
+          } else if keymap.is_button_down(rl, *gamepad, control) {
+            input.hold(action);
+          }
+        }
+        input
+      }
+      Self::Bot(bot) => bot.poll(playfield),
+    };
+    // Restarting would wipe the board in the middle of a match
+    input.release(Action::Restart);
+    input
+  }
+
+  // Keyboards are left to player one's keymap, which handles the whole match
+  fn is_button_pressed(&self, rl: &RaylibHandle, control: Control) -> bool {
+    match self {
+      Self::Gamepad { gamepad, keymap } => keymap.is_button_pressed(rl, *gamepad, control),
+      Self::Keyboard(_) | Self::Bot(_) => false,
+    }
+  }
+
+  fn name(&self) -> String {
+    match self {
+      Self::Keyboard(_) => String::from("Keyboard"),
+      Self::Gamepad { gamepad, .. } => format!("Gamepad {}", gamepad + 1),
+      Self::Bot(bot) => format!("CPU {}", bot.difficulty().name()),
+    }
+  }
+}
+
+// What can be done once a match is over
+#[derive(Clone, Copy, PartialEq)]
+enum MatchOption {
+  Rematch,
+  Menu,
+}
+
+impl MatchOption {
+  const ALL: [Self; 2] = [Self::Rematch, Self::Menu];
+
+  fn name(self) -> &'static str {
+    match self {
+      Self::Rematch => "REMATCH",
+      Self::Menu => "MENU",
+    }
+  }
+}
+
+// Two players on the same seed, each clearing lines to send garbage to the other.
+// The first one to top out loses. Player two can be played by the bot.
+pub struct Versus {
+  games: [Game; 2],
+  inputs: [PlayerInput; 2],
+  paused: bool,
+  // The option picked on the screen after the match
+  selected: usize,
+  is_quitting: bool,
+}
+
+impl Versus {
+  pub fn new(
+    rl: &RaylibHandle,
+    rules: Rules,
+    settings: &Settings,
+    opponent: Option<Difficulty>,
+  ) -> Self {
+    let rules_two = Rules {
+      handling: settings.handling_two,
+      ..rules.clone()
+    };
+    let mut games = side_by_side(rl, [rules, rules_two], settings);
+    games[1].keyboard_input.keymap = settings.keymap_two.clone();
+
+    let keyboard = |id: usize| {
+      PlayerInput::Keyboard(KeyboardInput {
+        keymap: games[id].keyboard_input.keymap.clone(),
+      })
+    };
+    let gamepad = |id: usize, gamepad: i32| PlayerInput::Gamepad {
+      gamepad,
+      keymap: games[id].keyboard_input.keymap.clone(),
+    };
+    // A single gamepad goes to player two, so player one keeps their usual keys
+    let inputs = match (
+      opponent,
+      rl.is_gamepad_available(0),
+      rl.is_gamepad_available(1),
+    ) {
+      (Some(difficulty), ..) => [keyboard(0), PlayerInput::Bot(Bot::new(difficulty))],
+      (None, true, true) => [gamepad(0, 0), gamepad(1, 1)],
+      (None, true, false) => [keyboard(0), gamepad(1, 0)],
+      _ => [keyboard(0), keyboard(1)],
+    };
+
+    Self {
+      games,
+      inputs,
+      paused: false,
+      selected: 0,
+      is_quitting: false,
+    }
+  }
+
+  pub fn run(&mut self, rl: &mut RaylibHandle, thread: &RaylibThread) {
+    while !self.is_quitting {
+      if self.can_quit() && self.is_pressed(rl, Control::Quit) {
+        break;
+      }
+      self.update(rl);
+      self.draw(rl, thread);
+    }
+
+    let _d = rl.begin_drawing(thread);
+  }
+
+  // Player one's keys, or the button of any gamepad in the match
+  fn is_pressed(&self, rl: &RaylibHandle, control: Control) -> bool {
+    self.games[0].keyboard_input.keymap.is_pressed(rl, control)
+      || self
+        .inputs
+        .iter()
+        .any(|input| input.is_button_pressed(rl, control))
+  }
+
+  fn can_quit(&self) -> bool {
+    self.paused || self.result().is_some()
+  }
+
+  fn result(&self) -> Option<&'static str> {
+    match [0, 1].map(|id| self.games[id].playfield.has_lost) {
+      [false, false] => None,
+      [false, true] => Some("PLAYER 1 WINS"),
+      [true, false] => Some("PLAYER 2 WINS"),
+      [true, true] => Some("DRAW"),
+    }
+  }
+
+  fn update(&mut self, rl: &RaylibHandle) {
+    if self.result().is_some() {
+      self.update_result(rl);
+      return;
+    }
+
+    if self.is_pressed(rl, Control::Pause) {
+      self.paused = !self.paused;
+    }
+
+    if self.paused {
+      return;
+    }
+
+    let inputs = [0, 1].map(|id| self.inputs[id].poll(rl, &self.games[id].playfield));
+    let [one, two] = &mut self.games;
+    update_both([&mut one.playfield, &mut two.playfield], inputs);
+  }
+
+  // Works like the results screen of a single game
+  fn update_result(&mut self, rl: &RaylibHandle) {
+    let options = MatchOption::ALL.len();
+    if self.is_pressed(rl, Control::ShiftRight) {
+      self.selected = (self.selected + 1) % options;
+    } else if self.is_pressed(rl, Control::ShiftLeft) {
+      self.selected = (self.selected + options - 1) % options;
+    }
+
+    let option = if self.is_pressed(rl, Control::Restart) {
+      MatchOption::Rematch
+    } else if self.is_pressed(rl, Control::Confirm) {
+      MatchOption::ALL[self.selected]
+    } else {
+      return;
+    };
+    match option {
+      MatchOption::Rematch => self.rematch(),
+      MatchOption::Menu => self.is_quitting = true,
+    }
+  }
+
+  // Both players start over on the same seed, against a fresh bot if there is one
+  fn rematch(&mut self) {
+    for game in &mut self.games {
+      game.retry();
+    }
+    for input in &mut self.inputs {
+      if let PlayerInput::Bot(bot) = input {
+        *bot = Bot::new(bot.difficulty());
+      }
+    }
+    self.paused = false;
+    self.selected = 0;
+  }
+
+  fn draw(&self, rl: &mut RaylibHandle, thread: &RaylibThread) {
+    let mut d = rl.begin_drawing(thread);
+    let labels = [0, 1].map(|id| format!("PLAYER {} ({})", id + 1, self.inputs[id].name()));
+    let keymap = &self.games[0].keyboard_input.keymap;
+    let (title, text) = match self.result() {
+      Some(result) => (
+        result,
+        format!(
+          "{}/{} to choose, {} to confirm, {} for a rematch",
+          keymap.hint(Control::ShiftLeft),
+          keymap.hint(Control::ShiftRight),
+          keymap.hint(Control::Confirm),
+          keymap.hint(Control::Restart)
+        ),
+      ),
+      None if self.paused => (
+        "GAME PAUSED",
+        format!("Press {} to quit", keymap.hint(Control::Quit)),
+      ),
+      None => ("", String::new()),
+    };
+    draw_match(&mut d, &self.games, &labels, title, &text);
+
+    if self.result().is_some() {
+      self.draw_options(&mut d);
+    }
+  }
+
+  // The options in a row under the banner, the selected one highlighted
+  fn draw_options(&self, d: &mut RaylibDrawHandle) {
+    let screen_width = d.get_screen_width();
+    let font_size = self.games[0].block_length as i32 * 2;
+    let y = d.get_screen_height() / 2 + self.games[0].block_length as i32 * 5 + 2 * font_size;
+    let gap = 2 * font_size;
+    let width: i32 = MatchOption::ALL
+      .iter()
+      .map(|option| d.measure_text(option.name(), font_size) + gap)
+      .sum::<i32>()
+      - gap;
+    let mut x = (screen_width - width) / 2;
+    for (id, option) in MatchOption::ALL.iter().enumerate() {
+      let color = if id == self.selected {
+        Color::GOLD
+      } else {
+        Color::WHITE
+      };
+      d.draw_text(option.name(), x, y, font_size, color);
+      x += d.measure_text(option.name(), font_size) + gap;
+    }
+  }
+}
+
+// Two games, each on its own half of the screen. The rules only differ in the
+// handling of each player.
+pub(super) fn side_by_side(rl: &RaylibHandle, rules: [Rules; 2], settings: &Settings) -> [Game; 2] {
+  let mut games = rules.map(|rules| Game::new(rl, rules, settings));
+  let screen_width = rl.get_screen_width() as f32;
+  let screen_height = rl.get_screen_height() as f32;
+  for (id, game) in games.iter_mut().enumerate() {
+    game.fit(Rectangle::new(
+      id as f32 * screen_width / 2.0,
+      0.0,
+      screen_width / 2.0,
+      screen_height,
+    ));
+  }
+  games
+}
+
+// Both boards with a label over each. A title, if any, goes in a banner across the
+// screen with a line of text under it.
+pub(super) fn draw_match(
+  d: &mut RaylibDrawHandle,
+  games: &[Game; 2],
+  labels: &[String; 2],
+  title: &str,
+  text: &str,
+) {
+  d.clear_background(Color::LIGHTGRAY);
+  for (game, label) in games.iter().zip(labels) {
+    game.draw_board(d);
+
+    let label_block = game.get_block_rectangle(0, Playfield::VISIBLE_HEIGHT as i8 - 3);
+    d.draw_text(
+      label,
+      label_block.x as i32,
+      label_block.y as i32,
+      game.block_length as i32 * 2,
+      Color::BLACK,
+    );
+  }
+
+  if title.is_empty() {
+    return;
+  }
+
+  let screen_width = d.get_screen_width();
+  let screen_height = d.get_screen_height();
+  let font_size = games[0].block_length as i32 * 2;
+  let font_size_big = games[0].block_length as i32 * 5;
+  d.draw_rectangle(
+    0,
+    0,
+    screen_width,
+    screen_height,
+    Color {
+      r: 0,
+      g: 0,
+      b: 0,
+      a: 100,
+    },
+  );
+  d.draw_text(
+    title,
+    (screen_width - d.measure_text(title, font_size_big)) / 2,
+    screen_height / 2,
+    font_size_big,
+    Color::BLUE,
+  );
+  d.draw_text(
+    text,
+    (screen_width - d.measure_text(text, font_size)) / 2,
+    screen_height / 2 + font_size_big,
+    font_size,
+    Color::WHITE,
+  );
+}
+
+// Steps both playfields a frame, then hands the lines each one sent to the other
+pub(super) fn update_both(playfields: [&mut Playfield; 2], inputs: [InputFrame; 2]) -> [u32; 2] {
+  let [one, two] = playfields;
+  one.update(inputs[0]);
+  two.update(inputs[1]);
+
+  let sent = [one.take_outgoing(), two.take_outgoing()];
+  one.receive_garbage(sent[1]);
+  two.receive_garbage(sent[0]);
+  sent
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::raytris::game::{playfield::falling_piece::Tetromino, GameMode};
+
+  fn pressed(action: Action) -> InputFrame {
+    let mut input = InputFrame::new();
+    input.press(action);
+    input
+  }
+
+  fn garbage_rows(playfield: &Playfield) -> usize {
+    playfield
+      .grid
+      .iter()
+      .filter(|row| row.contains(&Tetromino::Garbage))
+      .count()
+  }
+
+  #[test]
+  fn lines_sent_cancel_incoming_garbage_then_reach_the_other_player() {
+    let mut one = Playfield::scripted("I", GameMode::Versus);
+    let mut two = Playfield::scripted("O", GameMode::Versus);
+    for row in &mut one.grid[Playfield::HEIGHT - 4..] {
+      *row = [Tetromino::Garbage; Playfield::WIDTH];
+      row[5] = Tetromino::Empty;
+    }
+    // Leftovers above the tetris, so it isn't an all clear
+    one.grid[Playfield::HEIGHT - 5][0] = Tetromino::J;
+    one.receive_garbage(1);
+    let idle = InputFrame::new();
+
+    // A tetris sends four lines, one of which cancels the garbage on its way
+    update_both(
+      [&mut one, &mut two],
+      [pressed(Action::RotateClockwise), idle],
+    );
+    update_both([&mut one, &mut two], [pressed(Action::HardDrop), idle]);
+    assert_eq!(one.stats.lines, 4);
+    assert!(one.incoming.is_empty());
+    assert_eq!(two.incoming, [3]);
+
+    // The garbage only rises once the other player locks a piece without clearing
+    update_both([&mut one, &mut two], [idle, pressed(Action::HardDrop)]);
+    assert_eq!(garbage_rows(&two), 3);
+    assert_eq!(garbage_rows(&one), 0);
+  }
+}
@@ -1,112 +1,897 @@
+use super::{
+  controls::{Control, KeyBinding, Keymap, KeymapPreset},
+  game::{
+    Difficulty, GameMode, GravityCurve, HandlingSettings, RandomizerKind, ScoringSystem,
+    SoftDropSpeed,
+  },
+  settings::{Resolution, Settings, SettingsError},
+  Arguments,
+};
 use raylib::prelude::*;
+use std::ops::RangeInclusive;
 
 pub struct Menu {
-  resolution: Resolution,
   window_resolution: (i32, i32),
+  mode: GameMode,
+  scoring_system: ScoringSystem,
+  gravity_curve: GravityCurve,
+  randomizer: RandomizerKind,
+  settings: Settings,
+  screen: Screen,
+  selected_option: usize,
+  keymap_preset: KeymapPreset,
+  editing_player_two: bool,
+  pending_modifier: Option<KeyboardKey>,
+  // Set while the settings file failed to load and hasn't been replaced yet
+  is_settings_file_broken: bool,
+  error: Option<String>,
+  seed: Option<u64>,
+  seed_input: String,
+  address_input: String,
+  broadcast: bool,
+  opponent: Option<Difficulty>,
+  idle_frames: u32,
 }
 
 #[derive(PartialEq)]
-enum Resolution {
-  Small,
-  Medium,
-  Big,
-  Fullscreen,
-}
-
-impl Resolution {
-  pub fn next(&self) -> Self {
-    match self {
-      Self::Small => Self::Medium,
-      Self::Medium => Self::Big,
-      Self::Big => Self::Fullscreen,
-      Self::Fullscreen => Self::Small,
-    }
-  }
+enum Screen {
+  Main,
+  Handling,
+  Controls { is_capturing: bool },
+  Seed,
+  Connect { is_spectating: bool },
 }
 
 #[derive(PartialEq)]
 pub enum ExitCode {
   Game,
+  Host,
+  Join,
+  Watch,
+  Demo,
+  Replay,
   Exit,
 }
 
 impl Menu {
   pub const INITIAL_RESOLUTION: (i32, i32) = (640, 360);
 
-  pub fn new() -> Self {
-    Menu {
-      resolution: Resolution::Small,
+  const HANDLING_OPTIONS: usize = 7;
+  // Controls listed at once, scrolling to keep the selected one in sight
+  const CONTROL_ROWS: usize = 15;
+  // The title screen plays a demo after being left alone for this long
+  const DEMO_FRAMES: u32 = 20 * 60;
+
+  pub fn new(
+    rl: &mut RaylibHandle,
+    settings: Settings,
+    error: Option<SettingsError>,
+    arguments: Arguments,
+  ) -> Self {
+    let mut menu = Menu {
       window_resolution: Self::INITIAL_RESOLUTION,
-    }
+      mode: GameMode::Endless,
+      scoring_system: ScoringSystem::Guideline,
+      gravity_curve: GravityCurve::Guideline,
+      randomizer: arguments.randomizer.unwrap_or(RandomizerKind::SevenBag),
+      settings,
+      screen: Screen::Main,
+      selected_option: 0,
+      keymap_preset: KeymapPreset::Guideline,
+      editing_player_two: false,
+      pending_modifier: None,
+      is_settings_file_broken: error.is_some(),
+      error: error.map(|error| format!("Settings file: {}", error)),
+      seed: arguments.seed,
+      seed_input: String::new(),
+      address_input: String::from("127.0.0.1"),
+      broadcast: false,
+      opponent: None,
+      idle_frames: 0,
+    };
+    menu.apply_resolution(rl);
+    menu
+  }
+
+  pub fn mode(&self) -> GameMode {
+    self.mode
+  }
+
+  pub fn scoring_system(&self) -> ScoringSystem {
+    self.scoring_system
+  }
+
+  pub fn gravity_curve(&self) -> GravityCurve {
+    self.gravity_curve
+  }
+
+  pub fn randomizer(&self) -> RandomizerKind {
+    self.randomizer.clone()
+  }
+
+  pub fn settings(&self) -> &Settings {
+    &self.settings
+  }
+
+  // Shown on the main screen until something else goes wrong
+  pub fn set_error(&mut self, error: String) {
+    self.error = Some(error);
+  }
+
+  pub fn address(&self) -> &str {
+    &self.address_input
+  }
+
+  pub fn seed(&self) -> Option<u64> {
+    self.seed
+  }
+
+  pub fn broadcast(&self) -> bool {
+    self.broadcast
+  }
+
+  // The bot playing player two in versus, if any
+  pub fn opponent(&self) -> Option<Difficulty> {
+    self.opponent
   }
 
   pub fn run(&mut self, rl: &mut RaylibHandle, thread: &RaylibThread) -> ExitCode {
-    while !rl.is_key_pressed(KeyboardKey::KEY_ENTER) && !rl.is_key_pressed(KeyboardKey::KEY_ESCAPE)
-    {
-      self.update(rl);
-      self.draw(rl, thread);
+    let exit_code = loop {
+      let exit_code = match self.screen {
+        Screen::Main => self.update(rl),
+        Screen::Handling => {
+          self.update_handling(rl);
+          None
+        }
+        Screen::Controls { .. } => {
+          self.update_controls(rl);
+          None
+        }
+        Screen::Seed => {
+          self.update_seed(rl);
+          None
+        }
+        Screen::Connect { is_spectating } => self.update_connect(rl, is_spectating),
+      };
+      if let Some(exit_code) = exit_code {
+        break exit_code;
+      }
+      if self.screen != Screen::Main {
+        self.idle_frames = 0;
+      }
+
+      match self.screen {
+        Screen::Main => self.draw(rl, thread),
+        Screen::Handling => self.draw_handling(rl, thread),
+        Screen::Controls { is_capturing } => self.draw_controls(rl, thread, is_capturing),
+        Screen::Seed => self.draw_seed(rl, thread),
+        Screen::Connect { is_spectating } => self.draw_connect(rl, thread, is_spectating),
+      }
+    };
+
+    let _d = rl.begin_drawing(thread);
+
+    exit_code
+  }
+
+  fn update(&mut self, rl: &mut RaylibHandle) -> Option<ExitCode> {
+    if rl.get_key_pressed().is_some() {
+      self.idle_frames = 0;
+    } else {
+      self.idle_frames += 1;
+    }
+    if self.idle_frames > Self::DEMO_FRAMES {
+      self.idle_frames = 0;
+      return Some(ExitCode::Demo);
     }
 
-    let exit_code = if rl.is_key_down(KeyboardKey::KEY_ENTER) {
-      ExitCode::Game
+    let keymap = &self.settings.keymap;
+    if keymap.is_pressed(rl, Control::Confirm) {
+      return Some(ExitCode::Game);
+    }
+    if keymap.is_pressed(rl, Control::Quit) {
+      return Some(ExitCode::Exit);
+    }
+    if keymap.is_pressed(rl, Control::OpenReplay) {
+      return Some(ExitCode::Replay);
+    }
+    if keymap.is_pressed(rl, Control::OpenHandling) {
+      self.screen = Screen::Handling;
+      self.selected_option = 0;
+    }
+    if keymap.is_pressed(rl, Control::OpenControls) {
+      self.screen = Screen::Controls {
+        is_capturing: false,
+      };
+      self.selected_option = 0;
+    }
+    if keymap.is_pressed(rl, Control::ChangeMode) {
+      self.mode = self.mode.next();
+    }
+    let (is_first_option, is_second_option) = (
+      keymap.is_pressed(rl, Control::FirstModeOption),
+      keymap.is_pressed(rl, Control::SecondModeOption),
+    );
+    match &mut self.mode {
+      GameMode::Marathon { start_level, goal } => {
+        if is_first_option {
+          *start_level = *start_level % GameMode::MAX_START_LEVEL + 1;
+        }
+        if is_second_option {
+          *goal = next_option(&GameMode::MARATHON_GOALS, *goal);
+        }
+      }
+      GameMode::Dig { goal, messiness } => {
+        if is_first_option {
+          *messiness = next_option(&GameMode::DIG_MESSINESS, *messiness);
+        }
+        if is_second_option {
+          *goal = next_option(&GameMode::DIG_GOALS, *goal);
+        }
+      }
+      GameMode::Versus => {
+        if is_first_option {
+          self.opponent = match self.opponent {
+            None => Some(Difficulty::Easy),
+            Some(Difficulty::Hard) => None,
+            Some(difficulty) => Some(difficulty.next()),
+          };
+        }
+        if is_second_option {
+          return Some(ExitCode::Host);
+        }
+        if keymap.is_pressed(rl, Control::Join) {
+          self.screen = Screen::Connect {
+            is_spectating: false,
+          };
+        }
+      }
+      _ => {}
+    }
+    if keymap.is_pressed(rl, Control::ChangeScoring) {
+      self.scoring_system = self.scoring_system.next();
+    }
+    if keymap.is_pressed(rl, Control::ChangeGravity) {
+      self.gravity_curve = self.gravity_curve.next();
+    }
+    if keymap.is_pressed(rl, Control::ChangePieces) {
+      self.randomizer = self.randomizer.next();
+    }
+    if keymap.is_pressed(rl, Control::ToggleBroadcast) {
+      self.broadcast = !self.broadcast;
+    }
+    if keymap.is_pressed(rl, Control::Watch) {
+      self.screen = Screen::Connect {
+        is_spectating: true,
+      };
+    }
+    if keymap.is_pressed(rl, Control::ChangeSeed) {
+      self.screen = Screen::Seed;
+      self.seed_input = self.seed.map(|seed| seed.to_string()).unwrap_or_default();
+    }
+    if self.settings.keymap.is_pressed(rl, Control::Resize) {
+      self.settings.resolution = self.settings.resolution.next();
+      self.apply_resolution(rl);
+      self.save_settings();
+    }
+
+    None
+  }
+
+  // The handling and controls screens read a fixed set of keys rather than the
+  // keymap, so a keymap that lost its keys can still be fixed from them: arrows to
+  // move and edit, P to switch player and Escape to go back. Enter also goes back
+  // here, on the controls screen it adds a key.
+  fn update_handling(&mut self, rl: &RaylibHandle) {
+    if rl.is_key_pressed(KeyboardKey::KEY_ENTER) || rl.is_key_pressed(KeyboardKey::KEY_ESCAPE) {
+      self.screen = Screen::Main;
+      self.save_settings();
+      return;
+    }
+
+    if rl.is_key_pressed(KeyboardKey::KEY_P) {
+      self.editing_player_two = !self.editing_player_two;
+    }
+    if rl.is_key_pressed(KeyboardKey::KEY_DOWN) {
+      self.selected_option = (self.selected_option + 1) % Self::HANDLING_OPTIONS;
+    } else if rl.is_key_pressed(KeyboardKey::KEY_UP) {
+      self.selected_option =
+        (self.selected_option + Self::HANDLING_OPTIONS - 1) % Self::HANDLING_OPTIONS;
+    }
+
+    let increase = rl.is_key_pressed(KeyboardKey::KEY_RIGHT);
+    if !increase && !rl.is_key_pressed(KeyboardKey::KEY_LEFT) {
+      return;
+    }
+
+    let step = |value: u8, range: RangeInclusive<u8>| {
+      if increase {
+        value.saturating_add(1).min(*range.end())
+      } else {
+        value.saturating_sub(1).max(*range.start())
+      }
+    };
+
+    let handling = if self.editing_player_two {
+      &mut self.settings.handling_two
     } else {
-      ExitCode::Exit
+      &mut self.settings.handling
     };
+    match self.selected_option {
+      0 => handling.das = step(handling.das, HandlingSettings::DAS_RANGE),
+      1 => handling.arr = step(handling.arr, HandlingSettings::ARR_RANGE),
+      2 => handling.dcd = step(handling.dcd, HandlingSettings::DCD_RANGE),
+      3 => {
+        handling.sdf = if increase {
+          handling.sdf.increase()
+        } else {
+          handling.sdf.decrease()
+        }
+      }
+      4 => handling.lock_delay = step(handling.lock_delay, HandlingSettings::LOCK_DELAY_RANGE),
+      5 => {
+        handling.max_lock_resets = step(
+          handling.max_lock_resets,
+          HandlingSettings::MAX_LOCK_RESETS_RANGE,
+        )
+      }
+      _ => self.settings.input_delay = step(self.settings.input_delay, Settings::INPUT_DELAY_RANGE),
+    }
+  }
+
+  // An empty seed means every game gets a random one
+  fn update_seed(&mut self, rl: &mut RaylibHandle) {
+    let keymap = &self.settings.keymap;
+    if keymap.is_pressed(rl, Control::Quit) {
+      self.screen = Screen::Main;
+      return;
+    }
+    if keymap.is_pressed(rl, Control::Confirm) {
+      if let Ok(seed) = self.seed_input.parse() {
+        self.seed = Some(seed);
+      } else if self.seed_input.is_empty() {
+        self.seed = None;
+      }
+      self.screen = Screen::Main;
+      return;
+    }
 
-    let d = rl.begin_drawing(thread);
-    drop(d);
+    if rl.is_key_pressed(KeyboardKey::KEY_BACKSPACE) {
+      self.seed_input.pop();
+    }
+    while let Some(character) = rl.get_char_pressed() {
+      let mut seed_input = self.seed_input.clone();
+      seed_input.push(character);
+      if character.is_ascii_digit() && seed_input.parse::<u64>().is_ok() {
+        self.seed_input = seed_input;
+      }
+    }
+  }
 
-    exit_code
+  // The address of the host or the broadcast, with or without a port
+  fn update_connect(&mut self, rl: &mut RaylibHandle, is_spectating: bool) -> Option<ExitCode> {
+    let keymap = &self.settings.keymap;
+    if keymap.is_pressed(rl, Control::Quit) {
+      self.screen = Screen::Main;
+      return None;
+    }
+    if keymap.is_pressed(rl, Control::Confirm) && !self.address_input.is_empty() {
+      self.screen = Screen::Main;
+      return Some(if is_spectating {
+        ExitCode::Watch
+      } else {
+        ExitCode::Join
+      });
+    }
+
+    if rl.is_key_pressed(KeyboardKey::KEY_BACKSPACE) {
+      self.address_input.pop();
+    }
+    while let Some(character) = rl.get_char_pressed() {
+      if character.is_ascii_alphanumeric() || ".:-".contains(character) {
+        self.address_input.push(character);
+      }
+    }
+    None
+  }
+
+  // Reads the same fixed keys as the handling screen, plus Tab for the next preset
+  // and Backspace or Delete to clear a control
+  fn update_controls(&mut self, rl: &mut RaylibHandle) {
+    let control = Control::ALL[self.selected_option];
+
+    if self.screen == (Screen::Controls { is_capturing: true }) {
+      let binding = match rl.get_key_pressed() {
+        Some(KeyboardKey::KEY_ESCAPE) => None,
+        // A lone modifier is only bound once it is released without pressing another key
+        Some(key) if KeyBinding::is_modifier(key) => {
+          self.pending_modifier = Some(key);
+          return;
+        }
+        Some(key) => Some(KeyBinding::from_key_press(rl, key)),
+        None => match self.pending_modifier {
+          Some(modifier) if rl.is_key_released(modifier) => Some(KeyBinding::new(modifier)),
+          _ => return,
+        },
+      };
+
+      if let Some(binding) = binding {
+        self.edited_keymap_mut().add_binding(control, binding);
+      }
+      self.pending_modifier = None;
+      self.screen = Screen::Controls {
+        is_capturing: false,
+      };
+      return;
+    }
+
+    if rl.is_key_pressed(KeyboardKey::KEY_ESCAPE) && self.keymap_conflict().is_none() {
+      self.screen = Screen::Main;
+      self.save_settings();
+      return;
+    }
+
+    if rl.is_key_pressed(KeyboardKey::KEY_DOWN) {
+      self.selected_option = (self.selected_option + 1) % Control::ALL.len();
+    } else if rl.is_key_pressed(KeyboardKey::KEY_UP) {
+      self.selected_option = (self.selected_option + Control::ALL.len() - 1) % Control::ALL.len();
+    }
+
+    if rl.is_key_pressed(KeyboardKey::KEY_ENTER) {
+      self.screen = Screen::Controls { is_capturing: true };
+    } else if rl.is_key_pressed(KeyboardKey::KEY_BACKSPACE)
+      || rl.is_key_pressed(KeyboardKey::KEY_DELETE)
+    {
+      self.edited_keymap_mut().clear_bindings(control);
+    } else if rl.is_key_pressed(KeyboardKey::KEY_TAB) {
+      // Presets are keyboard layouts, so the gamepad buttons stay as they were
+      self.keymap_preset = self.keymap_preset.next();
+      let preset = self.keymap_preset.keymap();
+      let keymap = self.edited_keymap_mut();
+      for control in Control::ALL {
+        keymap.set_bindings(control, preset.bindings(control).to_vec());
+      }
+    } else if rl.is_key_pressed(KeyboardKey::KEY_P) {
+      self.editing_player_two = !self.editing_player_two;
+    }
+  }
+
+  // The first clash in either player's keys. Keys aren't saved until there are none.
+  fn keymap_conflict(&self) -> Option<String> {
+    [false, true].into_iter().find_map(|is_player_two| {
+      Control::ALL.into_iter().find_map(|control| {
+        let conflict = self.conflict(control, is_player_two)?;
+        Some(format!(
+          "Player {}: {} conflicts with {}",
+          if is_player_two { 2 } else { 1 },
+          control.label(),
+          conflict
+        ))
+      })
+    })
+  }
+
+  // What `control` of one player clashes with, either in their own keys or, in
+  // versus, in the other player's
+  fn conflict(&self, control: Control, is_player_two: bool) -> Option<String> {
+    let (one, two) = (&self.settings.keymap, &self.settings.keymap_two);
+    let keymap = if is_player_two { two } else { one };
+    if let Some(other) = keymap.conflicts(control).first() {
+      return Some(other.label().to_string());
+    }
+
+    let other = if is_player_two {
+      *one.versus_conflicts(two, control).first()?
+    } else {
+      Control::ALL
+        .into_iter()
+        .find(|&other| one.versus_conflicts(two, other).contains(&control))?
+    };
+    Some(format!(
+      "player {}'s {}",
+      if is_player_two { 1 } else { 2 },
+      other.label()
+    ))
   }
 
-  fn update(&mut self, rl: &mut RaylibHandle) {
-    if rl.is_key_pressed(KeyboardKey::KEY_F) {
-      self.resize_screen(rl);
+  // Player two's keys are only used in versus, when they don't have a gamepad
+  fn edited_keymap(&self) -> &Keymap {
+    if self.editing_player_two {
+      &self.settings.keymap_two
+    } else {
+      &self.settings.keymap
+    }
+  }
+
+  fn edited_keymap_mut(&mut self) -> &mut Keymap {
+    if self.editing_player_two {
+      &mut self.settings.keymap_two
+    } else {
+      &mut self.settings.keymap
     }
   }
 
   fn draw(&self, rl: &mut RaylibHandle, thread: &RaylibThread) {
-    let font_size = self.window_resolution.1 / 10;
-    let mut d = rl.begin_drawing(&thread);
+    let font_size = self.window_resolution.1 / 12;
+    let mut d = rl.begin_drawing(thread);
 
     d.clear_background(Color::LIGHTGRAY);
     d.draw_text(
       "RAYTRIS",
       (self.window_resolution.0 - d.measure_text("RAYTRIS", font_size * 2)) / 2,
-      self.window_resolution.1 / 2 - 3 * font_size,
-      font_size as i32 * 2,
+      self.window_resolution.1 / 2 - 5 * font_size,
+      font_size * 2,
       Color::RED,
     );
+
+    let key = |control| self.settings.keymap.hint(control);
     let resolution = format!(
-      "{} x {}",
-      self.window_resolution.0, self.window_resolution.1
+      "{} x {} ({})",
+      self.window_resolution.0,
+      self.window_resolution.1,
+      key(Control::Resize)
+    );
+    let seed = match self.seed {
+      Some(seed) => seed.to_string(),
+      None => "random".to_string(),
+    };
+    let mut lines = vec![
+      (resolution, Color::BLUE),
+      (
+        format!("Mode: {} ({})", self.mode.name(), key(Control::ChangeMode)),
+        Color::BLACK,
+      ),
+      (
+        format!(
+          "Scoring: {} ({})  Gravity: {} ({})",
+          self.scoring_system.name(),
+          key(Control::ChangeScoring),
+          self.gravity_curve.name(),
+          key(Control::ChangeGravity)
+        ),
+        Color::BLACK,
+      ),
+      (
+        format!(
+          "Pieces: {} ({})  Seed: {} ({})",
+          self.randomizer.name(),
+          key(Control::ChangePieces),
+          seed,
+          key(Control::ChangeSeed)
+        ),
+        Color::BLACK,
+      ),
+      (
+        format!(
+          "Handling ({})  Controls ({})  Replay ({})",
+          key(Control::OpenHandling),
+          key(Control::OpenControls),
+          key(Control::OpenReplay)
+        ),
+        Color::BLACK,
+      ),
+      (
+        format!(
+          "Broadcast: {} ({})  Watch ({})",
+          if self.broadcast { "on" } else { "off" },
+          key(Control::ToggleBroadcast),
+          key(Control::Watch)
+        ),
+        Color::BLACK,
+      ),
+      (
+        format!("Press {} to Play", key(Control::Confirm)),
+        Color::BLACK,
+      ),
+    ];
+    let (first, second) = (
+      key(Control::FirstModeOption),
+      key(Control::SecondModeOption),
     );
+    let options = match self.mode {
+      GameMode::Marathon { start_level, goal } => Some(match goal {
+        Some(goal) => format!(
+          "Level: {} ({})  Goal: {} lines ({})",
+          start_level, first, goal, second
+        ),
+        None => format!(
+          "Level: {} ({})  Goal: endless ({})",
+          start_level, first, second
+        ),
+      }),
+      GameMode::Dig { goal, messiness } => Some(format!(
+        "Messiness: {}% ({})  Goal: {} lines ({})",
+        messiness, first, goal, second
+      )),
+      GameMode::Versus => Some(format!(
+        "CPU: {} ({})  Host ({})  Join ({})",
+        self.opponent.map_or("off", |difficulty| difficulty.name()),
+        first,
+        second,
+        key(Control::Join)
+      )),
+      _ => None,
+    };
+    if let Some(options) = options {
+      lines.insert(2, (options, Color::BLACK));
+    }
+    for (id, (text, color)) in lines.iter().enumerate() {
+      d.draw_text(
+        text,
+        (self.window_resolution.0 - d.measure_text(text, font_size)) / 2,
+        self.window_resolution.1 / 2 + (id as i32 - 2) * font_size,
+        font_size,
+        *color,
+      );
+    }
+
+    if let Some(error) = &self.error {
+      let error_font_size = font_size / 2;
+      d.draw_text(
+        error,
+        (self.window_resolution.0 - d.measure_text(error, error_font_size)) / 2,
+        error_font_size / 2,
+        error_font_size,
+        Color::RED,
+      );
+    }
+  }
+
+  fn draw_seed(&self, rl: &mut RaylibHandle, thread: &RaylibThread) {
+    let font_size = self.window_resolution.1 / 10;
+    let mut d = rl.begin_drawing(thread);
+
+    d.clear_background(Color::LIGHTGRAY);
     d.draw_text(
-      &resolution,
-      (self.window_resolution.0 - d.measure_text(&resolution, font_size)) / 2,
+      "SEED",
+      (self.window_resolution.0 - d.measure_text("SEED", font_size * 2)) / 2,
+      self.window_resolution.1 / 2 - 3 * font_size,
+      font_size * 2,
+      Color::RED,
+    );
+    let seed = if self.seed_input.is_empty() {
+      "random"
+    } else {
+      &self.seed_input
+    };
+    d.draw_text(
+      seed,
+      (self.window_resolution.0 - d.measure_text(seed, font_size)) / 2,
       self.window_resolution.1 / 2,
       font_size,
       Color::BLUE,
     );
+    let help = format!(
+      "Type a number, {} to confirm",
+      self.settings.keymap.hint(Control::Confirm)
+    );
     d.draw_text(
-      "Press F to resize",
-      (self.window_resolution.0 - d.measure_text("Press F to resize", font_size)) / 2,
-      self.window_resolution.1 / 2 + font_size,
-      font_size,
+      &help,
+      (self.window_resolution.0 - d.measure_text(&help, font_size / 2)) / 2,
+      self.window_resolution.1 / 2 + 2 * font_size,
+      font_size / 2,
       Color::BLACK,
     );
+  }
+
+  fn draw_connect(&self, rl: &mut RaylibHandle, thread: &RaylibThread, is_spectating: bool) {
+    let font_size = self.window_resolution.1 / 10;
+    let (title, target) = if is_spectating {
+      ("WATCH", "broadcast")
+    } else {
+      ("JOIN", "host")
+    };
+    let mut d = rl.begin_drawing(thread);
+
+    d.clear_background(Color::LIGHTGRAY);
+    d.draw_text(
+      title,
+      (self.window_resolution.0 - d.measure_text(title, font_size * 2)) / 2,
+      self.window_resolution.1 / 2 - 3 * font_size,
+      font_size * 2,
+      Color::RED,
+    );
+    d.draw_text(
+      &self.address_input,
+      (self.window_resolution.0 - d.measure_text(&self.address_input, font_size)) / 2,
+      self.window_resolution.1 / 2,
+      font_size,
+      Color::BLUE,
+    );
+    let help = format!(
+      "Type the {}'s address, {} to connect",
+      target,
+      self.settings.keymap.hint(Control::Confirm)
+    );
     d.draw_text(
-      "Press Enter to Play",
-      (self.window_resolution.0 - d.measure_text("Press Enter to Play", font_size)) / 2,
+      &help,
+      (self.window_resolution.0 - d.measure_text(&help, font_size / 2)) / 2,
       self.window_resolution.1 / 2 + 2 * font_size,
+      font_size / 2,
+      Color::BLACK,
+    );
+  }
+
+  fn draw_controls(&self, rl: &mut RaylibHandle, thread: &RaylibThread, is_capturing: bool) {
+    let font_size = self.window_resolution.1 / 24;
+    let keymap = self.edited_keymap();
+    let mut d = rl.begin_drawing(thread);
+
+    d.clear_background(Color::LIGHTGRAY);
+    d.draw_text(
+      "CONTROLS",
+      (self.window_resolution.0 - d.measure_text("CONTROLS", font_size * 2)) / 2,
+      font_size,
+      font_size * 2,
+      Color::RED,
+    );
+    let preset = format!(
+      "Player {} (P), preset: {} (Tab)",
+      if self.editing_player_two { 2 } else { 1 },
+      self.keymap_preset.name()
+    );
+    d.draw_text(
+      &preset,
+      (self.window_resolution.0 - d.measure_text(&preset, font_size)) / 2,
+      4 * font_size,
+      font_size,
+      Color::BLACK,
+    );
+
+    let first_row = (self.selected_option + 1).saturating_sub(Self::CONTROL_ROWS);
+    let rows = Control::ALL
+      .into_iter()
+      .enumerate()
+      .skip(first_row)
+      .take(Self::CONTROL_ROWS);
+    for (id, control) in rows {
+      let bindings: Vec<String> = keymap
+        .bindings(control)
+        .iter()
+        .map(|binding| binding.name())
+        .collect();
+      let conflict = self.conflict(control, self.editing_player_two);
+
+      let mut text = format!("{}: {}", control.label(), bindings.join(", "));
+      if bindings.is_empty() {
+        text.push_str("UNBOUND");
+      }
+      if let Some(other) = &conflict {
+        text.push_str(&format!(" (conflicts with {})", other));
+      }
+
+      let color = if id == self.selected_option {
+        Color::BLUE
+      } else if conflict.is_some() || bindings.is_empty() {
+        Color::RED
+      } else {
+        Color::BLACK
+      };
+      d.draw_text(
+        &text,
+        self.window_resolution.0 / 8,
+        (id - first_row + 6) as i32 * font_size,
+        font_size,
+        color,
+      );
+    }
+
+    let (help, color) = match self.keymap_conflict() {
+      Some(conflict) if !is_capturing => (format!("{}, fix it to go back", conflict), Color::RED),
+      _ if is_capturing => (
+        "Press the new key, Escape to cancel".to_string(),
+        Color::BLACK,
+      ),
+      _ => (
+        "Enter to add a key, Backspace to clear, Escape to go back".to_string(),
+        Color::BLACK,
+      ),
+    };
+    d.draw_text(
+      &help,
+      (self.window_resolution.0 - d.measure_text(&help, font_size)) / 2,
+      21 * font_size,
+      font_size,
+      color,
+    );
+  }
+
+  fn draw_handling(&self, rl: &mut RaylibHandle, thread: &RaylibThread) {
+    let font_size = self.window_resolution.1 / 12;
+    let handling = if self.editing_player_two {
+      &self.settings.handling_two
+    } else {
+      &self.settings.handling
+    };
+    let mut d = rl.begin_drawing(thread);
+
+    d.clear_background(Color::LIGHTGRAY);
+    d.draw_text(
+      "HANDLING",
+      (self.window_resolution.0 - d.measure_text("HANDLING", font_size * 2)) / 2,
+      font_size / 2,
+      font_size * 2,
+      Color::RED,
+    );
+
+    let sdf = match handling.sdf {
+      SoftDropSpeed::Factor(factor) => format!("SDF: {}x", factor),
+      SoftDropSpeed::Instant => "SDF: Instant".to_string(),
+    };
+    let options = [
+      format!("DAS: {} frames", handling.das),
+      format!("ARR: {} frames", handling.arr),
+      format!("DCD: {} frames", handling.dcd),
+      sdf,
+      format!("Lock delay: {} frames", handling.lock_delay),
+      format!("Max lock resets: {}", handling.max_lock_resets),
+      format!("Online input delay: {} frames", self.settings.input_delay),
+    ];
+
+    for (id, option) in options.iter().enumerate() {
+      let color = if id == self.selected_option {
+        Color::BLUE
+      } else {
+        Color::BLACK
+      };
+      d.draw_text(
+        option,
+        (self.window_resolution.0 - d.measure_text(option, font_size)) / 2,
+        (id as i32 + 3) * font_size,
+        font_size,
+        color,
+      );
+    }
+
+    d.draw_text(
+      "Arrows to edit, Enter or Escape to go back",
+      (self.window_resolution.0
+        - d.measure_text("Arrows to edit, Enter or Escape to go back", font_size))
+        / 2,
+      10 * font_size,
       font_size,
       Color::BLACK,
     );
+    let player = format!(
+      "Player {} (P), player two's is used in versus",
+      if self.editing_player_two { 2 } else { 1 }
+    );
+    d.draw_text(
+      &player,
+      (self.window_resolution.0 - d.measure_text(&player, font_size / 2)) / 2,
+      11 * font_size,
+      font_size / 2,
+      Color::BLACK,
+    );
+  }
+
+  // A file that failed to load is left alone until a setting changes, then kept
+  // next to the new one
+  fn save_settings(&mut self) {
+    let mut backup = None;
+    if self.is_settings_file_broken {
+      if self.settings == Settings::new() {
+        return;
+      }
+      match Settings::back_up() {
+        Ok(path) => backup = Some(path),
+        Err(error) => {
+          self.error = Some(format!("Could not back up the settings file: {}", error));
+          return;
+        }
+      }
+      self.is_settings_file_broken = false;
+    }
+
+    self.error = match (self.settings.save(), backup) {
+      (Err(error), _) => Some(format!("Could not save settings: {}", error)),
+      (Ok(()), Some(path)) => Some(format!("Kept the old settings file as {}", path.display())),
+      (Ok(()), None) => None,
+    };
   }
 
-  fn resize_screen(&mut self, rl: &mut RaylibHandle) {
-    self.resolution = self.resolution.next();
-    self.window_resolution = self.get_window_resolution(&rl);
+  fn apply_resolution(&mut self, rl: &mut RaylibHandle) {
+    self.window_resolution = self.get_window_resolution(rl);
 
     if rl.is_window_fullscreen() {
       rl.toggle_fullscreen();
@@ -114,13 +899,13 @@ impl Menu {
 
     rl.set_window_size(self.window_resolution.0, self.window_resolution.1);
 
-    if self.resolution == Resolution::Fullscreen {
+    if self.settings.resolution == Resolution::Fullscreen {
       rl.toggle_fullscreen();
     }
   }
 
   fn get_window_resolution(&self, rl: &RaylibHandle) -> (i32, i32) {
-    match self.resolution {
+    match self.settings.resolution {
       Resolution::Small => Menu::INITIAL_RESOLUTION,
       Resolution::Medium => (960, 540),
       Resolution::Big => (1280, 720),
@@ -128,3 +913,9 @@ impl Menu {
     }
   }
 }
+
+// The option after `value` in `options`, wrapping around
+fn next_option<T: Copy + PartialEq>(options: &[T], value: T) -> T {
+  let id = options.iter().position(|&option| option == value);
+  options[id.map_or(0, |id| (id + 1) % options.len())]
+}
@@ -0,0 +1,108 @@
+use super::settings::config_directory;
+use std::{fs, io};
+
+// Personal bests, one for every combination of rules a mode can be played with.
+// The file holds a `name = value` line per record.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Records {
+  entries: Vec<(String, u64)>,
+}
+
+impl Records {
+  const FILE_NAME: &'static str = "records.txt";
+
+  pub fn new() -> Self {
+    Self {
+      entries: Vec::new(),
+    }
+  }
+
+  pub fn load() -> io::Result<Self> {
+    let Some(directory) = config_directory() else {
+      return Ok(Self::new());
+    };
+
+    match fs::read_to_string(directory.join(Self::FILE_NAME)) {
+      Ok(text) => Self::parse(&text),
+      Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::new()),
+      Err(error) => Err(error),
+    }
+  }
+
+  pub fn save(&self) -> io::Result<()> {
+    let Some(directory) = config_directory() else {
+      return Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "no configuration directory",
+      ));
+    };
+
+    fs::create_dir_all(&directory)?;
+    fs::write(directory.join(Self::FILE_NAME), self.to_text())
+  }
+
+  pub fn get(&self, name: &str) -> Option<u64> {
+    self
+      .entries
+      .iter()
+      .find(|(entry, _)| entry == name)
+      .map(|&(_, value)| value)
+  }
+
+  pub fn set(&mut self, name: &str, value: u64) {
+    match self.entries.iter_mut().find(|(entry, _)| entry == name) {
+      Some(entry) => entry.1 = value,
+      None => self.entries.push((name.to_string(), value)),
+    }
+  }
+
+  fn parse(text: &str) -> io::Result<Self> {
+    let mut records = Self::new();
+    for (id, line) in text.lines().enumerate() {
+      if line.trim().is_empty() {
+        continue;
+      }
+
+      let record = line
+        .rsplit_once('=')
+        .and_then(|(name, value)| Some((name.trim(), value.trim().parse().ok()?)));
+      match record {
+        Some((name, value)) => records.set(name, value),
+        None => {
+          return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line {} of the records file is not `name = value`", id + 1),
+          ))
+        }
+      }
+    }
+    Ok(records)
+  }
+
+  fn to_text(&self) -> String {
+    self
+      .entries
+      .iter()
+      .map(|(name, value)| format!("{} = {}\n", name, value))
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn records_survive_a_round_trip() {
+    let mut records = Records::new();
+    records.set("sprint 7-bag guideline", 3000);
+    records.set("sprint TGM history 20G", 2500);
+    records.set("sprint 7-bag guideline", 2900);
+
+    let loaded = Records::parse(&records.to_text()).unwrap();
+    assert_eq!(loaded, records);
+    assert_eq!(loaded.get("sprint 7-bag guideline"), Some(2900));
+    assert_eq!(loaded.get("sprint NES NES"), None);
+    assert!(Records::parse("sprint = fast\n").is_err());
+  }
+}
@@ -0,0 +1,595 @@
+use super::{
+  controls::{self, Control, KeyBinding, Keymap, KeymapPreset},
+  game::{HandlingSettings, SoftDropSpeed},
+};
+use std::{env, fmt, fs, io, ops::RangeInclusive, path::PathBuf};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Resolution {
+  Small,
+  Medium,
+  Big,
+  Fullscreen,
+}
+
+impl Resolution {
+  const ALL: [Resolution; 4] = [
+    Resolution::Small,
+    Resolution::Medium,
+    Resolution::Big,
+    Resolution::Fullscreen,
+  ];
+
+  pub fn next(&self) -> Self {
+    match self {
+      Self::Small => Self::Medium,
+      Self::Medium => Self::Big,
+      Self::Big => Self::Fullscreen,
+      Self::Fullscreen => Self::Small,
+    }
+  }
+
+  fn name(&self) -> &'static str {
+    match self {
+      Self::Small => "640x360",
+      Self::Medium => "960x540",
+      Self::Big => "1280x720",
+      Self::Fullscreen => "fullscreen",
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct VisualSettings {
+  pub ghost_piece: bool,
+  pub grid_lines: bool,
+  pub stats_panel: bool,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Settings {
+  pub resolution: Resolution,
+  // Kept for when the game has sound, so files written now already carry it
+  pub volume: u8,
+  pub visual: VisualSettings,
+  pub handling: HandlingSettings,
+  // Player two's handling in versus. Online, each player brings their own.
+  pub handling_two: HandlingSettings,
+  // Frames online inputs are held back, trading responsiveness for fewer rollbacks
+  pub input_delay: u8,
+  pub keymap: Keymap,
+  // Player two's keys in versus when there is no gamepad for them, and their
+  // buttons when there is
+  pub keymap_two: Keymap,
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+  Io(io::Error),
+  Syntax {
+    line: usize,
+    message: &'static str,
+  },
+  UnknownSection {
+    line: usize,
+    section: String,
+  },
+  UnknownKey {
+    line: usize,
+    key: String,
+  },
+  InvalidValue {
+    line: usize,
+    key: String,
+    expected: String,
+  },
+}
+
+impl fmt::Display for SettingsError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Io(error) => write!(f, "{}", error),
+      Self::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+      Self::UnknownSection { line, section } => {
+        write!(f, "line {}: unknown section [{}]", line, section)
+      }
+      Self::UnknownKey { line, key } => write!(f, "line {}: unknown setting {}", line, key),
+      Self::InvalidValue {
+        line,
+        key,
+        expected,
+      } => write!(f, "line {}: {} should be {}", line, key, expected),
+    }
+  }
+}
+
+impl From<io::Error> for SettingsError {
+  fn from(error: io::Error) -> Self {
+    Self::Io(error)
+  }
+}
+
+enum Value {
+  Integer(i64),
+  Boolean(bool),
+  String(String),
+  Array(Vec<String>),
+}
+
+impl Settings {
+  const FILE_NAME: &'static str = "settings.toml";
+  const BACKUP_FILE_NAME: &'static str = "settings.toml.bak";
+  const SECTIONS: [&'static str; 10] = [
+    "video",
+    "audio",
+    "visual",
+    "handling",
+    "player_two_handling",
+    "network",
+    "controls",
+    "player_two_controls",
+    "gamepad",
+    "player_two_gamepad",
+  ];
+  pub const VOLUME_RANGE: RangeInclusive<u8> = 0..=100;
+  pub const INPUT_DELAY_RANGE: RangeInclusive<u8> = 0..=8;
+
+  pub fn new() -> Self {
+    Self {
+      resolution: Resolution::Small,
+      volume: 100,
+      visual: VisualSettings {
+        ghost_piece: true,
+        grid_lines: true,
+        stats_panel: false,
+      },
+      handling: HandlingSettings::new(),
+      handling_two: HandlingSettings::new(),
+      input_delay: 2,
+      keymap: Keymap::new(),
+      keymap_two: KeymapPreset::Numpad.keymap(),
+    }
+  }
+
+  // A missing file just means the defaults are used. Anything else is reported,
+  // so a typo in the file doesn't silently reset every setting.
+  pub fn load() -> Result<Self, SettingsError> {
+    let Some(directory) = config_directory() else {
+      return Ok(Self::new());
+    };
+
+    match fs::read_to_string(directory.join(Self::FILE_NAME)) {
+      Ok(text) => Self::parse(&text),
+      Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::new()),
+      Err(error) => Err(error.into()),
+    }
+  }
+
+  pub fn save(&self) -> Result<(), SettingsError> {
+    let directory = Self::directory()?;
+    fs::create_dir_all(&directory)?;
+    fs::write(directory.join(Self::FILE_NAME), self.to_toml())?;
+    Ok(())
+  }
+
+  // Moves a file that failed to load out of the way, so saving doesn't lose it
+  pub fn back_up() -> Result<PathBuf, SettingsError> {
+    let directory = Self::directory()?;
+    let backup = directory.join(Self::BACKUP_FILE_NAME);
+    fs::rename(directory.join(Self::FILE_NAME), &backup)?;
+    Ok(backup)
+  }
+
+  fn directory() -> Result<PathBuf, SettingsError> {
+    config_directory().ok_or_else(|| {
+      SettingsError::Io(io::Error::new(
+        io::ErrorKind::NotFound,
+        "no configuration directory",
+      ))
+    })
+  }
+
+  fn parse(text: &str) -> Result<Self, SettingsError> {
+    let mut settings = Self::new();
+    let mut section = String::new();
+
+    for (id, line) in text.lines().enumerate() {
+      let line_number = id + 1;
+      let line = strip_comment(line).trim();
+      if line.is_empty() {
+        continue;
+      }
+
+      if let Some(name) = line.strip_prefix('[') {
+        let Some(name) = name.strip_suffix(']') else {
+          return Err(SettingsError::Syntax {
+            line: line_number,
+            message: "missing ] after the section name",
+          });
+        };
+        section = name.trim().to_string();
+        if !Self::SECTIONS.contains(&section.as_str()) {
+          return Err(SettingsError::UnknownSection {
+            line: line_number,
+            section,
+          });
+        }
+        continue;
+      }
+
+      let Some((key, value)) = line.split_once('=') else {
+        return Err(SettingsError::Syntax {
+          line: line_number,
+          message: "expected key = value",
+        });
+      };
+      let Some(value) = parse_value(value.trim()) else {
+        return Err(SettingsError::Syntax {
+          line: line_number,
+          message: "values must be numbers, true, false, \"strings\" or [\"lists\"]",
+        });
+      };
+
+      settings.apply(&section, key.trim(), value, line_number)?;
+    }
+
+    Ok(settings)
+  }
+
+  fn apply(
+    &mut self,
+    section: &str,
+    key: &str,
+    value: Value,
+    line: usize,
+  ) -> Result<(), SettingsError> {
+    let invalid = |expected: String| SettingsError::InvalidValue {
+      line,
+      key: key.to_string(),
+      expected,
+    };
+    let number = |range: RangeInclusive<u8>| {
+      match value {
+        Value::Integer(number) => u8::try_from(number)
+          .ok()
+          .filter(|number| range.contains(number)),
+        _ => None,
+      }
+      .ok_or_else(|| {
+        invalid(format!(
+          "a number from {} to {}",
+          range.start(),
+          range.end()
+        ))
+      })
+    };
+
+    let handling = if section == "player_two_handling" {
+      &mut self.handling_two
+    } else {
+      &mut self.handling
+    };
+    match (section, key) {
+      ("video", "resolution") => {
+        self.resolution = Resolution::ALL
+          .into_iter()
+          .find(|resolution| matches!(&value, Value::String(name) if name == resolution.name()))
+          .ok_or_else(|| {
+            invalid("\"640x360\", \"960x540\", \"1280x720\" or \"fullscreen\"".to_string())
+          })?
+      }
+      ("audio", "volume") => self.volume = number(Self::VOLUME_RANGE)?,
+      ("visual", "ghost_piece" | "grid_lines" | "stats_panel") => {
+        let Value::Boolean(enabled) = value else {
+          return Err(invalid("true or false".to_string()));
+        };
+        match key {
+          "ghost_piece" => self.visual.ghost_piece = enabled,
+          "grid_lines" => self.visual.grid_lines = enabled,
+          _ => self.visual.stats_panel = enabled,
+        }
+      }
+      ("handling" | "player_two_handling", "das") => {
+        handling.das = number(HandlingSettings::DAS_RANGE)?
+      }
+      ("handling" | "player_two_handling", "arr") => {
+        handling.arr = number(HandlingSettings::ARR_RANGE)?
+      }
+      ("handling" | "player_two_handling", "dcd") => {
+        handling.dcd = number(HandlingSettings::DCD_RANGE)?
+      }
+      ("handling" | "player_two_handling", "lock_delay") => {
+        handling.lock_delay = number(HandlingSettings::LOCK_DELAY_RANGE)?
+      }
+      ("handling" | "player_two_handling", "max_lock_resets") => {
+        handling.max_lock_resets = number(HandlingSettings::MAX_LOCK_RESETS_RANGE)?
+      }
+      ("handling" | "player_two_handling", "sdf") => {
+        handling.sdf = match &value {
+          Value::String(name) if name == "instant" => SoftDropSpeed::Instant,
+          _ => SoftDropSpeed::Factor(number(1..=HandlingSettings::MAX_SOFT_DROP_FACTOR).map_err(
+            |_| {
+              invalid(format!(
+                "a number from 1 to {} or \"instant\"",
+                HandlingSettings::MAX_SOFT_DROP_FACTOR
+              ))
+            },
+          )?),
+        }
+      }
+      ("network", "input_delay") => self.input_delay = number(Self::INPUT_DELAY_RANGE)?,
+      ("controls" | "player_two_controls", _) => {
+        let Some(control) = Control::ALL.into_iter().find(|c| c.name() == key) else {
+          return Err(SettingsError::UnknownKey {
+            line,
+            key: key.to_string(),
+          });
+        };
+        let Value::Array(names) = &value else {
+          return Err(invalid(
+            "a list of keys, like [\"LEFT\", \"LEFT_CONTROL+Z\"]".to_string(),
+          ));
+        };
+
+        let mut bindings = vec![];
+        for name in names {
+          let Some(binding) = KeyBinding::from_name(name) else {
+            return Err(invalid(format!(
+              "a list of keys, but {} is not a key",
+              name
+            )));
+          };
+          bindings.push(binding);
+        }
+        let keymap = if section == "controls" {
+          &mut self.keymap
+        } else {
+          &mut self.keymap_two
+        };
+        keymap.set_bindings(control, bindings);
+      }
+      ("gamepad" | "player_two_gamepad", _) => {
+        let Some(control) = Control::ALL.into_iter().find(|c| c.name() == key) else {
+          return Err(SettingsError::UnknownKey {
+            line,
+            key: key.to_string(),
+          });
+        };
+        let Value::Array(names) = &value else {
+          return Err(invalid(
+            "a list of buttons, like [\"LEFT_FACE_LEFT\"]".to_string(),
+          ));
+        };
+
+        let mut buttons = vec![];
+        for name in names {
+          let Some(button) = controls::button_from_name(name) else {
+            return Err(invalid(format!(
+              "a list of buttons, but {} is not a button",
+              name
+            )));
+          };
+          buttons.push(button);
+        }
+        let keymap = if section == "gamepad" {
+          &mut self.keymap
+        } else {
+          &mut self.keymap_two
+        };
+        keymap.set_buttons(control, buttons);
+      }
+      _ => {
+        return Err(SettingsError::UnknownKey {
+          line,
+          key: key.to_string(),
+        })
+      }
+    }
+
+    Ok(())
+  }
+
+  fn to_toml(&self) -> String {
+    let mut text = format!(
+      "[video]\nresolution = \"{}\"\n\n[audio]\nvolume = {}\n\n[visual]\nghost_piece = {}\ngrid_lines = {}\nstats_panel = {}\n\n",
+      self.resolution.name(),
+      self.volume,
+      self.visual.ghost_piece,
+      self.visual.grid_lines,
+      self.visual.stats_panel,
+    );
+
+    for (section, handling) in [
+      ("handling", &self.handling),
+      ("player_two_handling", &self.handling_two),
+    ] {
+      let sdf = match handling.sdf {
+        SoftDropSpeed::Factor(factor) => factor.to_string(),
+        SoftDropSpeed::Instant => "\"instant\"".to_string(),
+      };
+      text.push_str(&format!(
+        "[{}]\ndas = {}\narr = {}\ndcd = {}\nsdf = {}\nlock_delay = {}\nmax_lock_resets = {}\n\n",
+        section,
+        handling.das,
+        handling.arr,
+        handling.dcd,
+        sdf,
+        handling.lock_delay,
+        handling.max_lock_resets,
+      ));
+    }
+    text.push_str(&format!("[network]\ninput_delay = {}\n", self.input_delay));
+
+    for (section, keymap) in [
+      ("controls", &self.keymap),
+      ("player_two_controls", &self.keymap_two),
+    ] {
+      text.push_str(&format!("\n[{}]\n", section));
+      for control in Control::ALL {
+        let bindings: Vec<String> = keymap
+          .bindings(control)
+          .iter()
+          .map(|binding| format!("\"{}\"", binding.name()))
+          .collect();
+        text.push_str(&format!("{} = [{}]\n", control.name(), bindings.join(", ")));
+      }
+    }
+
+    for (section, keymap) in [
+      ("gamepad", &self.keymap),
+      ("player_two_gamepad", &self.keymap_two),
+    ] {
+      text.push_str(&format!("\n[{}]\n", section));
+      for control in Control::ALL {
+        let buttons: Vec<String> = keymap
+          .buttons(control)
+          .iter()
+          .map(|&button| format!("\"{}\"", controls::button_name(button)))
+          .collect();
+        text.push_str(&format!("{} = [{}]\n", control.name(), buttons.join(", ")));
+      }
+    }
+
+    text
+  }
+}
+
+// Where settings, and anything else raytris keeps between runs, are stored
+pub fn config_directory() -> Option<PathBuf> {
+  let config_directory = match env::var_os("XDG_CONFIG_HOME") {
+    Some(directory) if !directory.is_empty() => PathBuf::from(directory),
+    _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+  };
+
+  Some(config_directory.join("raytris"))
+}
+
+fn strip_comment(line: &str) -> &str {
+  let mut is_in_string = false;
+  for (id, character) in line.char_indices() {
+    match character {
+      '"' => is_in_string = !is_in_string,
+      '#' if !is_in_string => return &line[..id],
+      _ => {}
+    }
+  }
+  line
+}
+
+fn parse_string(text: &str) -> Option<String> {
+  let text = text.strip_prefix('"')?.strip_suffix('"')?;
+  if text.contains('"') {
+    return None;
+  }
+  Some(text.to_string())
+}
+
+fn parse_value(text: &str) -> Option<Value> {
+  if let Some(list) = text.strip_prefix('[') {
+    let list = list.strip_suffix(']')?.trim();
+    if list.is_empty() {
+      return Some(Value::Array(vec![]));
+    }
+    return list
+      .split(',')
+      .map(|item| parse_string(item.trim()))
+      .collect::<Option<_>>()
+      .map(Value::Array);
+  }
+
+  match text {
+    "true" => Some(Value::Boolean(true)),
+    "false" => Some(Value::Boolean(false)),
+    _ if text.starts_with('"') => parse_string(text).map(Value::String),
+    _ => text.parse().ok().map(Value::Integer),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use raylib::prelude::{GamepadButton, KeyboardKey};
+
+  #[test]
+  fn saved_settings_load_back() {
+    let mut settings = Settings::new();
+    settings.resolution = Resolution::Fullscreen;
+    settings.volume = 35;
+    settings.visual.grid_lines = false;
+    settings.visual.stats_panel = true;
+    settings.handling.das = 10;
+    settings.handling.sdf = SoftDropSpeed::Instant;
+    settings.handling_two.arr = 3;
+    settings.input_delay = 0;
+    settings.keymap.set_bindings(
+      Control::Hold,
+      vec![KeyBinding::with_modifier(
+        KeyboardKey::KEY_LEFT_ALT,
+        KeyboardKey::KEY_H,
+      )],
+    );
+    settings
+      .keymap_two
+      .set_bindings(Control::HardDrop, vec![KeyBinding::new(KeyboardKey::KEY_I)]);
+    settings.keymap_two.set_buttons(
+      Control::Hold,
+      vec![GamepadButton::GAMEPAD_BUTTON_RIGHT_TRIGGER_1],
+    );
+
+    assert_eq!(Settings::parse(&settings.to_toml()).unwrap(), settings);
+  }
+
+  #[test]
+  fn controls_needed_to_leave_cant_be_unbound() {
+    let mut settings = Settings::parse("[controls]\nquit = []\nhold = []\n").unwrap();
+    settings.keymap.clear_bindings(Control::Pause);
+
+    let defaults = Keymap::new();
+    for control in [Control::Quit, Control::Pause] {
+      assert_eq!(
+        settings.keymap.bindings(control),
+        defaults.bindings(control)
+      );
+    }
+    assert!(settings.keymap.bindings(Control::Hold).is_empty());
+  }
+
+  #[test]
+  fn missing_keys_keep_their_defaults() {
+    let settings = Settings::parse("# only this\n[handling]\narr = 2 # frames\n").unwrap();
+
+    let mut expected = Settings::new();
+    expected.handling.arr = 2;
+    assert_eq!(settings, expected);
+  }
+
+  #[test]
+  fn malformed_files_report_the_line() {
+    let error = |text| Settings::parse(text).unwrap_err().to_string();
+
+    assert_eq!(
+      error("[handling]\n\ndas = 99"),
+      "line 3: das should be a number from 0 to 60"
+    );
+    assert_eq!(
+      error("[handling]\ndas = -1"),
+      "line 2: das should be a number from 0 to 60"
+    );
+    assert_eq!(
+      error("[audio]\nvolume = 101"),
+      "line 2: volume should be a number from 0 to 100"
+    );
+    assert_eq!(error("[sound]"), "line 1: unknown section [sound]");
+    assert_eq!(
+      error("[video]\nvsync = true"),
+      "line 2: unknown setting vsync"
+    );
+    assert!(error("[controls]\nhold = [\"NOT_A_KEY\"]").starts_with("line 2: hold"));
+    assert!(error("[gamepad]\nhold = [\"SPACE\"]").starts_with("line 2: hold"));
+    assert_eq!(
+      error("[visual\n"),
+      "line 1: missing ] after the section name"
+    );
+    assert_eq!(error("das"), "line 1: expected key = value");
+  }
+}